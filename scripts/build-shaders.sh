@@ -1,10 +1,20 @@
 #!/usr/bin/env sh
 glslangValidator shaders/tex_unlit.vert -V -l -o src/mesh/shaders/tex_unlit_vert.spv
+glslangValidator shaders/tex_unlit_skinned.vert -V -l -o src/mesh/shaders/tex_unlit_skinned_vert.spv
+glslangValidator shaders/tex_unlit_instanced.vert -V -l -o src/mesh/shaders/tex_unlit_instanced_vert.spv
 glslangValidator shaders/tex_unlit.frag -V -l -o src/mesh/shaders/tex_unlit_frag.spv
 
 glslangValidator shaders/pbr.vert -V -l -o src/mesh/shaders/pbr_vert.spv
-glslangValidator shaders/untex_pbr.frag -V -l -o src/mesh/shaders/untex_pbr_frag.spv
+glslangValidator shaders/pbr_skinned.vert -V -l -o src/mesh/shaders/pbr_skinned_vert.spv
+glslangValidator shaders/pbr_instanced.vert -V -l -o src/mesh/shaders/pbr_instanced_vert.spv
 glslangValidator shaders/tex_pbr.frag -V -l -o src/mesh/shaders/tex_pbr_frag.spv
 glslangValidator shaders/tex_norm.frag -V -l -o src/mesh/shaders/tex_norm_frag.spv
 glslangValidator shaders/tex_norm_pbr.frag -V -l -o src/mesh/shaders/tex_norm_pbr_frag.spv
 glslangValidator shaders/tex_emiss_pbr.frag -V -l -o src/mesh/shaders/tex_emiss_pbr_frag.spv
+glslangValidator shaders/tex_layered_pbr.frag -V -l -o src/mesh/shaders/tex_layered_pbr_frag.spv
+glslangValidator shaders/tex_transmissive_pbr.frag -V -l -o src/mesh/shaders/tex_transmissive_pbr_frag.spv
+
+glslangValidator shaders/blit.vert -V -l -o src/mesh/shaders/blit_vert.spv
+glslangValidator shaders/blit.frag -V -l -o src/mesh/shaders/blit_frag.spv
+
+glslangValidator shaders/morph.comp -V -l -o src/mesh/shaders/morph_comp.spv
@@ -0,0 +1,14 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 f_uv;
+
+// A single mip level, which a bilinear sample at half the size box filters
+layout(set = 0, binding = 0) uniform texture2D source;
+layout(set = 0, binding = 1) uniform sampler source_sampler;
+
+layout(location = 0) out vec4 color;
+
+void main() {
+    color = textureLod(sampler2D(source, source_sampler), f_uv, 0.0);
+}
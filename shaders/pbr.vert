@@ -5,6 +5,9 @@ layout(location = 0) in vec3 a_pos;
 layout(location = 1) in vec3 a_norm;
 layout(location = 2) in vec4 a_tang;
 layout(location = 3) in vec2 a_uv;
+// Per-vertex, or a single default of white and (0, 0) for parts without them
+layout(location = 6) in vec4 a_color;
+layout(location = 7) in vec2 a_uv1;
 
 layout(set = 0, binding = 0) uniform Globals {
     layout(offset = 0) mat4 view_proj;
@@ -13,6 +16,13 @@ layout(set = 0, binding = 0) uniform Globals {
 layout(set = 1, binding = 0) uniform Mesh {
     mat4 transform;
 };
+// Restores the positions and UVs of parts stored in the compact vertex format. Identity for
+// full precision parts.
+layout(set = 1, binding = 2) uniform Part {
+    vec4 position_scale;
+    vec4 position_offset;
+    vec4 uv_scale_offset;
+};
 
 layout(location = 0) out vec4 frag_world_pos;
 layout(location = 1) out vec2 frag_uv;
@@ -20,10 +30,16 @@ layout(location = 1) out vec2 frag_uv;
 layout(location = 2) out vec3 tbn_t;
 layout(location = 3) out vec3 tbn_b;
 layout(location = 4) out vec3 tbn_n;
+layout(location = 5) out vec4 frag_color;
+layout(location = 6) out vec2 frag_uv1;
 
 void main() {
-    frag_uv = a_uv;
-    frag_world_pos = transform * vec4(a_pos, 1.0);
+    vec3 pos = a_pos * position_scale.xyz + position_offset.xyz;
+    vec2 uv = a_uv * uv_scale_offset.xy + uv_scale_offset.zw;
+    frag_uv = uv;
+    frag_uv1 = a_uv1;
+    frag_color = a_color;
+    frag_world_pos = transform * vec4(pos, 1.0);
 
     vec3 frag_norm = normalize(mat3(transform) * a_norm);
     vec3 frag_tang = normalize(mat3(transform) * a_tang.xyz);
@@ -0,0 +1,61 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec3 a_pos;
+layout(location = 1) in vec3 a_norm;
+layout(location = 2) in vec4 a_tang;
+layout(location = 3) in vec2 a_uv;
+// Per-vertex, or a default of white and (0, 0) per instance for parts without them
+layout(location = 6) in vec4 a_color;
+layout(location = 7) in vec2 a_uv1;
+// Per-instance transform columns, relative to the mesh, and color
+layout(location = 8) in vec4 i_transform_0;
+layout(location = 9) in vec4 i_transform_1;
+layout(location = 10) in vec4 i_transform_2;
+layout(location = 11) in vec4 i_transform_3;
+layout(location = 12) in vec4 i_color;
+
+layout(set = 0, binding = 0) uniform Globals {
+    layout(offset = 0) mat4 view_proj;
+    layout(offset = 64) vec3 camera_pos;
+};
+layout(set = 1, binding = 0) uniform Mesh {
+    mat4 transform;
+};
+// Restores the positions and UVs of parts stored in the compact vertex format. Identity for
+// full precision parts.
+layout(set = 1, binding = 2) uniform Part {
+    vec4 position_scale;
+    vec4 position_offset;
+    vec4 uv_scale_offset;
+};
+
+layout(location = 0) out vec4 frag_world_pos;
+layout(location = 1) out vec2 frag_uv;
+// XXX mat3 isn't interpolated so we pass in rows individually
+layout(location = 2) out vec3 tbn_t;
+layout(location = 3) out vec3 tbn_b;
+layout(location = 4) out vec3 tbn_n;
+layout(location = 5) out vec4 frag_color;
+layout(location = 6) out vec2 frag_uv1;
+
+void main() {
+    vec3 pos = a_pos * position_scale.xyz + position_offset.xyz;
+    vec2 uv = a_uv * uv_scale_offset.xy + uv_scale_offset.zw;
+    mat4 instance_transform =
+        transform * mat4(i_transform_0, i_transform_1, i_transform_2, i_transform_3);
+
+    frag_uv = uv;
+    frag_uv1 = a_uv1;
+    frag_color = a_color * i_color;
+    frag_world_pos = instance_transform * vec4(pos, 1.0);
+
+    vec3 frag_norm = normalize(mat3(instance_transform) * a_norm);
+    vec3 frag_tang = normalize(mat3(instance_transform) * a_tang.xyz);
+    vec3 vertex_bitangent = cross(frag_norm, frag_tang) * a_tang.w;
+    tbn_t = frag_tang;
+    tbn_b = vertex_bitangent;
+    tbn_n = frag_norm;
+
+    gl_Position = view_proj * frag_world_pos;
+}
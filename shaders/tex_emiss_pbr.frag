@@ -9,6 +9,8 @@ layout(location = 1) in vec2 f_uv;
 layout(location = 2) in vec3 f_tbn_t;
 layout(location = 3) in vec3 f_tbn_b;
 layout(location = 4) in vec3 f_tbn_n;
+layout(location = 5) in vec4 f_color;
+layout(location = 6) in vec2 f_uv1;
 
 struct Light {
     vec3 position;
@@ -41,6 +43,9 @@ layout(set = 2, binding = 0) uniform MeshPart {
     layout(offset = 32) float rough_factor;
     layout(offset = 48) vec3 emissive_factor;
     layout(offset = 64) vec3 extra_emissive;
+    layout(offset = 80) vec4 uv_transforms[10];
+    layout(offset = 240) ivec4 texture_channels[2];
+    layout(offset = 272) ivec4 texture_uv_sets[2];
 };
 layout(set = 2, binding = 1) uniform sampler tex_sampler;
 layout(set = 2, binding = 2) uniform texture2D albedo_map;
@@ -48,18 +53,51 @@ layout(set = 2, binding = 3) uniform texture2D normal_map;
 layout(set = 2, binding = 4) uniform texture2D metallic_roughness_map;
 layout(set = 2, binding = 5) uniform texture2D ao_map;
 layout(set = 2, binding = 6) uniform texture2D emissive_map;
+layout(set = 2, binding = 7) uniform sampler normal_sampler;
+layout(set = 2, binding = 8) uniform sampler metallic_roughness_sampler;
+layout(set = 2, binding = 9) uniform sampler ao_sampler;
+layout(set = 2, binding = 10) uniform sampler emissive_sampler;
 
 layout(location = 0) out vec4 color;
 layout(location = 1) out vec4 bright_color;
 
 const float PI = 3.14159265359;
 
-float tex_coord(float coord, vec2 offset) {
-    return offset.x + coord * (offset.y - offset.x);
+const int BASE_COLOR_SLOT = 0;
+const int NORMAL_SLOT = 1;
+const int METALLIC_ROUGHNESS_SLOT = 2;
+const int AO_SLOT = 3;
+const int EMISSIVE_SLOT = 4;
+
+// Apply a texture slot's KHR_texture_transform, stored as the two rows of a 2x3 matrix
+vec2 transform_uv(int slot, vec2 uv) {
+    vec3 uv1 = vec3(uv, 1.0);
+    return vec2(dot(uv_transforms[slot * 2].xyz, uv1), dot(uv_transforms[slot * 2 + 1].xyz, uv1));
+}
+
+// The UV set a texture slot samples with
+vec2 slot_uv(int slot) {
+    return texture_uv_sets[slot / 4][slot % 4] == 1 ? f_uv1 : f_uv;
 }
 
-vec2 tex_coords(vec2 coord, vec2 u, vec2 v) {
-    return vec2(tex_coord(coord.x, u), tex_coord(coord.y, v));
+// Single and dual channel textures are stored as luminance and luminance-alpha
+vec4 expand_channels(int slot, vec4 texel) {
+    int channels = texture_channels[slot / 4][slot % 4];
+    if (channels == 1) {
+        return vec4(texel.rrr, 1.0);
+    } else if (channels == 2) {
+        return vec4(texel.rrr, texel.g);
+    }
+    return texel;
+}
+
+// Two channel metallic roughness maps hold (roughness, metallic). Otherwise metallic is in blue
+// and roughness in green.
+vec2 metallic_roughness_channels(vec4 texel) {
+    if (texture_channels[METALLIC_ROUGHNESS_SLOT / 4][METALLIC_ROUGHNESS_SLOT % 4] == 2) {
+        return texel.gr;
+    }
+    return expand_channels(METALLIC_ROUGHNESS_SLOT, texel).bg;
 }
 
 float normal_distribution(vec3 N, vec3 H, float a) {
@@ -119,24 +157,24 @@ vec3 compute_light(vec3 attenuation,
 }
 
 void main() {
-    vec4 albedo_rgba = texture(sampler2D(albedo_map, tex_sampler), f_uv) * in_diffuse;
+    vec4 albedo_rgba = expand_channels(BASE_COLOR_SLOT, texture(sampler2D(albedo_map, tex_sampler), transform_uv(BASE_COLOR_SLOT, slot_uv(BASE_COLOR_SLOT)))) * in_diffuse * f_color;
     vec3 albedo = albedo_rgba.rgb;
 
     mat3 f_tbn = mat3(f_tbn_t, f_tbn_b, f_tbn_n);
 
-    vec3 normal = texture(sampler2D(normal_map, tex_sampler), f_uv).rgb;
+    vec3 normal = expand_channels(NORMAL_SLOT, texture(sampler2D(normal_map, normal_sampler), transform_uv(NORMAL_SLOT, slot_uv(NORMAL_SLOT)))).rgb;
     normal = normalize(normal * 2 - 1); // Convert [0, 1] to [-1, 1]
     normal = normalize(f_tbn * normal);
 
-    vec2 metallic_roughness = texture(sampler2D(metallic_roughness_map, tex_sampler), f_uv).bg;
+    vec2 metallic_roughness = metallic_roughness_channels(texture(sampler2D(metallic_roughness_map, metallic_roughness_sampler), transform_uv(METALLIC_ROUGHNESS_SLOT, slot_uv(METALLIC_ROUGHNESS_SLOT))));
     float metallic = metallic_roughness.r * metal_factor;
     float roughness = metallic_roughness.g * rough_factor;
     float roughness2 = roughness * roughness;
     vec3 fresnel_base = mix(vec3(0.04), albedo, metallic);
 
-    float ambient_occlusion = 1.0; //texture(sampler2D(ao_map, tex_sampler), f_uv).r;
+    float ambient_occlusion = 1.0; //expand_channels(AO_SLOT, texture(sampler2D(ao_map, ao_sampler), transform_uv(AO_SLOT, slot_uv(AO_SLOT)))).r;
 
-    vec3 emission = texture(sampler2D(emissive_map, tex_sampler), f_uv).rgb + extra_emissive;
+    vec3 emission = expand_channels(EMISSIVE_SLOT, texture(sampler2D(emissive_map, emissive_sampler), transform_uv(EMISSIVE_SLOT, slot_uv(EMISSIVE_SLOT)))).rgb + extra_emissive;
 
     if (albedo_rgba.a == 0.0) discard;
 
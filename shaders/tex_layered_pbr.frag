@@ -0,0 +1,386 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(early_fragment_tests) in;
+
+layout(location = 0) in vec4 f_world_pos;
+layout(location = 1) in vec2 f_uv;
+// XXX mat3 isn't interpolated so we pass in rows individually
+layout(location = 2) in vec3 f_tbn_t;
+layout(location = 3) in vec3 f_tbn_b;
+layout(location = 4) in vec3 f_tbn_n;
+layout(location = 5) in vec4 f_color;
+layout(location = 6) in vec2 f_uv1;
+
+struct Light {
+    vec3 position;
+    float intensity;
+    vec3 color;
+};
+
+struct SpotLight {
+    vec3 position;
+    float angle;
+    vec3 color;
+    float range;
+    vec3 direction;
+    float smoothness;
+    float intensity;
+};
+
+layout(std140, set = 0, binding = 0) uniform Args {
+    layout(offset = 0) mat4 proj_view;
+    layout(offset = 64) vec3 camera_pos;
+    layout(offset = 76) int point_light_count;
+    layout(offset = 80) Light point_lights[32];
+    layout(offset = 1104) int spot_light_count;
+    layout(offset = 1120) SpotLight spot_lights[32];
+};
+
+layout(set = 2, binding = 0) uniform MeshPart {
+    layout(offset = 0) vec4 in_diffuse;
+    layout(offset = 16) float metal_factor;
+    layout(offset = 32) float rough_factor;
+    layout(offset = 48) vec3 emissive_factor;
+    layout(offset = 64) vec3 extra_emissive;
+    layout(offset = 80) vec4 uv_transforms[10];
+    layout(offset = 240) ivec4 texture_channels[2];
+    layout(offset = 272) ivec4 texture_uv_sets[2];
+    layout(offset = 304) float clearcoat_factor;
+    layout(offset = 308) float clearcoat_rough_factor;
+    layout(offset = 312) float specular_factor;
+    layout(offset = 316) float ior;
+    layout(offset = 320) vec3 sheen_color_factor;
+    layout(offset = 332) float sheen_rough_factor;
+    layout(offset = 336) vec3 specular_color_factor;
+    layout(offset = 384) vec4 layer_uv_transforms[18];
+    layout(offset = 672) ivec4 layer_texture_channels[3];
+    layout(offset = 720) ivec4 layer_texture_uv_sets[3];
+};
+layout(set = 2, binding = 1) uniform sampler tex_sampler;
+layout(set = 2, binding = 2) uniform texture2D albedo_map;
+layout(set = 2, binding = 3) uniform texture2D normal_map;
+layout(set = 2, binding = 4) uniform texture2D metallic_roughness_map;
+layout(set = 2, binding = 5) uniform texture2D ao_map;
+layout(set = 2, binding = 6) uniform texture2D emissive_map;
+layout(set = 2, binding = 7) uniform sampler normal_sampler;
+layout(set = 2, binding = 8) uniform sampler metallic_roughness_sampler;
+layout(set = 2, binding = 9) uniform sampler ao_sampler;
+layout(set = 2, binding = 10) uniform sampler emissive_sampler;
+layout(set = 2, binding = 11) uniform texture2D clearcoat_map;
+layout(set = 2, binding = 12) uniform sampler clearcoat_sampler;
+layout(set = 2, binding = 13) uniform texture2D clearcoat_roughness_map;
+layout(set = 2, binding = 14) uniform sampler clearcoat_roughness_sampler;
+layout(set = 2, binding = 15) uniform texture2D clearcoat_normal_map;
+layout(set = 2, binding = 16) uniform sampler clearcoat_normal_sampler;
+layout(set = 2, binding = 17) uniform texture2D sheen_color_map;
+layout(set = 2, binding = 18) uniform sampler sheen_color_sampler;
+layout(set = 2, binding = 19) uniform texture2D sheen_roughness_map;
+layout(set = 2, binding = 20) uniform sampler sheen_roughness_sampler;
+layout(set = 2, binding = 21) uniform texture2D specular_map;
+layout(set = 2, binding = 22) uniform sampler specular_sampler;
+layout(set = 2, binding = 23) uniform texture2D specular_color_map;
+layout(set = 2, binding = 24) uniform sampler specular_color_sampler;
+
+layout(location = 0) out vec4 color;
+layout(location = 1) out vec4 bright_color;
+
+const float PI = 3.14159265359;
+
+const int BASE_COLOR_SLOT = 0;
+const int NORMAL_SLOT = 1;
+const int METALLIC_ROUGHNESS_SLOT = 2;
+const int AO_SLOT = 3;
+const int EMISSIVE_SLOT = 4;
+const int CLEARCOAT_SLOT = 5;
+const int CLEARCOAT_ROUGHNESS_SLOT = 6;
+const int CLEARCOAT_NORMAL_SLOT = 7;
+const int SHEEN_COLOR_SLOT = 8;
+const int SHEEN_ROUGHNESS_SLOT = 9;
+const int SPECULAR_SLOT = 10;
+const int SPECULAR_COLOR_SLOT = 11;
+
+// The slots from the clearcoat slot on are stored in the layer arrays
+const int FIRST_LAYER_SLOT = CLEARCOAT_SLOT;
+
+struct Layers {
+    vec3 clearcoat_normal;
+    float clearcoat;
+    float clearcoat_roughness2;
+    vec3 sheen_color;
+    float sheen_roughness2;
+    // Scales down the base to make room for the energy the sheen reflects
+    float sheen_scaling;
+};
+
+// Apply a texture slot's KHR_texture_transform, stored as the two rows of a 2x3 matrix
+vec2 transform_uv(int slot, vec2 uv) {
+    vec3 uv1 = vec3(uv, 1.0);
+    vec4 row0;
+    vec4 row1;
+    if (slot < FIRST_LAYER_SLOT) {
+        row0 = uv_transforms[slot * 2];
+        row1 = uv_transforms[slot * 2 + 1];
+    } else {
+        row0 = layer_uv_transforms[(slot - FIRST_LAYER_SLOT) * 2];
+        row1 = layer_uv_transforms[(slot - FIRST_LAYER_SLOT) * 2 + 1];
+    }
+    return vec2(dot(row0.xyz, uv1), dot(row1.xyz, uv1));
+}
+
+// The UV set a texture slot samples with
+vec2 slot_uv(int slot) {
+    int uv_set;
+    if (slot < FIRST_LAYER_SLOT) {
+        uv_set = texture_uv_sets[slot / 4][slot % 4];
+    } else {
+        int i = slot - FIRST_LAYER_SLOT;
+        uv_set = layer_texture_uv_sets[i / 4][i % 4];
+    }
+    return uv_set == 1 ? f_uv1 : f_uv;
+}
+
+// Single and dual channel textures are stored as luminance and luminance-alpha
+vec4 expand_channels(int slot, vec4 texel) {
+    int channels;
+    if (slot < FIRST_LAYER_SLOT) {
+        channels = texture_channels[slot / 4][slot % 4];
+    } else {
+        int i = slot - FIRST_LAYER_SLOT;
+        channels = layer_texture_channels[i / 4][i % 4];
+    }
+    if (channels == 1) {
+        return vec4(texel.rrr, 1.0);
+    } else if (channels == 2) {
+        return vec4(texel.rrr, texel.g);
+    }
+    return texel;
+}
+
+// Two channel metallic roughness maps hold (roughness, metallic). Otherwise metallic is in blue
+// and roughness in green.
+vec2 metallic_roughness_channels(vec4 texel) {
+    if (texture_channels[METALLIC_ROUGHNESS_SLOT / 4][METALLIC_ROUGHNESS_SLOT % 4] == 2) {
+        return texel.gr;
+    }
+    return expand_channels(METALLIC_ROUGHNESS_SLOT, texel).bg;
+}
+
+float normal_distribution(vec3 N, vec3 H, float a) {
+    float a2 = a * a;
+    float NdotH = max(dot(N, H), 0.0);
+    float NdotH2 = NdotH*NdotH;
+
+    float denom = (NdotH2 * (a2 - 1.0) + 1.0);
+    denom = PI * denom * denom;
+
+    return (a2 + 0.0000001) / denom;
+}
+
+float geometry(float NdotV, float NdotL, float r2) {
+    float a1 = r2 + 1.0;
+    float k = a1 * a1 / 8.0;
+    float denom = NdotV * (1.0 - k) + k;
+    float ggx1 = NdotV / denom;
+    denom = NdotL * (1.0 - k) + k;
+    float ggx2 = NdotL / denom;
+    return ggx1 * ggx2;
+}
+
+vec3 fresnel(float HdotV, vec3 fresnel_base, float fresnel_edge) {
+    return fresnel_base + (fresnel_edge - fresnel_base) * pow(1.0 - HdotV, 5.0);
+}
+
+// The "Charlie" sheen distribution of Estevez and Kulla
+float sheen_distribution(vec3 N, vec3 H, float a) {
+    float inv_a = 1.0 / max(a, 0.0001);
+    float NdotH = max(dot(N, H), 0.0);
+    float sin2 = 1.0 - NdotH * NdotH;
+    return (2.0 + inv_a) * pow(sin2, inv_a * 0.5) / (2.0 * PI);
+}
+
+// Neubelt and Pettineo's approximation of the sheen visibility term
+float sheen_visibility(float NdotV, float NdotL) {
+    return 1.0 / (4.0 * (NdotL + NdotV - NdotL * NdotV) + 0.0001);
+}
+
+vec3 compute_light(vec3 attenuation,
+                   vec3 light_color,
+                   vec3 view_direction,
+                   vec3 light_direction,
+                   vec3 albedo,
+                   vec3 normal,
+                   float roughness2,
+                   float metallic,
+                   vec3 fresnel_base,
+                   float fresnel_edge,
+                   Layers layers) {
+
+    vec3 halfway = normalize(view_direction + light_direction);
+    float normal_distribution = normal_distribution(normal, halfway, roughness2);
+
+    float NdotV = max(dot(normal, view_direction), 0.0);
+    float NdotL = max(dot(normal, light_direction), 0.0);
+    float HdotV = max(dot(halfway, view_direction), 0.0);
+    float geometry = geometry(NdotV, NdotL, roughness2);
+
+
+    vec3 fresnel = fresnel(HdotV, fresnel_base, fresnel_edge);
+    vec3 diffuse = vec3(1.0) - fresnel;
+    diffuse *= 1.0 - metallic;
+
+    vec3 nominator = normal_distribution * geometry * fresnel;
+    float denominator = 4 * NdotV * NdotL + 0.0001;
+    vec3 specular = nominator / denominator;
+
+    vec3 base = (diffuse * albedo / PI + specular) * NdotL;
+
+    // Sheen sits on top of the base
+    vec3 sheen = layers.sheen_color
+        * sheen_distribution(normal, halfway, layers.sheen_roughness2)
+        * sheen_visibility(NdotV, NdotL)
+        * NdotL;
+    base = base * layers.sheen_scaling + sheen;
+
+    // The clearcoat is a dielectric layer with its own normal on top of everything else
+    vec3 coat_normal = layers.clearcoat_normal;
+    float coat_NdotV = max(dot(coat_normal, view_direction), 0.0);
+    float coat_NdotL = max(dot(coat_normal, light_direction), 0.0);
+    float coat_fresnel = fresnel(HdotV, vec3(0.04), 1.0).r * layers.clearcoat;
+    float coat_specular = normal_distribution(coat_normal, halfway, layers.clearcoat_roughness2)
+        * geometry(coat_NdotV, coat_NdotL, layers.clearcoat_roughness2)
+        * coat_fresnel
+        / (4 * coat_NdotV * coat_NdotL + 0.0001);
+
+    vec3 resulting_light = base * (1.0 - coat_fresnel) + coat_specular * coat_NdotL;
+    return resulting_light * light_color * attenuation;
+}
+
+void main() {
+    vec4 albedo_rgba = expand_channels(BASE_COLOR_SLOT, texture(sampler2D(albedo_map, tex_sampler), transform_uv(BASE_COLOR_SLOT, slot_uv(BASE_COLOR_SLOT)))) * in_diffuse * f_color;
+    vec3 albedo = albedo_rgba.rgb;
+
+    mat3 f_tbn = mat3(f_tbn_t, f_tbn_b, f_tbn_n);
+
+    vec3 normal = expand_channels(NORMAL_SLOT, texture(sampler2D(normal_map, normal_sampler), transform_uv(NORMAL_SLOT, slot_uv(NORMAL_SLOT)))).rgb;
+    normal = normalize(normal * 2 - 1); // Convert [0, 1] to [-1, 1]
+    normal = normalize(f_tbn * normal);
+
+    vec2 metallic_roughness = metallic_roughness_channels(texture(sampler2D(metallic_roughness_map, metallic_roughness_sampler), transform_uv(METALLIC_ROUGHNESS_SLOT, slot_uv(METALLIC_ROUGHNESS_SLOT))));
+    float metallic = metallic_roughness.r * metal_factor;
+    float roughness = metallic_roughness.g * rough_factor;
+    float roughness2 = roughness * roughness;
+
+    // KHR_materials_specular and KHR_materials_ior only change the reflectance of dielectrics
+    float specular = expand_channels(SPECULAR_SLOT, texture(sampler2D(specular_map, specular_sampler), transform_uv(SPECULAR_SLOT, slot_uv(SPECULAR_SLOT)))).a * specular_factor;
+    vec3 specular_color = expand_channels(SPECULAR_COLOR_SLOT, texture(sampler2D(specular_color_map, specular_color_sampler), transform_uv(SPECULAR_COLOR_SLOT, slot_uv(SPECULAR_COLOR_SLOT)))).rgb * specular_color_factor;
+    float ior_reflectance = pow((ior - 1.0) / (ior + 1.0), 2.0);
+    vec3 dielectric_base = min(vec3(ior_reflectance) * specular_color, vec3(1.0)) * specular;
+    vec3 fresnel_base = mix(dielectric_base, albedo, metallic);
+    float fresnel_edge = mix(specular, 1.0, metallic);
+
+    Layers layers;
+
+    vec3 clearcoat_normal = expand_channels(CLEARCOAT_NORMAL_SLOT, texture(sampler2D(clearcoat_normal_map, clearcoat_normal_sampler), transform_uv(CLEARCOAT_NORMAL_SLOT, slot_uv(CLEARCOAT_NORMAL_SLOT)))).rgb;
+    layers.clearcoat_normal = normalize(f_tbn * normalize(clearcoat_normal * 2 - 1));
+    layers.clearcoat = expand_channels(CLEARCOAT_SLOT, texture(sampler2D(clearcoat_map, clearcoat_sampler), transform_uv(CLEARCOAT_SLOT, slot_uv(CLEARCOAT_SLOT)))).r * clearcoat_factor;
+    float clearcoat_roughness = expand_channels(CLEARCOAT_ROUGHNESS_SLOT, texture(sampler2D(clearcoat_roughness_map, clearcoat_roughness_sampler), transform_uv(CLEARCOAT_ROUGHNESS_SLOT, slot_uv(CLEARCOAT_ROUGHNESS_SLOT)))).g * clearcoat_rough_factor;
+    layers.clearcoat_roughness2 = clearcoat_roughness * clearcoat_roughness;
+
+    layers.sheen_color = expand_channels(SHEEN_COLOR_SLOT, texture(sampler2D(sheen_color_map, sheen_color_sampler), transform_uv(SHEEN_COLOR_SLOT, slot_uv(SHEEN_COLOR_SLOT)))).rgb * sheen_color_factor;
+    float sheen_roughness = expand_channels(SHEEN_ROUGHNESS_SLOT, texture(sampler2D(sheen_roughness_map, sheen_roughness_sampler), transform_uv(SHEEN_ROUGHNESS_SLOT, slot_uv(SHEEN_ROUGHNESS_SLOT)))).a * sheen_rough_factor;
+    layers.sheen_roughness2 = sheen_roughness * sheen_roughness;
+    // Approximates the directional albedo of the sheen lobe with its average
+    layers.sheen_scaling = 1.0 - max(layers.sheen_color.r, max(layers.sheen_color.g, layers.sheen_color.b)) * 0.157;
+
+    float ambient_occlusion = 1.0; //expand_channels(AO_SLOT, texture(sampler2D(ao_map, ao_sampler), transform_uv(AO_SLOT, slot_uv(AO_SLOT)))).r;
+
+    vec3 emission = expand_channels(EMISSIVE_SLOT, texture(sampler2D(emissive_map, emissive_sampler), transform_uv(EMISSIVE_SLOT, slot_uv(EMISSIVE_SLOT)))).rgb + extra_emissive;
+
+    if (albedo_rgba.a == 0.0) discard;
+
+    vec3 view_dist = camera_pos - f_world_pos.xyz;
+    vec3 view_direction = normalize(view_dist);
+
+    vec3 lighted = vec3(0.0);
+    for (int i = 0; i < point_light_count; i++) {
+        vec3 light_direction = point_lights[i].position - f_world_pos.xyz;
+        float attenuation = point_lights[i].intensity / dot(light_direction, light_direction);
+
+        vec3 light = compute_light(vec3(attenuation),
+                                   point_lights[i].color,
+                                   view_direction,
+                                   normalize(light_direction),
+                                   albedo,
+                                   normal,
+                                   roughness2,
+                                   metallic,
+                                   fresnel_base,
+                                   fresnel_edge,
+                                   layers);
+
+        lighted += light;
+    }
+
+    for (int i = 0; i < spot_light_count; i++) {
+        vec3 light_vec = spot_lights[i].position - f_world_pos.xyz;
+        vec3 normalized_light_vec = normalize(light_vec);
+
+        // The distance between the current fragment and the "core" of the light
+        float light_length = length(light_vec);
+
+        // The allowed "length", everything after this won't be lit.
+        // Later on we are dividing by this range, so it can't be 0
+        float range = max(spot_lights[i].range, 0.00001);
+
+        // get normalized range, so everything 0..1 could be lit, everything else can't.
+        float normalized_range = light_length / max(0.00001, range);
+
+        // The attenuation for the "range". If we would only consider this, we'd have a
+        // point light instead, so we need to also check for the spot angle and direction.
+        float range_attenuation = max(0.0, 1.0 - normalized_range);
+
+        // this is actually the cosine of the angle, so it can be compared with the
+        // "dotted" frag_angle below a lot cheaper.
+        float spot_angle = max(spot_lights[i].angle, 0.00001);
+        vec3 spot_direction = normalize(spot_lights[i].direction);
+        float smoothness = 1.0 - spot_lights[i].smoothness;
+
+        // Here we check if the current fragment is within the "ring" of the spotlight.
+        float frag_angle = dot(spot_direction, -normalized_light_vec);
+
+        // so that the ring_attenuation won't be > 1
+        frag_angle = max(frag_angle, spot_angle);
+
+        // How much is this outside of the ring? (let's call it "rim")
+        // Also smooth this out.
+        float rim_attenuation = pow(max((1.0 - frag_angle) / (1.0 - spot_angle), 0.00001), smoothness);
+
+        // How much is this inside the "ring"?
+        float ring_attenuation = 1.0 - rim_attenuation;
+
+        // combine the attenuations and intensity
+        float attenuation = range_attenuation * ring_attenuation * spot_lights[i].intensity;
+
+        vec3 light = compute_light(vec3(attenuation),
+                                   spot_lights[i].color,
+                                   view_direction,
+                                   normalized_light_vec,
+                                   albedo,
+                                   normal,
+                                   roughness2,
+                                   metallic,
+                                   fresnel_base,
+                                   fresnel_edge,
+                                   layers);
+        lighted += light;
+    }
+
+    // The clearcoat also dims what's emitted underneath it
+    float coat_NdotV = max(dot(layers.clearcoat_normal, view_direction), 0.0);
+    float coat_fresnel = fresnel(coat_NdotV, vec3(0.04), 1.0).r * layers.clearcoat;
+
+    vec3 ambient_color = vec3(0.01, 0.01, 0.01);
+    vec3 ambient = ambient_color * albedo * ambient_occlusion;
+    color = vec4(ambient + lighted + (emission + extra_emissive) * (1.0 - coat_fresnel), albedo_rgba.a);
+}
@@ -9,6 +9,8 @@ layout(location = 1) in vec2 f_uv;
 layout(location = 2) in vec3 f_tbn_t;
 layout(location = 3) in vec3 f_tbn_b;
 layout(location = 4) in vec3 f_tbn_n;
+layout(location = 5) in vec4 f_color;
+layout(location = 6) in vec2 f_uv1;
 
 struct Light {
     vec3 position;
@@ -41,6 +43,9 @@ layout(set = 2, binding = 0) uniform MeshPart {
     layout(offset = 32) float rough_factor;
     layout(offset = 48) vec3 emissive_factor;
     layout(offset = 64) vec3 extra_emissive;
+    layout(offset = 80) vec4 uv_transforms[10];
+    layout(offset = 240) ivec4 texture_channels[2];
+    layout(offset = 272) ivec4 texture_uv_sets[2];
 };
 layout(set = 2, binding = 1) uniform sampler tex_sampler;
 layout(set = 2, binding = 2) uniform texture2D albedo_map;
@@ -50,12 +55,32 @@ layout(location = 1) out vec4 bright_color;
 
 const float PI = 3.14159265359;
 
-float tex_coord(float coord, vec2 offset) {
-    return offset.x + coord * (offset.y - offset.x);
+const int BASE_COLOR_SLOT = 0;
+const int NORMAL_SLOT = 1;
+const int METALLIC_ROUGHNESS_SLOT = 2;
+const int AO_SLOT = 3;
+const int EMISSIVE_SLOT = 4;
+
+// Apply a texture slot's KHR_texture_transform, stored as the two rows of a 2x3 matrix
+vec2 transform_uv(int slot, vec2 uv) {
+    vec3 uv1 = vec3(uv, 1.0);
+    return vec2(dot(uv_transforms[slot * 2].xyz, uv1), dot(uv_transforms[slot * 2 + 1].xyz, uv1));
+}
+
+// The UV set a texture slot samples with
+vec2 slot_uv(int slot) {
+    return texture_uv_sets[slot / 4][slot % 4] == 1 ? f_uv1 : f_uv;
 }
 
-vec2 tex_coords(vec2 coord, vec2 u, vec2 v) {
-    return vec2(tex_coord(coord.x, u), tex_coord(coord.y, v));
+// Single and dual channel textures are stored as luminance and luminance-alpha
+vec4 expand_channels(int slot, vec4 texel) {
+    int channels = texture_channels[slot / 4][slot % 4];
+    if (channels == 1) {
+        return vec4(texel.rrr, 1.0);
+    } else if (channels == 2) {
+        return vec4(texel.rrr, texel.g);
+    }
+    return texel;
 }
 
 float normal_distribution(vec3 N, vec3 H, float a) {
@@ -115,7 +140,7 @@ vec3 compute_light(vec3 attenuation,
 }
 
 void main() {
-    vec4 albedo_rgba = texture(sampler2D(albedo_map, tex_sampler), f_uv) * in_diffuse;
+    vec4 albedo_rgba = expand_channels(BASE_COLOR_SLOT, texture(sampler2D(albedo_map, tex_sampler), transform_uv(BASE_COLOR_SLOT, slot_uv(BASE_COLOR_SLOT)))) * in_diffuse * f_color;
     if (albedo_rgba.a == 0.0) discard;
     vec3 albedo = albedo_rgba.rgb;
 
@@ -126,7 +151,7 @@ void main() {
     float roughness2 = roughness * roughness;
     vec3 fresnel_base = mix(vec3(0.04), albedo, metallic);
 
-    float ambient_occlusion = 1.0; //texture(sampler2D(ao_map, tex_sampler), f_uv).r;
+    float ambient_occlusion = 1.0; //expand_channels(AO_SLOT, texture(sampler2D(ao_map, tex_sampler), transform_uv(AO_SLOT, slot_uv(AO_SLOT)))).r;
 
     vec3 view_dist = camera_pos - f_world_pos.xyz;
     vec3 view_direction = normalize(view_dist);
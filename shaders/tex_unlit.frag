@@ -1,6 +1,8 @@
 #version 450
 
 layout(location = 0) in vec2 v_TexCoord;
+layout(location = 2) in vec4 v_Color;
+layout(location = 3) in vec2 v_TexCoord1;
 layout(location = 0) out vec4 o_Target;
 layout(location = 1) out vec4 bright_color;
 
@@ -10,10 +12,31 @@ layout(set = 2, binding = 0) uniform MeshPart {
     layout(offset = 32) float rough_factor;
     layout(offset = 48) vec3 emissive_factor;
     layout(offset = 64) vec3 extra_emissive;
+    layout(offset = 80) vec4 uv_transforms[10];
+    layout(offset = 240) ivec4 texture_channels[2];
+    layout(offset = 272) ivec4 texture_uv_sets[2];
 };
 layout(set = 2, binding = 1) uniform sampler s_Color;
 layout(set = 2, binding = 2) uniform texture2D t_Color;
 
+// Apply the base color KHR_texture_transform, stored as the two rows of a 2x3 matrix
+vec2 transform_uv(vec2 uv) {
+    vec3 uv1 = vec3(uv, 1.0);
+    return vec2(dot(uv_transforms[0].xyz, uv1), dot(uv_transforms[1].xyz, uv1));
+}
+
+// Single and dual channel textures are stored as luminance and luminance-alpha
+vec4 expand_channels(vec4 texel) {
+    int channels = texture_channels[0].x;
+    if (channels == 1) {
+        return vec4(texel.rrr, 1.0);
+    } else if (channels == 2) {
+        return vec4(texel.rrr, texel.g);
+    }
+    return texel;
+}
+
 void main() {
-    o_Target = texture(sampler2D(t_Color, s_Color), v_TexCoord) * in_diffuse;
+    vec2 uv = texture_uv_sets[0].x == 1 ? v_TexCoord1 : v_TexCoord;
+    o_Target = expand_channels(texture(sampler2D(t_Color, s_Color), transform_uv(uv))) * in_diffuse * v_Color;
 }
@@ -0,0 +1,43 @@
+#version 450
+
+layout(location = 0) in vec3 a_Pos;
+layout(location = 1) in vec3 a_Norm;
+layout(location = 2) in vec4 a_Tang;
+layout(location = 3) in vec2 a_TexCoord;
+layout(location = 6) in vec4 a_Color;
+layout(location = 7) in vec2 a_TexCoord1;
+layout(location = 8) in vec4 i_Transform0;
+layout(location = 9) in vec4 i_Transform1;
+layout(location = 10) in vec4 i_Transform2;
+layout(location = 11) in vec4 i_Transform3;
+layout(location = 12) in vec4 i_Color;
+layout(location = 0) out vec2 v_TexCoord;
+layout(location = 1) out vec4 v_Position;
+layout(location = 2) out vec4 v_Color;
+layout(location = 3) out vec2 v_TexCoord1;
+
+layout(set = 0, binding = 0) uniform Globals {
+    layout(offset = 0) mat4 view_proj;
+    layout(offset = 64) vec3 camera_pos;
+};
+layout(set = 1, binding = 0) uniform Mesh {
+    mat4 transform;
+};
+// Restores the positions and UVs of parts stored in the compact vertex format. Identity for
+// full precision parts.
+layout(set = 1, binding = 2) uniform Part {
+    vec4 position_scale;
+    vec4 position_offset;
+    vec4 uv_scale_offset;
+};
+
+void main() {
+    vec3 pos = a_Pos * position_scale.xyz + position_offset.xyz;
+    vec2 uv = a_TexCoord * uv_scale_offset.xy + uv_scale_offset.zw;
+    v_TexCoord = uv;
+    v_TexCoord1 = a_TexCoord1;
+    v_Color = a_Color * i_Color;
+    mat4 instance_transform = mat4(i_Transform0, i_Transform1, i_Transform2, i_Transform3);
+    v_Position = transform * instance_transform * vec4(pos, 1.0);
+    gl_Position = view_proj * v_Position;
+}
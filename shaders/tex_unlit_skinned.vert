@@ -0,0 +1,48 @@
+#version 450
+
+layout(location = 0) in vec3 a_Pos;
+layout(location = 1) in vec3 a_Norm;
+layout(location = 2) in vec4 a_Tang;
+layout(location = 3) in vec2 a_TexCoord;
+layout(location = 4) in uvec4 a_Joints;
+layout(location = 5) in vec4 a_Weights;
+layout(location = 6) in vec4 a_Color;
+layout(location = 7) in vec2 a_TexCoord1;
+layout(location = 0) out vec2 v_TexCoord;
+layout(location = 1) out vec4 v_Position;
+layout(location = 2) out vec4 v_Color;
+layout(location = 3) out vec2 v_TexCoord1;
+
+layout(set = 0, binding = 0) uniform Globals {
+    layout(offset = 0) mat4 view_proj;
+    layout(offset = 64) vec3 camera_pos;
+};
+layout(set = 1, binding = 0) uniform Mesh {
+    mat4 transform;
+};
+layout(set = 1, binding = 1) uniform Joints {
+    mat4 joint_matrices[256];
+};
+// Restores the positions and UVs of parts stored in the compact vertex format. Identity for
+// full precision parts.
+layout(set = 1, binding = 2) uniform Part {
+    vec4 position_scale;
+    vec4 position_offset;
+    vec4 uv_scale_offset;
+};
+
+void main() {
+    vec3 pos = a_Pos * position_scale.xyz + position_offset.xyz;
+    vec2 uv = a_TexCoord * uv_scale_offset.xy + uv_scale_offset.zw;
+    mat4 skin =
+        a_Weights.x * joint_matrices[a_Joints.x] +
+        a_Weights.y * joint_matrices[a_Joints.y] +
+        a_Weights.z * joint_matrices[a_Joints.z] +
+        a_Weights.w * joint_matrices[a_Joints.w];
+
+    v_TexCoord = uv;
+    v_TexCoord1 = a_TexCoord1;
+    v_Color = a_Color;
+    v_Position = transform * skin * vec4(pos, 1.0);
+    gl_Position = view_proj * v_Position;
+}
@@ -0,0 +1,570 @@
+use std::rc::Rc;
+
+use slotmap::SecondaryMap;
+use ultraviolet::{Lerp, Rotor3, Slerp, Vec3};
+
+use super::node::NodeContent;
+use super::scene::{NodeId, Scene};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    /// Hermite spline, with an in and out tangent stored around each keyframe's value
+    CubicSpline,
+}
+
+/// The node property a channel animates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnimationProperty {
+    Translation,
+    Rotation,
+    Scale,
+    /// The morph target weights of the node's mesh
+    MorphWeights,
+}
+
+/// The keyframes of one animated property.
+#[derive(Debug, Clone)]
+pub struct Keyframes {
+    pub property: AnimationProperty,
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds, ascending
+    pub times: Vec<f32>,
+    /// The components of each keyframe's value, back to back: XYZ for translation and scale, a
+    /// quaternion XYZW for rotation and one weight per morph target. With cubic spline
+    /// interpolation each keyframe is an in tangent, the value and an out tangent.
+    pub values: Vec<f32>,
+}
+
+impl Keyframes {
+    /// The number of components in a value.
+    pub fn width(&self) -> usize {
+        let values_per_key = match self.interpolation {
+            Interpolation::CubicSpline => 3,
+            _ => 1,
+        };
+        match self.times.len() {
+            0 => 0,
+            keys => self.values.len() / (keys * values_per_key),
+        }
+    }
+
+    /// The value at a time, holding the first and last keyframes outside of their range.
+    pub fn sample(&self, time: f32) -> Vec<f32> {
+        let width = self.width();
+        let keys = self.times.len();
+        if keys == 0 || width == 0 {
+            return Vec::new();
+        }
+
+        let value = |key: usize| -> &[f32] {
+            let start = match self.interpolation {
+                Interpolation::CubicSpline => (key * 3 + 1) * width,
+                _ => key * width,
+            };
+            &self.values[start..start + width]
+        };
+
+        if time <= self.times[0] {
+            return value(0).to_vec();
+        }
+        if time >= self.times[keys - 1] {
+            return value(keys - 1).to_vec();
+        }
+
+        let next = self.times.partition_point(|&key_time| key_time <= time);
+        let prev = next - 1;
+        let key_duration = self.times[next] - self.times[prev];
+        let t = (time - self.times[prev]) / key_duration;
+
+        match self.interpolation {
+            Interpolation::Step => value(prev).to_vec(),
+            Interpolation::Linear => match self.property {
+                AnimationProperty::Rotation => {
+                    let from = Rotor3::from_quaternion_array(quaternion(value(prev)));
+                    let to = Rotor3::from_quaternion_array(quaternion(value(next)));
+                    slerp(from, to, t).into_quaternion_array().to_vec()
+                }
+                _ => value(prev).iter()
+                    .zip(value(next))
+                    .map(|(&from, &to)| from + (to - from) * t)
+                    .collect(),
+            },
+            Interpolation::CubicSpline => {
+                let out_tangent = &self.values[(prev * 3 + 2) * width..][..width];
+                let in_tangent = &self.values[(next * 3) * width..][..width];
+                let (t2, t3) = (t * t, t * t * t);
+                let mut result = (0..width)
+                    .map(|i| {
+                        (2.0 * t3 - 3.0 * t2 + 1.0) * value(prev)[i]
+                            + (t3 - 2.0 * t2 + t) * key_duration * out_tangent[i]
+                            + (-2.0 * t3 + 3.0 * t2) * value(next)[i]
+                            + (t3 - t2) * key_duration * in_tangent[i]
+                    })
+                    .collect::<Vec<_>>();
+                if self.property == AnimationProperty::Rotation {
+                    let rotor = Rotor3::from_quaternion_array(quaternion(&result)).normalized();
+                    result = rotor.into_quaternion_array().to_vec();
+                }
+                result
+            }
+        }
+    }
+
+    /// The time of the last keyframe.
+    pub fn duration(&self) -> f32 {
+        self.times.last().copied().unwrap_or(0.0)
+    }
+}
+
+fn quaternion(value: &[f32]) -> [f32; 4] {
+    [value[0], value[1], value[2], value[3]]
+}
+
+/// Interpolate along the shortest arc
+fn slerp(from: Rotor3, to: Rotor3, t: f32) -> Rotor3 {
+    let to = if from.dot(to) < 0.0 { to * -1.0 } else { to };
+    from.slerp(to, t).normalized()
+}
+
+/// Keyframes driving a node in a `Scene`.
+#[derive(Debug, Clone)]
+pub struct AnimationChannel {
+    pub node: NodeId,
+    pub keyframes: Rc<Keyframes>,
+}
+
+/// A set of channels played together, like a character's walk cycle.
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    pub channels: Vec<AnimationChannel>,
+}
+
+impl AnimationClip {
+    /// The time of the last keyframe of any channel.
+    pub fn duration(&self) -> f32 {
+        self.channels.iter()
+            .map(|channel| channel.keyframes.duration())
+            .fold(0.0, f32::max)
+    }
+
+    pub fn sample(&self, time: f32) -> Pose {
+        let mut pose = Pose::new();
+        for channel in &self.channels {
+            let value = channel.keyframes.sample(time);
+            if value.is_empty() {
+                continue;
+            }
+
+            let node = pose.node_entry(channel.node);
+            match channel.keyframes.property {
+                AnimationProperty::Translation => {
+                    node.translation = Some(Vec3::new(value[0], value[1], value[2]));
+                }
+                AnimationProperty::Rotation => {
+                    node.rotation = Some(Rotor3::from_quaternion_array(quaternion(&value)));
+                }
+                AnimationProperty::Scale => {
+                    node.scale = Some(Vec3::new(value[0], value[1], value[2]));
+                }
+                AnimationProperty::MorphWeights => node.morph_weights = Some(value),
+            }
+        }
+        pose
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// The animated properties of a node. Properties that aren't animated are `None`.
+#[derive(Debug, Clone, Default)]
+pub struct NodePose {
+    pub translation: Option<Vec3>,
+    pub rotation: Option<Rotor3>,
+    pub scale: Option<Vec3>,
+    pub morph_weights: Option<Vec<f32>>,
+}
+
+/// Sampled node properties, ready to apply to a `Scene`.
+#[derive(Debug, Clone, Default)]
+pub struct Pose {
+    pub nodes: SecondaryMap<NodeId, NodePose>,
+}
+
+impl Pose {
+    pub fn new() -> Self {
+        Pose { nodes: SecondaryMap::new() }
+    }
+
+    fn node_entry(&mut self, node: NodeId) -> &mut NodePose {
+        if !self.nodes.contains_key(node) {
+            self.nodes.insert(node, NodePose::default());
+        }
+        &mut self.nodes[node]
+    }
+
+    /// Interpolate from this pose to another by `factor`, from 0 to 1. A property that only one
+    /// of the poses animates is taken from that pose.
+    pub fn blend(&self, other: &Pose, factor: f32) -> Pose {
+        fn mix<T: Clone>(a: &Option<T>, b: &Option<T>, lerp: impl Fn(&T, &T) -> T) -> Option<T> {
+            match (a, b) {
+                (Some(a), Some(b)) => Some(lerp(a, b)),
+                _ => a.clone().or_else(|| b.clone()),
+            }
+        }
+
+        let mut pose = self.clone();
+        for (id, b) in other.nodes.iter() {
+            let node = pose.node_entry(id);
+            let a = node.clone();
+            *node = NodePose {
+                translation: mix(&a.translation, &b.translation, |a, b| a.lerp(*b, factor)),
+                rotation: mix(&a.rotation, &b.rotation, |a, b| slerp(*a, *b, factor)),
+                scale: mix(&a.scale, &b.scale, |a, b| a.lerp(*b, factor)),
+                morph_weights: mix(&a.morph_weights, &b.morph_weights, |a, b| {
+                    a.iter().zip(b).map(|(&a, &b)| a + (b - a) * factor).collect()
+                }),
+            };
+        }
+        pose
+    }
+
+    /// Set the posed nodes' transforms, and the morph weights of the meshes they hold. Skins
+    /// follow their joint nodes.
+    pub fn apply(&self, scene: &mut Scene) {
+        for (id, pose) in self.nodes.iter() {
+            let node = scene.node_mut(id);
+            if let Some(translation) = pose.translation {
+                node.set_position(translation);
+            }
+            if let Some(rotation) = pose.rotation {
+                node.set_rotation(rotation);
+            }
+            if let Some(scale) = pose.scale {
+                node.set_scale(scale);
+            }
+            if let (Some(weights), NodeContent::Mesh(mesh)) = (&pose.morph_weights, node.content()) {
+                scene.mesh_mut(mesh).morph_weights.clone_from(weights);
+            }
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Plays a clip over time, optionally cross-fading from the previous clip.
+pub struct AnimationPlayer {
+    /// Playback rate, 1 being real time. Negative speeds play backwards.
+    pub speed: f32,
+    /// Wrap around at the ends of the clip instead of stopping
+    pub looping: bool,
+
+    clip: Option<Rc<AnimationClip>>,
+    time: f32,
+    playing: bool,
+    fade: Option<Fade>,
+}
+
+// A clip being faded out
+struct Fade {
+    clip: Rc<AnimationClip>,
+    time: f32,
+    duration: f32,
+    elapsed: f32,
+}
+
+impl AnimationPlayer {
+    pub fn new() -> Self {
+        AnimationPlayer {
+            speed: 1.0,
+            looping: true,
+            clip: None,
+            time: 0.0,
+            playing: false,
+            fade: None,
+        }
+    }
+
+    /// Play a clip from its start, replacing the current one.
+    pub fn play(&mut self, clip: Rc<AnimationClip>) {
+        self.clip = Some(clip);
+        self.time = 0.0;
+        self.playing = true;
+        self.fade = None;
+    }
+
+    /// Play a clip from its start, blending from the current clip over `duration` seconds.
+    pub fn crossfade(&mut self, clip: Rc<AnimationClip>, duration: f32) {
+        self.fade = self.clip.take().map(|previous| Fade {
+            clip: previous,
+            time: self.time,
+            duration,
+            elapsed: 0.0,
+        });
+        self.clip = Some(clip);
+        self.time = 0.0;
+        self.playing = true;
+    }
+
+    pub fn pause(&mut self) { self.playing = false; }
+    pub fn resume(&mut self) { self.playing = true; }
+    pub fn is_playing(&self) -> bool { self.playing }
+
+    pub fn clip(&self) -> Option<&Rc<AnimationClip>> { self.clip.as_ref() }
+    pub fn time(&self) -> f32 { self.time }
+
+    pub fn seek(&mut self, time: f32) {
+        self.time = time;
+    }
+
+    /// Advance playback by `dt` seconds. A clip that doesn't loop pauses at its end.
+    pub fn update(&mut self, dt: f32) {
+        if !self.playing {
+            return;
+        }
+        let step = dt * self.speed;
+
+        if let Some(ref mut fade) = self.fade {
+            fade.elapsed += dt;
+            fade.time = advance(fade.time, step, fade.clip.duration(), self.looping).0;
+            if fade.elapsed >= fade.duration {
+                self.fade = None;
+            }
+        }
+
+        if let Some(ref clip) = self.clip {
+            let (time, finished) = advance(self.time, step, clip.duration(), self.looping);
+            self.time = time;
+            if finished {
+                self.playing = false;
+            }
+        }
+    }
+
+    /// The current pose, blended with the clip being faded out.
+    pub fn pose(&self) -> Pose {
+        let pose = match self.clip {
+            Some(ref clip) => clip.sample(self.time),
+            None => Pose::new(),
+        };
+        match self.fade {
+            Some(ref fade) if fade.duration > 0.0 => {
+                let factor = (fade.elapsed / fade.duration).min(1.0);
+                fade.clip.sample(fade.time).blend(&pose, factor)
+            }
+            _ => pose,
+        }
+    }
+
+    pub fn apply(&self, scene: &mut Scene) {
+        self.pose().apply(scene);
+    }
+}
+
+/// Step a playback time, wrapping or clamping it to the clip. Also returns whether a clip that
+/// doesn't loop reached its end.
+fn advance(time: f32, step: f32, duration: f32, looping: bool) -> (f32, bool) {
+    let time = time + step;
+    if duration <= 0.0 {
+        (0.0, !looping)
+    } else if looping {
+        (time.rem_euclid(duration), false)
+    } else if time >= duration {
+        (duration, true)
+    } else if time <= 0.0 {
+        (0.0, step < 0.0)
+    } else {
+        (time, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyframes(
+        property: AnimationProperty,
+        interpolation: Interpolation,
+        times: &[f32],
+        values: &[f32],
+    ) -> Keyframes {
+        Keyframes { property, interpolation, times: times.to_vec(), values: values.to_vec() }
+    }
+
+    fn assert_near(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    /// Quaternions q and -q are the same rotation.
+    fn assert_same_rotation(actual: &[f32], expected: [f32; 4]) {
+        let dot: f32 = actual.iter().zip(&expected).map(|(a, e)| a * e).sum();
+        assert!((dot.abs() - 1.0).abs() < 1e-5, "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn step() {
+        let keys = keyframes(
+            AnimationProperty::Translation, Interpolation::Step,
+            &[0.0, 1.0, 2.0],
+            &[0.0, 0.0, 0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0],
+        );
+        assert_eq!(keys.width(), 3);
+        assert_eq!(keys.sample(0.5), [0.0, 0.0, 0.0]);
+        assert_eq!(keys.sample(1.0), [1.0, 2.0, 3.0]);
+        assert_eq!(keys.sample(1.99), [1.0, 2.0, 3.0]);
+    }
+
+    #[test]
+    fn linear() {
+        let keys = keyframes(
+            AnimationProperty::MorphWeights, Interpolation::Linear,
+            &[1.0, 3.0],
+            &[0.0, 1.0, 1.0, 0.0],
+        );
+        assert_eq!(keys.width(), 2);
+        assert_near(&keys.sample(2.0), &[0.5, 0.5]);
+        assert_near(&keys.sample(2.5), &[0.75, 0.25]);
+    }
+
+    #[test]
+    fn linear_rotation_slerps() {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        // From no rotation to a quarter turn about Y
+        let keys = keyframes(
+            AnimationProperty::Rotation, Interpolation::Linear,
+            &[0.0, 1.0],
+            &[0.0, 0.0, 0.0, 1.0, 0.0, half, 0.0, half],
+        );
+        let eighth = std::f32::consts::FRAC_PI_8;
+        let sampled = keys.sample(0.5);
+        assert_same_rotation(&sampled, [0.0, eighth.sin(), 0.0, eighth.cos()]);
+        let length: f32 = sampled.iter().map(|c| c * c).sum();
+        assert!((length - 1.0).abs() < 1e-5);
+
+        // -q is the same rotation as q, so the shortest arc doesn't rotate at all
+        let keys = keyframes(
+            AnimationProperty::Rotation, Interpolation::Linear,
+            &[0.0, 1.0],
+            &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, -1.0],
+        );
+        assert_same_rotation(&keys.sample(0.5), [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn cubic_spline() {
+        // In tangent, value and out tangent of each key
+        let keys = keyframes(
+            AnimationProperty::MorphWeights, Interpolation::CubicSpline,
+            &[0.0, 2.0],
+            &[0.0, 0.0, 1.0, 0.0, 1.0, 0.0],
+        );
+        assert_eq!(keys.width(), 1);
+        // Hermite basis at t = 0.5 with a key duration of 2: 0.5 * 0 + 0.125 * 2 * 1 + 0.5 * 1
+        assert_near(&keys.sample(1.0), &[0.75]);
+        // The value is hit exactly at the keys, not a tangent
+        assert_near(&keys.sample(0.0), &[0.0]);
+        assert_near(&keys.sample(2.0), &[1.0]);
+
+        // Zero tangents ease in and out
+        let keys = keyframes(
+            AnimationProperty::MorphWeights, Interpolation::CubicSpline,
+            &[0.0, 1.0],
+            &[0.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+        );
+        assert_near(&keys.sample(0.25), &[0.15625]);
+        assert_near(&keys.sample(0.5), &[0.5]);
+    }
+
+    #[test]
+    fn cubic_spline_rotation_is_normalized() {
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        let keys = keyframes(
+            AnimationProperty::Rotation, Interpolation::CubicSpline,
+            &[0.0, 1.0],
+            &[
+                0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0,
+                0.0, 0.0, 0.0, 0.0, 0.0, half, 0.0, half, 0.0, 0.0, 0.0, 0.0,
+            ],
+        );
+        let sampled = keys.sample(0.5);
+        let length: f32 = sampled.iter().map(|c| c * c).sum();
+        assert!((length - 1.0).abs() < 1e-5, "{:?}", sampled);
+        let eighth = std::f32::consts::FRAC_PI_8;
+        assert_same_rotation(&sampled, [0.0, eighth.sin(), 0.0, eighth.cos()]);
+    }
+
+    #[test]
+    fn clamps_outside_keys() {
+        let keys = keyframes(
+            AnimationProperty::Scale, Interpolation::Linear,
+            &[1.0, 2.0],
+            &[1.0, 1.0, 1.0, 2.0, 2.0, 2.0],
+        );
+        assert_eq!(keys.sample(0.0), [1.0, 1.0, 1.0]);
+        assert_eq!(keys.sample(-5.0), [1.0, 1.0, 1.0]);
+        assert_eq!(keys.sample(2.0), [2.0, 2.0, 2.0]);
+        assert_eq!(keys.sample(10.0), [2.0, 2.0, 2.0]);
+        assert_eq!(keys.duration(), 2.0);
+
+        let single = keyframes(
+            AnimationProperty::Translation, Interpolation::Linear, &[0.5], &[1.0, 2.0, 3.0],
+        );
+        assert_eq!(single.sample(0.0), [1.0, 2.0, 3.0]);
+        assert_eq!(single.sample(1.0), [1.0, 2.0, 3.0]);
+
+        let empty = keyframes(AnimationProperty::Translation, Interpolation::Linear, &[], &[]);
+        assert!(empty.sample(0.0).is_empty());
+    }
+
+    #[test]
+    fn advance_wraps_and_clamps() {
+        // Looping wraps both ways
+        assert_eq!(advance(0.5, 0.75, 1.0, true), (0.25, false));
+        assert_eq!(advance(0.25, -0.5, 1.0, true), (0.75, false));
+
+        // Not looping stops at either end, finishing only in the direction of play
+        assert_eq!(advance(0.5, 0.25, 1.0, false), (0.75, false));
+        assert_eq!(advance(0.5, 0.75, 1.0, false), (1.0, true));
+        assert_eq!(advance(0.25, -0.5, 1.0, false), (0.0, true));
+        assert_eq!(advance(0.0, 0.0, 1.0, false), (0.0, false));
+
+        // An empty clip has nowhere to go
+        assert_eq!(advance(0.5, 0.5, 0.0, true), (0.0, false));
+        assert_eq!(advance(0.5, 0.5, 0.0, false), (0.0, true));
+    }
+
+    #[test]
+    fn player_wraps_or_pauses_at_the_end() {
+        let keys = keyframes(
+            AnimationProperty::Translation, Interpolation::Linear,
+            &[0.0, 2.0],
+            &[0.0, 0.0, 0.0, 2.0, 0.0, 0.0],
+        );
+        let node = slotmap::SlotMap::<NodeId, ()>::with_key().insert(());
+        let clip = Rc::new(AnimationClip {
+            name: None,
+            channels: vec![AnimationChannel { node, keyframes: Rc::new(keys) }],
+        });
+
+        let mut player = AnimationPlayer::new();
+        player.play(clip.clone());
+        player.update(1.5);
+        assert_eq!(player.time(), 1.5);
+        player.update(1.0);
+        assert_eq!(player.time(), 0.5);
+        assert!(player.is_playing());
+
+        player.looping = false;
+        player.play(clip);
+        player.update(3.0);
+        assert_eq!(player.time(), 2.0);
+        assert!(!player.is_playing());
+        let pose = player.pose();
+        assert_eq!(pose.nodes[node].translation, Some(Vec3::new(2.0, 0.0, 0.0)));
+    }
+}
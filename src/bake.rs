@@ -0,0 +1,374 @@
+//! Offline texture baking, as done by the `wgpu-pbr-bake` binary. Textures are decoded, mipped
+//! and block compressed ahead of time into a KTX2 sidecar next to the source image, which the
+//! runtime loader prefers while it's newer than the source.
+
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use image::GenericImageView;
+use ktx2::Format;
+
+use crate::bcn::{compress_bc1, compress_bc4, compress_bc5, compress_bc7};
+use crate::gltf::{gltf_texture_paths, GltfLoadError};
+use crate::mipmaps::{generate_mipmaps, MipLevel};
+use crate::obj::obj_texture_paths;
+use crate::resources::TextureOptions;
+
+const KTX2_MAGIC: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+// Khronos data format descriptor values
+const KHR_DF_MODEL_BC1A: u32 = 128;
+const KHR_DF_MODEL_BC4: u32 = 131;
+const KHR_DF_MODEL_BC5: u32 = 132;
+const KHR_DF_MODEL_BC7: u32 = 134;
+const KHR_DF_PRIMARIES_BT709: u32 = 1;
+const KHR_DF_TRANSFER_LINEAR: u32 = 1;
+const KHR_DF_TRANSFER_SRGB: u32 = 2;
+const KHR_DF_CHANNEL_BC1A_ALPHA: u32 = 1;
+
+/// The sidecar path of a baked texture, e.g. `albedo.png.ktx2` for `albedo.png`.
+pub fn baked_texture_path(texture_path: impl AsRef<Path>) -> PathBuf {
+    let mut path = texture_path.as_ref().as_os_str().to_owned();
+    path.push(".ktx2");
+    path.into()
+}
+
+/// Whether a texture has a baked sidecar at least as new as the texture itself.
+pub fn is_baked(texture_path: impl AsRef<Path>) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified());
+    let texture_path = texture_path.as_ref();
+    match (modified(&baked_texture_path(texture_path)), modified(texture_path)) {
+        (Ok(baked), Ok(source)) => baked >= source,
+        _ => false,
+    }
+}
+
+/// List the image files a glTF or OBJ model uses, along with how each is used so it can be
+/// baked with the right options. Images embedded in glTF buffers aren't included.
+pub fn model_textures(
+    model_path: impl AsRef<Path>,
+) -> Result<Vec<(PathBuf, TextureOptions)>, BakeError> {
+    let model_path = model_path.as_ref();
+    let mut textures = match model_path.extension().and_then(|ext| ext.to_str()) {
+        Some("gltf") | Some("glb") => gltf_texture_paths(model_path)?,
+        Some("obj") => obj_texture_paths(model_path).map_err(|err| {
+            BakeError::Message(format!("failed to load {}: {}", model_path.display(), err))
+        })?,
+        _ => return Err(BakeError::Message(
+            format!("{} is not a glTF or OBJ file", model_path.display()),
+        )),
+    };
+
+    // Materials commonly share textures
+    let mut seen = std::collections::HashSet::new();
+    textures.retain(|(path, _)| seen.insert(path.clone()));
+    Ok(textures)
+}
+
+/// Bake a texture to its KTX2 sidecar and return the sidecar's path. Opaque color textures are
+/// compressed to BC1, normal maps and textures with alpha to BC7, and linear grayscale textures
+/// to BC4, or BC5 with alpha. A full mip chain is always written; the loader drops it for
+/// textures loaded without mipmaps.
+pub fn bake_texture(
+    texture_path: impl AsRef<Path>,
+    options: TextureOptions,
+) -> Result<PathBuf, BakeError> {
+    use image::DynamicImage::*;
+
+    let texture_path = texture_path.as_ref();
+    let img = image::open(texture_path)?;
+
+    let (width, height) = (img.width(), img.height());
+    if width % 4 != 0 || height % 4 != 0 {
+        return Err(BakeError::Message(format!(
+            "{} is {}x{}, block compressed textures must be a multiple of 4 in each dimension",
+            texture_path.display(), width, height,
+        )));
+    }
+
+    let grayscale = !options.srgb && !options.normal_map;
+    let (format, channels, texels) = match img {
+        ImageLuma8(_) | ImageLuma16(_) if grayscale => {
+            (Format::BC4_UNORM_BLOCK, 1, img.to_luma8().into_raw())
+        }
+        ImageLumaA8(_) | ImageLumaA16(_) if grayscale => {
+            (Format::BC5_UNORM_BLOCK, 2, img.to_luma_alpha8().into_raw())
+        }
+        _ => {
+            let rgba = img.to_rgba8().into_raw();
+            let opaque = rgba.chunks(4).all(|texel| texel[3] == 255);
+            // BC1 halves the size of opaque textures, but is too lossy for normal maps
+            let format = match (opaque && !options.normal_map, options.srgb) {
+                (true, true) => Format::BC1_RGBA_SRGB_BLOCK,
+                (true, false) => Format::BC1_RGBA_UNORM_BLOCK,
+                (false, true) => Format::BC7_SRGB_BLOCK,
+                (false, false) => Format::BC7_UNORM_BLOCK,
+            };
+            (format, 4, rgba)
+        }
+    };
+
+    let base = MipLevel { width, height, texels };
+    let mips = generate_mipmaps(&base, channels, options.mipmap_filter());
+    let levels = std::iter::once(&base).chain(mips.iter())
+        .map(|level| {
+            let compress = match format {
+                Format::BC4_UNORM_BLOCK => compress_bc4,
+                Format::BC5_UNORM_BLOCK => compress_bc5,
+                Format::BC1_RGBA_SRGB_BLOCK | Format::BC1_RGBA_UNORM_BLOCK => compress_bc1,
+                _ => compress_bc7,
+            };
+            compress(level.width, level.height, &level.texels)
+        })
+        .collect::<Vec<_>>();
+
+    let baked_path = baked_texture_path(texture_path);
+    let mut file = io::BufWriter::new(std::fs::File::create(&baked_path)?);
+    write_ktx2(&mut file, format, width, height, &levels)?;
+    file.flush()?;
+
+    Ok(baked_path)
+}
+
+/// Write a 2D block compressed KTX2 file. `levels` starts at the base level.
+fn write_ktx2(
+    out: &mut impl Write,
+    format: Format,
+    width: u32,
+    height: u32,
+    levels: &[Vec<u8>],
+) -> io::Result<()> {
+    let dfd = data_format_descriptor(format);
+
+    let header_size = 80;
+    let level_index_size = 24 * levels.len();
+    let dfd_offset = header_size + level_index_size;
+    let block_size = if is_bc1_or_bc4(format) { 8 } else { 16 };
+    let align = |offset: usize| (offset + block_size - 1) / block_size * block_size;
+
+    // Level data is stored from the smallest level to the base level
+    let mut level_offsets = vec![0; levels.len()];
+    let mut offset = dfd_offset + dfd.len();
+    for (i, level) in levels.iter().enumerate().rev() {
+        offset = align(offset);
+        level_offsets[i] = offset;
+        offset += level.len();
+    }
+
+    out.write_all(&KTX2_MAGIC)?;
+    for value in &[
+        format.0.get(),             // vkFormat
+        1,                          // typeSize
+        width,
+        height,
+        0,                          // pixelDepth
+        0,                          // layerCount
+        1,                          // faceCount
+        levels.len() as u32,
+        0,                          // supercompressionScheme
+        dfd_offset as u32,
+        dfd.len() as u32,
+        0,                          // kvdByteOffset
+        0,                          // kvdByteLength
+    ] {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    // sgdByteOffset and sgdByteLength
+    out.write_all(&[0; 16])?;
+
+    for (level, &offset) in levels.iter().zip(level_offsets.iter()) {
+        out.write_all(&(offset as u64).to_le_bytes())?;
+        out.write_all(&(level.len() as u64).to_le_bytes())?;
+        out.write_all(&(level.len() as u64).to_le_bytes())?;
+    }
+
+    out.write_all(&dfd)?;
+
+    let mut written = dfd_offset + dfd.len();
+    for (i, level) in levels.iter().enumerate().rev() {
+        out.write_all(&vec![0; level_offsets[i] - written])?;
+        out.write_all(level)?;
+        written = level_offsets[i] + level.len();
+    }
+
+    Ok(())
+}
+
+fn is_bc1_or_bc4(format: Format) -> bool {
+    match format {
+        Format::BC1_RGBA_UNORM_BLOCK | Format::BC1_RGBA_SRGB_BLOCK | Format::BC4_UNORM_BLOCK => true,
+        _ => false,
+    }
+}
+
+/// A basic data format descriptor for one of the formats written by `bake_texture`, including
+/// the leading total size.
+fn data_format_descriptor(format: Format) -> Vec<u8> {
+    let (model, srgb) = match format {
+        Format::BC1_RGBA_UNORM_BLOCK => (KHR_DF_MODEL_BC1A, false),
+        Format::BC1_RGBA_SRGB_BLOCK => (KHR_DF_MODEL_BC1A, true),
+        Format::BC4_UNORM_BLOCK => (KHR_DF_MODEL_BC4, false),
+        Format::BC5_UNORM_BLOCK => (KHR_DF_MODEL_BC5, false),
+        Format::BC7_SRGB_BLOCK => (KHR_DF_MODEL_BC7, true),
+        _ => (KHR_DF_MODEL_BC7, false),
+    };
+    let block_bits = if is_bc1_or_bc4(format) { 64 } else { 128 };
+
+    // (bit offset, bit length, channel type) of each sample
+    let samples: &[(u32, u32, u32)] = match model {
+        KHR_DF_MODEL_BC5 => &[(0, 64, 0), (64, 64, 1)],
+        _ => &[(0, block_bits, 0)],
+    };
+    // The RGBA flavour of BC1 marks its sample as possibly having punch-through alpha
+    let channel = |c: u32| if model == KHR_DF_MODEL_BC1A { KHR_DF_CHANNEL_BC1A_ALPHA } else { c };
+
+    let block_size = 24 + 16 * samples.len() as u32;
+    let mut words = vec![
+        4 + block_size,
+        0, // vendorId and descriptorType
+        2 | (block_size << 16),
+        model
+            | (KHR_DF_PRIMARIES_BT709 << 8)
+            | (if srgb { KHR_DF_TRANSFER_SRGB } else { KHR_DF_TRANSFER_LINEAR } << 16),
+        3 | (3 << 8), // 4x4 texel blocks
+        block_bits / 8,
+        0,
+    ];
+    for &(offset, length, channel_type) in samples {
+        words.push(offset | ((length - 1) << 16) | (channel(channel_type) << 24));
+        words.push(0);
+        words.push(0);
+        words.push(u32::MAX);
+    }
+
+    words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub enum BakeError {
+    Io(io::Error),
+    Image(image::ImageError),
+    Gltf(GltfLoadError),
+    Message(String),
+}
+
+impl std::error::Error for BakeError {
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        match *self {
+            BakeError::Io(ref err) => Some(err),
+            BakeError::Image(ref err) => Some(err),
+            BakeError::Gltf(ref err) => Some(err),
+            BakeError::Message(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for BakeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            BakeError::Io(ref err) => write!(f, "Bake IO error: {}", err),
+            BakeError::Image(ref err) => write!(f, "Bake image error: {}", err),
+            BakeError::Gltf(ref err) => write!(f, "Bake glTF error: {}", err),
+            BakeError::Message(ref err) => write!(f, "Bake error: {}", err),
+        }
+    }
+}
+
+impl From<io::Error> for BakeError {
+    fn from(err: io::Error) -> BakeError {
+        BakeError::Io(err)
+    }
+}
+
+impl From<image::ImageError> for BakeError {
+    fn from(err: image::ImageError) -> BakeError {
+        BakeError::Image(err)
+    }
+}
+
+impl From<GltfLoadError> for BakeError {
+    fn from(err: GltfLoadError) -> BakeError {
+        BakeError::Gltf(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ktx2::{BasicDataFormatDescriptor, ColorModel, DataFormatDescriptorHeader, TransferFunction};
+
+    /// Write a file with distinguishable levels for an 8x8 texture and read it back.
+    fn round_trip(format: Format, block_size: usize) -> (Vec<u8>, Vec<Vec<u8>>) {
+        // 8x8, 4x4, 2x2 and 1x1, the last two padded to a whole block
+        let levels: Vec<Vec<u8>> = [4, 1, 1, 1].iter().enumerate()
+            .map(|(i, &blocks)| vec![i as u8 + 1; blocks * block_size])
+            .collect();
+        let mut bytes = Vec::new();
+        write_ktx2(&mut bytes, format, 8, 8, &levels).unwrap();
+        (bytes, levels)
+    }
+
+    #[test]
+    fn ktx2_header_and_levels() {
+        let (bytes, levels) = round_trip(Format::BC7_SRGB_BLOCK, 16);
+        let reader = ktx2::Reader::new(&bytes).unwrap();
+
+        let header = reader.header();
+        assert_eq!(header.format, Some(Format::BC7_SRGB_BLOCK));
+        assert_eq!((header.pixel_width, header.pixel_height), (8, 8));
+        assert_eq!((header.pixel_depth, header.layer_count, header.face_count), (0, 0, 1));
+        assert_eq!(header.level_count, 4);
+        assert_eq!(header.supercompression_scheme, None);
+
+        let read_levels: Vec<&[u8]> = reader.levels().collect();
+        assert_eq!(read_levels, levels.iter().map(|level| &level[..]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn ktx2_level_alignment() {
+        for &(format, block_size) in &[
+            (Format::BC1_RGBA_UNORM_BLOCK, 8),
+            (Format::BC4_UNORM_BLOCK, 8),
+            (Format::BC5_UNORM_BLOCK, 16),
+            (Format::BC7_UNORM_BLOCK, 16),
+        ] {
+            let (bytes, _) = round_trip(format, block_size);
+            let reader = ktx2::Reader::new(&bytes).unwrap();
+            for level in reader.levels() {
+                let offset = level.as_ptr() as usize - bytes.as_ptr() as usize;
+                assert_eq!(offset % block_size, 0, "{:?} level at {}", format, offset);
+            }
+        }
+    }
+
+    #[test]
+    fn ktx2_data_format_descriptor() {
+        for &(format, block_size, model, transfer, sample_count) in &[
+            (Format::BC1_RGBA_SRGB_BLOCK, 8, ColorModel::BC1A, TransferFunction::SRGB, 1),
+            (Format::BC4_UNORM_BLOCK, 8, ColorModel::BC4, TransferFunction::Linear, 1),
+            (Format::BC5_UNORM_BLOCK, 16, ColorModel::BC5, TransferFunction::Linear, 2),
+            (Format::BC7_UNORM_BLOCK, 16, ColorModel::BC7, TransferFunction::Linear, 1),
+        ] {
+            let (bytes, _) = round_trip(format, block_size);
+            let reader = ktx2::Reader::new(&bytes).unwrap();
+            let dfds: Vec<_> = reader.data_format_descriptors().collect();
+            assert_eq!(dfds.len(), 1);
+            assert_eq!(dfds[0].header, DataFormatDescriptorHeader::BASIC);
+
+            let dfd = BasicDataFormatDescriptor::parse(dfds[0].data).unwrap();
+            assert_eq!(dfd.color_model, Some(model));
+            assert_eq!(dfd.transfer_function, Some(transfer));
+            assert_eq!(dfd.texel_block_dimensions, [4, 4, 1, 1]);
+            assert_eq!(dfd.bytes_planes[0], block_size as u32);
+
+            let samples: Vec<_> = dfd.sample_information().collect();
+            assert_eq!(samples.len(), sample_count);
+            let bits: u32 = samples.iter().map(|sample| sample.bit_length).sum();
+            assert_eq!(bits, 8 * block_size as u32);
+        }
+    }
+}
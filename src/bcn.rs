@@ -0,0 +1,524 @@
+//! Simple block compression encoders used when baking textures offline. They favour being small
+//! and predictable over ultimate quality: endpoints come from the principal axis of each block,
+//! refined once with least squares for BC7.
+
+/// Compress opaque RGBA8 texels to BC1. Alpha is ignored.
+pub fn compress_bc1(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for_each_block(width, height, 4, rgba, |block| out.extend_from_slice(&bc1_block(block)));
+    out
+}
+
+/// Compress single channel texels to BC4.
+pub fn compress_bc4(width: u32, height: u32, r: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for_each_block(width, height, 1, r, |block| {
+        out.extend_from_slice(&bc4_block(&channel(block, 0)));
+    });
+    out
+}
+
+/// Compress dual channel texels to BC5.
+pub fn compress_bc5(width: u32, height: u32, rg: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for_each_block(width, height, 2, rg, |block| {
+        out.extend_from_slice(&bc4_block(&channel(block, 0)));
+        out.extend_from_slice(&bc4_block(&channel(block, 1)));
+    });
+    out
+}
+
+/// Compress RGBA8 texels to BC7, using only mode 6 (a single subset with alpha).
+pub fn compress_bc7(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for_each_block(width, height, 4, rgba, |block| out.extend_from_slice(&bc7_mode6_block(block)));
+    out
+}
+
+/// Call `f` with the texels of every 4x4 block, in row-major block order. Blocks overlapping
+/// the right or bottom edge repeat the last column or row.
+fn for_each_block(
+    width: u32,
+    height: u32,
+    channels: usize,
+    texels: &[u8],
+    mut f: impl FnMut(&[[u8; 4]; 16]),
+) {
+    for by in 0..(height + 3) / 4 {
+        for bx in 0..(width + 3) / 4 {
+            let mut block = [[0u8; 4]; 16];
+            for (i, texel) in block.iter_mut().enumerate() {
+                let x = (bx * 4 + i as u32 % 4).min(width - 1);
+                let y = (by * 4 + i as u32 / 4).min(height - 1);
+                let offset = (y * width + x) as usize * channels;
+                texel[..channels].copy_from_slice(&texels[offset..offset + channels]);
+            }
+            f(&block);
+        }
+    }
+}
+
+fn channel(block: &[[u8; 4]; 16], c: usize) -> [u8; 16] {
+    let mut values = [0; 16];
+    for i in 0..16 {
+        values[i] = block[i][c];
+    }
+    values
+}
+
+/// The endpoints of the principal axis through the block's texels, in `channels` dimensions.
+fn principal_endpoints(block: &[[u8; 4]; 16], channels: usize) -> ([f32; 4], [f32; 4]) {
+    let mut mean = [0.0f32; 4];
+    for texel in block {
+        for c in 0..channels {
+            mean[c] += texel[c] as f32 / 16.0;
+        }
+    }
+
+    let mut covariance = [[0.0f32; 4]; 4];
+    for texel in block {
+        for a in 0..channels {
+            for b in 0..channels {
+                covariance[a][b] += (texel[a] as f32 - mean[a]) * (texel[b] as f32 - mean[b]);
+            }
+        }
+    }
+
+    // Power iteration for the dominant eigenvector, starting from the covariance of the channel
+    // that varies most. A fixed start like the gray axis can be orthogonal to it, e.g. when one
+    // channel rises as another falls.
+    let widest = (0..channels)
+        .max_by(|&a, &b| covariance[a][a].partial_cmp(&covariance[b][b]).unwrap())
+        .unwrap();
+    let mut axis = covariance[widest];
+    for _ in 0..8 {
+        let mut next = [0.0f32; 4];
+        for a in 0..channels {
+            for b in 0..channels {
+                next[a] += covariance[a][b] * axis[b];
+            }
+        }
+        let len = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if len < 1e-6 {
+            break;
+        }
+        for c in 0..channels {
+            axis[c] = next[c] / len;
+        }
+    }
+
+    let (mut min_t, mut max_t) = (f32::MAX, f32::MIN);
+    for texel in block {
+        let t = (0..channels).map(|c| (texel[c] as f32 - mean[c]) * axis[c]).sum::<f32>();
+        min_t = min_t.min(t);
+        max_t = max_t.max(t);
+    }
+
+    let mut e0 = [0.0f32; 4];
+    let mut e1 = [0.0f32; 4];
+    for c in 0..channels {
+        e0[c] = (mean[c] + axis[c] * min_t).max(0.0).min(255.0);
+        e1[c] = (mean[c] + axis[c] * max_t).max(0.0).min(255.0);
+    }
+    (e0, e1)
+}
+
+fn distance2(a: &[u8; 4], b: &[u8; 4], channels: usize) -> u32 {
+    (0..channels).map(|c| {
+        let d = a[c] as i32 - b[c] as i32;
+        (d * d) as u32
+    }).sum()
+}
+
+fn nearest(palette: &[[u8; 4]], texel: &[u8; 4], channels: usize) -> usize {
+    (0..palette.len())
+        .min_by_key(|&i| distance2(&palette[i], texel, channels))
+        .unwrap()
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// BC1
+
+fn to_565(c: &[f32; 4]) -> u16 {
+    let r = (c[0] * 31.0 / 255.0 + 0.5) as u16;
+    let g = (c[1] * 63.0 / 255.0 + 0.5) as u16;
+    let b = (c[2] * 31.0 / 255.0 + 0.5) as u16;
+    (r << 11) | (g << 5) | b
+}
+
+fn from_565(c: u16) -> [u8; 4] {
+    let r = (c >> 11) & 31;
+    let g = (c >> 5) & 63;
+    let b = c & 31;
+    [
+        ((r << 3) | (r >> 2)) as u8,
+        ((g << 2) | (g >> 4)) as u8,
+        ((b << 3) | (b >> 2)) as u8,
+        255,
+    ]
+}
+
+fn bc1_block(block: &[[u8; 4]; 16]) -> [u8; 8] {
+    let (e0, e1) = principal_endpoints(block, 3);
+    let (mut c0, mut c1) = (to_565(&e1), to_565(&e0));
+    // color0 > color1 selects the opaque four color mode
+    if c0 < c1 {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+
+    let mut indices = 0u32;
+    if c0 != c1 {
+        let (p0, p1) = (from_565(c0), from_565(c1));
+        let mut palette = [p0, p1, [0; 4], [0; 4]];
+        for c in 0..3 {
+            palette[2][c] = ((2 * p0[c] as u32 + p1[c] as u32) / 3) as u8;
+            palette[3][c] = ((p0[c] as u32 + 2 * p1[c] as u32) / 3) as u8;
+        }
+        for (i, texel) in block.iter().enumerate() {
+            indices |= (nearest(&palette, texel, 3) as u32) << (2 * i);
+        }
+    }
+
+    let mut out = [0u8; 8];
+    out[0..2].copy_from_slice(&c0.to_le_bytes());
+    out[2..4].copy_from_slice(&c1.to_le_bytes());
+    out[4..8].copy_from_slice(&indices.to_le_bytes());
+    out
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// BC4
+
+fn bc4_block(values: &[u8; 16]) -> [u8; 8] {
+    let r0 = *values.iter().max().unwrap();
+    let r1 = *values.iter().min().unwrap();
+
+    let mut indices = 0u64;
+    if r0 != r1 {
+        // r0 > r1 selects the eight value mode
+        let mut palette = [[0u8; 4]; 8];
+        palette[0][0] = r0;
+        palette[1][0] = r1;
+        for i in 2..8 {
+            palette[i][0] = (((8 - i as u32) * r0 as u32 + (i as u32 - 1) * r1 as u32 + 3) / 7) as u8;
+        }
+        for (i, &v) in values.iter().enumerate() {
+            indices |= (nearest(&palette, &[v, 0, 0, 0], 1) as u64) << (3 * i);
+        }
+    }
+
+    let mut out = [0u8; 8];
+    out[0] = r0;
+    out[1] = r1;
+    out[2..8].copy_from_slice(&indices.to_le_bytes()[..6]);
+    out
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// BC7
+
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Quantize an endpoint to 7 bits per channel plus a shared p-bit, returning the 7 bit values,
+/// the p-bit and the resulting 8 bit color.
+fn bc7_quantize_endpoint(e: &[f32; 4]) -> ([u8; 4], u8, [u8; 4]) {
+    let mut best = ([0u8; 4], 0u8, [0u8; 4]);
+    let mut best_error = f32::MAX;
+    for p in 0..2u8 {
+        let mut q = [0u8; 4];
+        let mut color = [0u8; 4];
+        let mut error = 0.0;
+        for c in 0..4 {
+            q[c] = ((e[c] - p as f32) / 2.0).round().max(0.0).min(127.0) as u8;
+            color[c] = (q[c] << 1) | p;
+            error += (color[c] as f32 - e[c]).powi(2);
+        }
+        if error < best_error {
+            best_error = error;
+            best = (q, p, color);
+        }
+    }
+    best
+}
+
+fn bc7_palette(c0: &[u8; 4], c1: &[u8; 4]) -> [[u8; 4]; 16] {
+    let mut palette = [[0u8; 4]; 16];
+    for (i, &w) in BC7_WEIGHTS_4.iter().enumerate() {
+        for c in 0..4 {
+            palette[i][c] = (((64 - w) * c0[c] as u32 + w * c1[c] as u32 + 32) >> 6) as u8;
+        }
+    }
+    palette
+}
+
+fn bc7_mode6_block(block: &[[u8; 4]; 16]) -> [u8; 16] {
+    let (mut e0, mut e1) = principal_endpoints(block, 4);
+
+    let mut indices = [0usize; 16];
+    for pass in 0..2 {
+        let (_, _, c0) = bc7_quantize_endpoint(&e0);
+        let (_, _, c1) = bc7_quantize_endpoint(&e1);
+        let palette = bc7_palette(&c0, &c1);
+        for (i, texel) in block.iter().enumerate() {
+            indices[i] = nearest(&palette, texel, 4);
+        }
+
+        if pass == 1 {
+            break;
+        }
+
+        // Least squares fit of the endpoints to the chosen weights
+        let (mut aa, mut ab, mut bb) = (0.0f32, 0.0f32, 0.0f32);
+        let mut ax = [0.0f32; 4];
+        let mut bx = [0.0f32; 4];
+        for (i, texel) in block.iter().enumerate() {
+            let t = BC7_WEIGHTS_4[indices[i]] as f32 / 64.0;
+            let (a, b) = (1.0 - t, t);
+            aa += a * a;
+            ab += a * b;
+            bb += b * b;
+            for c in 0..4 {
+                ax[c] += a * texel[c] as f32;
+                bx[c] += b * texel[c] as f32;
+            }
+        }
+        let det = aa * bb - ab * ab;
+        if det.abs() < 1e-6 {
+            break;
+        }
+        for c in 0..4 {
+            e0[c] = ((bb * ax[c] - ab * bx[c]) / det).max(0.0).min(255.0);
+            e1[c] = ((aa * bx[c] - ab * ax[c]) / det).max(0.0).min(255.0);
+        }
+    }
+
+    let (mut q0, mut p0, _) = bc7_quantize_endpoint(&e0);
+    let (mut q1, mut p1, _) = bc7_quantize_endpoint(&e1);
+    // The anchor texel's index has an implicit 0 MSB
+    if indices[0] >= 8 {
+        std::mem::swap(&mut q0, &mut q1);
+        std::mem::swap(&mut p0, &mut p1);
+        for index in indices.iter_mut() {
+            *index = 15 - *index;
+        }
+    }
+
+    let mut bits = BitWriter::default();
+    bits.write(1 << 6, 7);
+    for c in 0..4 {
+        bits.write(q0[c] as u128, 7);
+        bits.write(q1[c] as u128, 7);
+    }
+    bits.write(p0 as u128, 1);
+    bits.write(p1 as u128, 1);
+    bits.write(indices[0] as u128, 3);
+    for &index in &indices[1..] {
+        bits.write(index as u128, 4);
+    }
+    bits.value.to_le_bytes()
+}
+
+#[derive(Default)]
+struct BitWriter {
+    value: u128,
+    offset: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u128, bit_count: u32) {
+        self.value |= value << self.offset;
+        self.offset += bit_count;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_bc1(block: &[u8]) -> [[u8; 4]; 16] {
+        let c0 = u16::from_le_bytes([block[0], block[1]]);
+        let c1 = u16::from_le_bytes([block[2], block[3]]);
+        let (p0, p1) = (from_565(c0), from_565(c1));
+        let mut palette = [p0, p1, [0; 4], [0; 4]];
+        for c in 0..3 {
+            if c0 > c1 {
+                palette[2][c] = ((2 * p0[c] as u32 + p1[c] as u32) / 3) as u8;
+                palette[3][c] = ((p0[c] as u32 + 2 * p1[c] as u32) / 3) as u8;
+            } else {
+                palette[2][c] = ((p0[c] as u32 + p1[c] as u32) / 2) as u8;
+            }
+        }
+        palette[2][3] = 255;
+        palette[3][3] = if c0 > c1 { 255 } else { 0 };
+
+        let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+        let mut texels = [[0; 4]; 16];
+        for (i, texel) in texels.iter_mut().enumerate() {
+            *texel = palette[(indices >> (2 * i)) as usize & 3];
+        }
+        texels
+    }
+
+    fn decode_bc4(block: &[u8]) -> [u8; 16] {
+        let (r0, r1) = (block[0] as u32, block[1] as u32);
+        let mut palette = [r0, r1, 0, 0, 0, 0, 0, 255];
+        if r0 > r1 {
+            for i in 2..8 {
+                palette[i] = ((8 - i as u32) * r0 + (i as u32 - 1) * r1 + 3) / 7;
+            }
+        } else {
+            for i in 2..6 {
+                palette[i] = ((6 - i as u32) * r0 + (i as u32 - 1) * r1 + 2) / 5;
+            }
+        }
+
+        let mut bits = [0u8; 8];
+        bits[..6].copy_from_slice(&block[2..8]);
+        let indices = u64::from_le_bytes(bits);
+        let mut values = [0; 16];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = palette[(indices >> (3 * i)) as usize & 7] as u8;
+        }
+        values
+    }
+
+    fn decode_bc7_mode6(block: &[u8]) -> [[u8; 4]; 16] {
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(block);
+        let mut bits = u128::from_le_bytes(bytes);
+        let mut read = |bit_count: u32| {
+            let value = bits & ((1 << bit_count) - 1);
+            bits >>= bit_count;
+            value as u8
+        };
+
+        assert_eq!(read(7), 1 << 6, "not a mode 6 block");
+        let mut q = [[0u8; 4]; 2];
+        for c in 0..4 {
+            q[0][c] = read(7);
+            q[1][c] = read(7);
+        }
+        let (p0, p1) = (read(1), read(1));
+        let c0 = [q[0][0] << 1 | p0, q[0][1] << 1 | p0, q[0][2] << 1 | p0, q[0][3] << 1 | p0];
+        let c1 = [q[1][0] << 1 | p1, q[1][1] << 1 | p1, q[1][2] << 1 | p1, q[1][3] << 1 | p1];
+        let palette = bc7_palette(&c0, &c1);
+
+        let mut texels = [[0; 4]; 16];
+        for (i, texel) in texels.iter_mut().enumerate() {
+            *texel = palette[read(if i == 0 { 3 } else { 4 }) as usize];
+        }
+        texels
+    }
+
+    fn max_error(decoded: &[[u8; 4]; 16], block: &[[u8; 4]; 16], channels: usize) -> u8 {
+        decoded.iter().zip(block.iter())
+            .flat_map(|(d, b)| (0..channels).map(move |c| (d[c] as i32 - b[c] as i32).unsigned_abs() as u8))
+            .max()
+            .unwrap()
+    }
+
+    fn block_of(texel: impl Fn(usize) -> [u8; 4]) -> [[u8; 4]; 16] {
+        let mut block = [[0; 4]; 16];
+        for (i, t) in block.iter_mut().enumerate() {
+            *t = texel(i);
+        }
+        block
+    }
+
+    #[test]
+    fn bc1_round_trip() {
+        // A solid color only loses its 5:6:5 quantization
+        let solid = block_of(|_| [200, 100, 50, 255]);
+        assert!(max_error(&decode_bc1(&bc1_block(&solid)), &solid, 3) <= 4);
+
+        // Four colors evenly spaced on a line land on the palette
+        let steps = block_of(|i| {
+            let t = (i % 4) as u8;
+            [t * 80, 240 - t * 80, 64 + t * 32, 255]
+        });
+        let decoded = decode_bc1(&bc1_block(&steps));
+        assert!(max_error(&decoded, &steps, 3) <= 8);
+        assert!(decoded.iter().all(|texel| texel[3] == 255), "opaque block decoded with alpha");
+
+        // Otherwise the error is at most half the palette spacing, plus quantization. Red rises
+        // as green falls, so the axis isn't found from the gray diagonal.
+        let gradient = block_of(|i| [i as u8 * 16, 255 - i as u8 * 16, 128, 255]);
+        assert!(max_error(&decode_bc1(&bc1_block(&gradient)), &gradient, 3) <= 44);
+    }
+
+    #[test]
+    fn bc4_round_trip() {
+        let solid = [77; 16];
+        assert_eq!(decode_bc4(&bc4_block(&solid)), solid);
+
+        // Eight levels between the extremes are exact
+        let mut steps = [0u8; 16];
+        for (i, value) in steps.iter_mut().enumerate() {
+            *value = 10 + (i % 8) as u8 * 30;
+        }
+        assert_eq!(decode_bc4(&bc4_block(&steps)), steps);
+
+        let mut gradient = [0u8; 16];
+        for (i, value) in gradient.iter_mut().enumerate() {
+            *value = i as u8 * 17;
+        }
+        let decoded = decode_bc4(&bc4_block(&gradient));
+        for (d, g) in decoded.iter().zip(gradient.iter()) {
+            // Half the spacing of 8 levels over 0..=255
+            assert!((*d as i32 - *g as i32).abs() <= 19, "{} decoded as {}", g, d);
+        }
+    }
+
+    #[test]
+    fn bc5_round_trip() {
+        let rg: Vec<u8> = (0..16u8).flat_map(|i| vec![i * 16, 255 - i * 8]).collect();
+        let compressed = compress_bc5(4, 4, &rg);
+        assert_eq!(compressed.len(), 16);
+
+        let (r, g) = (decode_bc4(&compressed[..8]), decode_bc4(&compressed[8..]));
+        for i in 0..16 {
+            assert!((r[i] as i32 - rg[2 * i] as i32).abs() <= 19);
+            assert!((g[i] as i32 - rg[2 * i + 1] as i32).abs() <= 19);
+        }
+    }
+
+    #[test]
+    fn bc7_round_trip() {
+        let solid = block_of(|_| [200, 100, 50, 128]);
+        assert!(max_error(&decode_bc7_mode6(&bc7_mode6_block(&solid)), &solid, 4) <= 1);
+
+        // 16 levels along a line with varying alpha, including a descending anchor texel
+        let gradient = block_of(|i| {
+            let t = 15 - i as u8;
+            [t * 16, 255 - t * 16, 128, 64 + t * 8]
+        });
+        assert!(max_error(&decode_bc7_mode6(&bc7_mode6_block(&gradient)), &gradient, 4) <= 4);
+
+        // Colors off the principal axis are approximated within half the palette spacing
+        let noisy = block_of(|i| {
+            let i = i as u8;
+            [i * 16, i % 3 * 30, 255 - i * 12, 255]
+        });
+        assert!(max_error(&decode_bc7_mode6(&bc7_mode6_block(&noisy)), &noisy, 4) <= 40);
+    }
+
+    #[test]
+    fn partial_blocks_repeat_the_edge() {
+        // 5x3 texels cover two blocks
+        let rgba: Vec<u8> = (0..15u8).flat_map(|i| vec![i % 5 * 50, if i / 5 == 2 { 200 } else { 0 }, 0, 255]).collect();
+        let compressed = compress_bc1(5, 3, &rgba);
+        assert_eq!(compressed.len(), 2 * 8);
+
+        // The second block is the last column, repeated, and the last row is repeated too
+        let decoded = decode_bc1(&compressed[8..]);
+        for (i, texel) in decoded.iter().enumerate() {
+            let y = (i / 4).min(2);
+            let expected = &rgba[(y * 5 + 4) * 4..][..2];
+            assert!((texel[0] as i32 - expected[0] as i32).abs() <= 4, "texel {}", i);
+            assert!((texel[1] as i32 - expected[1] as i32).abs() <= 4, "texel {}", i);
+        }
+
+        assert_eq!(compress_bc4(6, 6, &[0; 36]).len(), 4 * 8);
+        assert_eq!(compress_bc7(4, 8, &[0; 128]).len(), 2 * 16);
+    }
+}
@@ -0,0 +1,54 @@
+//! Bake the textures of glTF and OBJ models into pre-mipped, block compressed KTX2 sidecars.
+//!
+//! Usage: wgpu-pbr-bake [--force] <model.gltf|model.glb|model.obj>...
+//!
+//! Textures with an up to date sidecar are skipped unless `--force` is passed.
+
+use wgpu_pbr::bake::{bake_texture, is_baked, model_textures};
+
+fn main() {
+    let mut force = false;
+    let mut models = Vec::new();
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--force" => force = true,
+            _ => models.push(arg),
+        }
+    }
+
+    if models.is_empty() {
+        eprintln!("usage: wgpu-pbr-bake [--force] <model.gltf|model.glb|model.obj>...");
+        std::process::exit(2);
+    }
+
+    let mut failed = false;
+    for model in &models {
+        let textures = match model_textures(model) {
+            Ok(textures) => textures,
+            Err(err) => {
+                eprintln!("{}: {}", model, err);
+                failed = true;
+                continue;
+            }
+        };
+
+        for (texture, options) in textures {
+            if !force && is_baked(&texture) {
+                println!("{} is up to date", texture.display());
+                continue;
+            }
+
+            match bake_texture(&texture, options) {
+                Ok(baked) => println!("{} -> {}", texture.display(), baked.display()),
+                Err(err) => {
+                    eprintln!("{}: {}", texture.display(), err);
+                    failed = true;
+                }
+            }
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
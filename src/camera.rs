@@ -1,30 +1,84 @@
 use ultraviolet::{self, Mat4, Vec3, Vec4};
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Perspective {
+        /// Vertical field of view, in radians
+        fov_y: f32,
+        near: f32,
+        /// `None` for an infinite far plane
+        far: Option<f32>,
+    },
+    Orthographic {
+        /// Half the height of the view. The width follows the aspect ratio.
+        y_mag: f32,
+        near: f32,
+        far: f32,
+    },
+}
+
+impl Projection {
+    pub fn matrix(&self, aspect_ratio: f32) -> Mat4 {
+        use ultraviolet::projection::rh_yup::*;
+
+        match *self {
+            Projection::Perspective { fov_y, near, far: Some(far) } => {
+                perspective_gl(fov_y, aspect_ratio, near, far)
+            }
+            Projection::Perspective { fov_y, near, far: None } => {
+                perspective_infinite_z_gl(fov_y, aspect_ratio, near)
+            }
+            Projection::Orthographic { y_mag, near, far } => {
+                let x_mag = y_mag * aspect_ratio;
+                orthographic_gl(-x_mag, x_mag, -y_mag, y_mag, near, far)
+            }
+        }
+    }
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection::Perspective { fov_y: f32::to_radians(45.0), near: 0.001, far: Some(1000.0) }
+    }
+}
+
 pub struct Camera {
     pub proj: Mat4,
 
+    projection: Projection,
+    aspect_ratio: f32,
     position: Vec3,
     view: Mat4,
 }
 
 impl Camera {
     pub fn new(aspect_ratio: f32) -> Self {
-        let proj = ultraviolet::projection::rh_yup::perspective_gl(
-            f32::to_radians(45.0), aspect_ratio, 0.001, 1000.0,
-        );
+        Camera::with_projection(Projection::default(), aspect_ratio)
+    }
 
+    pub fn with_projection(projection: Projection, aspect_ratio: f32) -> Self {
         Camera {
-            proj,
+            proj: projection.matrix(aspect_ratio),
 
+            projection,
+            aspect_ratio,
             position: Vec3::zero(),
             view: Mat4::identity(),
         }
     }
 
     pub fn resize(&mut self, aspect_ratio: f32) {
-        self.proj = ultraviolet::projection::rh_yup::perspective_gl(
-            f32::to_radians(45.0), aspect_ratio, 0.001, 1000.0,
-        );
+        self.aspect_ratio = aspect_ratio;
+        self.proj = self.projection.matrix(aspect_ratio);
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    pub fn set_projection(&mut self, projection: Projection) {
+        self.projection = projection;
+        self.proj = projection.matrix(self.aspect_ratio);
     }
 
     pub fn look_at(
@@ -0,0 +1,629 @@
+use std::path::Path;
+use std::sync::Once;
+
+use basis_universal::{
+    DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc, TranscodeParameters, Transcoder,
+    TranscoderBlockFormat, TranscoderTextureFormat,
+};
+use ktx2::{
+    BasicDataFormatDescriptor, ColorModel, DataFormatDescriptorHeader, Format,
+    SupercompressionScheme,
+};
+
+use crate::bake::{baked_texture_path, is_baked};
+use crate::resources::TextureOptions;
+use crate::texture::Texture;
+
+const KTX2_MAGIC: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+// KHR_DF_CHANNEL_UASTC_* channel types that carry alpha
+const UASTC_CHANNEL_RGBA: u32 = 3;
+const UASTC_CHANNEL_RRRG: u32 = 5;
+
+// Sizes of the BasisLZ global data header and its per image slice descriptions
+const BASIS_LZ_HEADER_SIZE: usize = 20;
+const BASIS_LZ_IMAGE_DESC_SIZE: usize = 20;
+// Sizes of the .basis file header and its slice descriptions
+const BASIS_HEADER_SIZE: usize = 77;
+const BASIS_SLICE_DESC_SIZE: usize = 23;
+const BASIS_SIGNATURE: usize = 0x4273;
+const BASIS_VERSION: usize = 0x13;
+const BASIS_FLAG_ETC1S: usize = 1;
+const BASIS_FLAG_HAS_ALPHA_SLICES: usize = 4;
+const BASIS_SLICE_HAS_ALPHA: u8 = 1;
+
+static TRANSCODER_INIT: Once = Once::new();
+
+pub fn is_ktx2(bytes: &[u8]) -> bool {
+    bytes.starts_with(&KTX2_MAGIC)
+}
+
+/// Load a 2D KTX2 texture. Textures in a GPU format are uploaded as-is if the device supports
+/// it. UASTC and BasisLZ (ETC1S) textures are transcoded to the best block format enabled on the
+/// device: BC7 (BC1 for opaque non-normal maps), then ASTC 4x4, then ETC2 for opaque textures,
+/// falling back to RGBA8. Zstandard supercompression is supported.
+pub fn load_ktx2(
+    device: &mut wgpu::Device,
+    queue: &mut wgpu::Queue,
+    options: TextureOptions,
+    bytes: &[u8],
+) -> Result<Texture, CompressedTextureError> {
+    let reader = ktx2::Reader::new(bytes)?;
+    let header = reader.header();
+
+    if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+        return Err(CompressedTextureError::Unsupported(
+            "only 2D textures are supported, not arrays, cubemaps or 3D textures".into(),
+        ));
+    }
+
+    // A level count of 0 asks the loader to generate mips, which can't be done for block
+    // compressed data
+    let level_count = if options.mipmaps { header.level_count.max(1) } else { 1 };
+    let levels = reader.levels()
+        .take(level_count as usize)
+        .map(|level| match header.supercompression_scheme {
+            None => Ok(level.to_vec()),
+            Some(SupercompressionScheme::Zstandard) => {
+                zstd::decode_all(level).map_err(CompressedTextureError::Zstd)
+            }
+            // ETC1S slices, decoded with the codebooks in the global data when transcoding
+            Some(SupercompressionScheme::BasisLZ) => Ok(level.to_vec()),
+            Some(scheme) => Err(CompressedTextureError::Unsupported(
+                format!("{:?} supercompression", scheme),
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (format, levels) = match header.format {
+        Some(format) => {
+            let format = native_format(format).ok_or_else(|| {
+                CompressedTextureError::Unsupported(format!("format {:?}", format))
+            })?;
+            let required_features = format.describe().required_features;
+            if !device.features().contains(required_features) {
+                return Err(CompressedTextureError::Unsupported(
+                    format!("{:?} without device feature {:?}", format, required_features),
+                ));
+            }
+            (format, levels)
+        }
+        None => {
+            let dfd = reader.data_format_descriptors()
+                .find(|dfd| dfd.header == DataFormatDescriptorHeader::BASIC)
+                .and_then(|dfd| BasicDataFormatDescriptor::parse(dfd.data).ok())
+                .ok_or_else(|| {
+                    CompressedTextureError::Unsupported("missing data format descriptor".into())
+                })?;
+            let has_alpha = match dfd.color_model {
+                Some(ColorModel::UASTC) => dfd.sample_information().any(|sample| {
+                    sample.channel_type == UASTC_CHANNEL_RGBA
+                        || sample.channel_type == UASTC_CHANNEL_RRRG
+                }),
+                // ETC1S alpha is a second slice, described by a second sample
+                Some(ColorModel::ETC1S) => dfd.sample_information().count() == 2,
+                model => {
+                    return Err(CompressedTextureError::Unsupported(
+                        format!("color model {:?}", model),
+                    ));
+                }
+            };
+
+            let (width, height) = (header.pixel_width, header.pixel_height);
+            let (block_format, texture_format, format) =
+                transcode_target(device, options, has_alpha, width, height);
+            let levels = if dfd.color_model == Some(ColorModel::ETC1S) {
+                if header.supercompression_scheme != Some(SupercompressionScheme::BasisLZ) {
+                    return Err(CompressedTextureError::Unsupported(
+                        "ETC1S without BasisLZ supercompression".into(),
+                    ));
+                }
+                let basis_file = etc1s_basis_file(
+                    reader.supercompression_global_data(),
+                    header.level_count.max(1) as usize,
+                    has_alpha,
+                    width, height,
+                    &levels,
+                )?;
+                transcode_etc1s(texture_format, &basis_file, levels.len() as u32)?
+            } else {
+                transcode_uastc(block_format, has_alpha, width, height, &levels)?
+            };
+
+            (format, levels)
+        }
+    };
+
+    Ok(levels_to_gpu(
+        device, queue,
+        format,
+        header.pixel_width, header.pixel_height,
+        &levels,
+    ))
+}
+
+/// Load the sidecar written by `wgpu-pbr-bake` for a texture, if there is an up to date one the
+/// device can sample with the requested color space.
+pub fn load_baked_texture(
+    device: &mut wgpu::Device,
+    queue: &mut wgpu::Queue,
+    options: TextureOptions,
+    texture_path: &Path,
+) -> Option<Texture> {
+    if !is_baked(texture_path) {
+        return None;
+    }
+
+    let bytes = std::fs::read(baked_texture_path(texture_path)).ok()?;
+    let format = ktx2::Reader::new(&bytes).ok()?.header().format.and_then(native_format)?;
+    // Baked with other options, e.g. the same image used as both color and data
+    if format.describe().srgb != options.srgb {
+        return None;
+    }
+    // Two channel sidecars hold luminance and alpha, while two channel metallic roughness maps
+    // are sampled as (roughness, metallic)
+    if options.metallic_roughness && format == wgpu::TextureFormat::Bc5RgUnorm {
+        return None;
+    }
+
+    load_ktx2(device, queue, options, &bytes).ok()
+}
+
+/// Pick the block format to transcode basis-universal textures to, as the low-level UASTC
+/// transcoder's block format, the ETC1S transcoder's texture format, and the uploaded format.
+fn transcode_target(
+    device: &wgpu::Device,
+    options: TextureOptions,
+    has_alpha: bool,
+    width: u32,
+    height: u32,
+) -> (TranscoderBlockFormat, TranscoderTextureFormat, wgpu::TextureFormat) {
+    use wgpu::TextureFormat::*;
+
+    let features = device.features();
+    // Block compressed textures must have block aligned dimensions
+    let block_aligned = width % 4 == 0 && height % 4 == 0;
+    let srgb = options.srgb;
+    if block_aligned && features.contains(wgpu::Features::TEXTURE_COMPRESSION_BC) {
+        // BC1 halves the size of opaque textures, but is too lossy for normal maps
+        if has_alpha || options.normal_map {
+            let format = if srgb { Bc7RgbaUnormSrgb } else { Bc7RgbaUnorm };
+            (TranscoderBlockFormat::BC7, TranscoderTextureFormat::BC7_RGBA, format)
+        } else {
+            let format = if srgb { Bc1RgbaUnormSrgb } else { Bc1RgbaUnorm };
+            (TranscoderBlockFormat::BC1, TranscoderTextureFormat::BC1_RGB, format)
+        }
+    } else if block_aligned && features.contains(wgpu::Features::TEXTURE_COMPRESSION_ASTC_LDR) {
+        let format = if srgb { Astc4x4RgbaUnormSrgb } else { Astc4x4RgbaUnorm };
+        (TranscoderBlockFormat::ASTC_4x4, TranscoderTextureFormat::ASTC_4x4_RGBA, format)
+    } else if block_aligned && !has_alpha
+        && features.contains(wgpu::Features::TEXTURE_COMPRESSION_ETC2)
+    {
+        // ETC1 blocks are valid ETC2 RGB blocks. wgpu 0.11 has no ETC2 RGBA8 format, so textures
+        // with alpha fall back to RGBA8 on ETC2 only devices.
+        let format = if srgb { Etc2RgbUnormSrgb } else { Etc2RgbUnorm };
+        (TranscoderBlockFormat::ETC1, TranscoderTextureFormat::ETC1_RGB, format)
+    } else {
+        let format = if srgb { Rgba8UnormSrgb } else { Rgba8Unorm };
+        (TranscoderBlockFormat::RGBA32, TranscoderTextureFormat::RGBA32, format)
+    }
+}
+
+fn transcode_uastc(
+    block_format: TranscoderBlockFormat,
+    has_alpha: bool,
+    width: u32,
+    height: u32,
+    levels: &[Vec<u8>],
+) -> Result<Vec<Vec<u8>>, CompressedTextureError> {
+    TRANSCODER_INIT.call_once(basis_universal::transcoder_init);
+
+    let transcoder = LowLevelUastcTranscoder::new();
+    levels.iter().enumerate()
+        .map(|(mip_level, level)| {
+            let level_width = (width >> mip_level).max(1);
+            let level_height = (height >> mip_level).max(1);
+            transcoder
+                .transcode_slice(
+                    level,
+                    SliceParametersUastc {
+                        num_blocks_x: (level_width + 3) / 4,
+                        num_blocks_y: (level_height + 3) / 4,
+                        has_alpha,
+                        original_width: level_width,
+                        original_height: level_height,
+                    },
+                    DecodeFlags::HIGH_QUALITY,
+                    block_format,
+                )
+                .map_err(|_| CompressedTextureError::Transcode(
+                    format!("failed to transcode mip level {} to {:?}", mip_level, block_format),
+                ))
+        })
+        .collect()
+}
+
+fn transcode_etc1s(
+    texture_format: TranscoderTextureFormat,
+    basis_file: &[u8],
+    level_count: u32,
+) -> Result<Vec<Vec<u8>>, CompressedTextureError> {
+    TRANSCODER_INIT.call_once(basis_universal::transcoder_init);
+
+    let mut transcoder = Transcoder::new();
+    transcoder.prepare_transcoding(basis_file).map_err(|_| {
+        CompressedTextureError::Transcode("failed to decode the ETC1S codebooks".into())
+    })?;
+    (0..level_count)
+        .map(|level_index| {
+            let parameters = TranscodeParameters {
+                image_index: 0,
+                level_index,
+                decode_flags: Some(DecodeFlags::HIGH_QUALITY),
+                ..Default::default()
+            };
+            transcoder
+                .transcode_image_level(basis_file, texture_format, parameters)
+                .map_err(|_| CompressedTextureError::Transcode(format!(
+                    "failed to transcode mip level {} to {:?}", level_index, texture_format,
+                )))
+        })
+        .collect()
+}
+
+/// Wrap the ETC1S slices of a BasisLZ texture in a .basis file, whose header points at the
+/// codebooks in the KTX2 global data. basis-universal only exposes its low-level transcoder for
+/// UASTC, so ETC1S goes through the .basis transcoder, which wraps the low-level one.
+fn etc1s_basis_file(
+    global_data: &[u8],
+    image_count: usize,
+    has_alpha: bool,
+    width: u32,
+    height: u32,
+    levels: &[Vec<u8>],
+) -> Result<Vec<u8>, CompressedTextureError> {
+    let malformed =
+        || CompressedTextureError::Transcode("malformed BasisLZ global data".into());
+    let field = |offset: usize, size: usize| {
+        global_data.get(offset..offset + size)
+            .map(|bytes| bytes.iter().rev().fold(0, |value, &byte| (value << 8) | byte as usize))
+            .ok_or_else(malformed)
+    };
+
+    let endpoint_count = field(0, 2)?;
+    let selector_count = field(2, 2)?;
+    let endpoints_length = field(4, 4)?;
+    let selectors_length = field(8, 4)?;
+    let tables_length = field(12, 4)?;
+    let codebooks_start = BASIS_LZ_HEADER_SIZE + BASIS_LZ_IMAGE_DESC_SIZE * image_count;
+    let codebooks = global_data
+        .get(codebooks_start..codebooks_start + endpoints_length + selectors_length + tables_length)
+        .ok_or_else(malformed)?;
+    // The .basis header stores codebook sizes in 3 bytes and dimensions in 2
+    if endpoints_length >= 1 << 24 || selectors_length >= 1 << 24
+        || width > 0xFFFF || height > 0xFFFF
+    {
+        return Err(CompressedTextureError::Unsupported("ETC1S texture too large".into()));
+    }
+
+    // (mip level, slice flags, slice data), with each alpha slice following its color slice
+    let mut slices = Vec::new();
+    for (mip_level, level) in levels.iter().enumerate() {
+        let desc = BASIS_LZ_HEADER_SIZE + BASIS_LZ_IMAGE_DESC_SIZE * mip_level;
+        let mut level_slices = vec![(desc + 4, 0)];
+        if has_alpha {
+            level_slices.push((desc + 12, BASIS_SLICE_HAS_ALPHA));
+        }
+        for (field_offset, flags) in level_slices {
+            let (offset, length) = (field(field_offset, 4)?, field(field_offset + 4, 4)?);
+            let data = level.get(offset..offset + length).ok_or_else(malformed)?;
+            slices.push((mip_level, flags, data));
+        }
+    }
+
+    let endpoints_start = BASIS_HEADER_SIZE + BASIS_SLICE_DESC_SIZE * slices.len();
+    let selectors_start = endpoints_start + endpoints_length;
+    let tables_start = selectors_start + selectors_length;
+    let slices_start = tables_start + tables_length;
+    let file_size = slices_start + slices.iter().map(|(_, _, data)| data.len()).sum::<usize>();
+    let flags = BASIS_FLAG_ETC1S | if has_alpha { BASIS_FLAG_HAS_ALPHA_SLICES } else { 0 };
+
+    let mut file = Vec::with_capacity(file_size);
+    // The transcoder doesn't check the CRCs, so they're left zero
+    for &(value, size) in &[
+        (BASIS_SIGNATURE, 2),
+        (BASIS_VERSION, 2),
+        (BASIS_HEADER_SIZE, 2),
+        (0, 2), // header CRC
+        (file_size - BASIS_HEADER_SIZE, 4),
+        (0, 2), // data CRC
+        (slices.len(), 3),
+        (1, 3), // images
+        (0, 1), // ETC1S
+        (flags, 2),
+        (0, 1), // 2D
+        (0, 3), // microseconds per frame
+        (0, 4), // reserved
+        (0, 4), // user data
+        (0, 4),
+        (endpoint_count, 2),
+        (endpoints_start, 4),
+        (endpoints_length, 3),
+        (selector_count, 2),
+        (selectors_start, 4),
+        (selectors_length, 3),
+        (tables_start, 4),
+        (tables_length, 4),
+        (BASIS_HEADER_SIZE, 4), // slice descriptions
+        (0, 4), // extended data
+        (0, 4),
+    ] {
+        put_le(&mut file, value, size);
+    }
+
+    let mut slice_start = slices_start;
+    for &(mip_level, flags, data) in &slices {
+        let level_width = (width as usize >> mip_level).max(1);
+        let level_height = (height as usize >> mip_level).max(1);
+        for &(value, size) in &[
+            (0, 3), // image
+            (mip_level, 1),
+            (flags as usize, 1),
+            (level_width, 2),
+            (level_height, 2),
+            ((level_width + 3) / 4, 2),
+            ((level_height + 3) / 4, 2),
+            (slice_start, 4),
+            (data.len(), 4),
+            (0, 2), // CRC
+        ] {
+            put_le(&mut file, value, size);
+        }
+        slice_start += data.len();
+    }
+
+    file.extend_from_slice(codebooks);
+    for (_, _, data) in &slices {
+        file.extend_from_slice(data);
+    }
+
+    Ok(file)
+}
+
+fn put_le(bytes: &mut Vec<u8>, value: usize, size: usize) {
+    bytes.extend_from_slice(&(value as u32).to_le_bytes()[..size]);
+}
+
+fn native_format(format: Format) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat::*;
+
+    Some(match format {
+        Format::R8_UNORM => R8Unorm,
+        Format::R8G8_UNORM => Rg8Unorm,
+        Format::R8G8B8A8_UNORM => Rgba8Unorm,
+        Format::R8G8B8A8_SRGB => Rgba8UnormSrgb,
+        Format::R16G16B16A16_SFLOAT => Rgba16Float,
+        Format::BC1_RGBA_UNORM_BLOCK => Bc1RgbaUnorm,
+        Format::BC1_RGBA_SRGB_BLOCK => Bc1RgbaUnormSrgb,
+        Format::BC3_UNORM_BLOCK => Bc3RgbaUnorm,
+        Format::BC3_SRGB_BLOCK => Bc3RgbaUnormSrgb,
+        Format::BC4_UNORM_BLOCK => Bc4RUnorm,
+        Format::BC5_UNORM_BLOCK => Bc5RgUnorm,
+        Format::BC7_UNORM_BLOCK => Bc7RgbaUnorm,
+        Format::BC7_SRGB_BLOCK => Bc7RgbaUnormSrgb,
+        Format::ETC2_R8G8B8_UNORM_BLOCK => Etc2RgbUnorm,
+        Format::ETC2_R8G8B8_SRGB_BLOCK => Etc2RgbUnormSrgb,
+        Format::ASTC_4x4_UNORM_BLOCK => Astc4x4RgbaUnorm,
+        Format::ASTC_4x4_SRGB_BLOCK => Astc4x4RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+/// Upload tightly packed mip levels, starting at level 0.
+pub fn levels_to_gpu(
+    device: &mut wgpu::Device,
+    queue: &mut wgpu::Queue,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    levels: &[Vec<u8>],
+) -> Texture {
+    let info = format.describe();
+    let (block_width, block_height) =
+        (info.block_dimensions.0 as u32, info.block_dimensions.1 as u32);
+
+    let size = wgpu::Extent3d { width, height, depth_or_array_layers: 1 };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: None,
+        size,
+        mip_level_count: levels.len() as u32,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    });
+
+    for (mip_level, level) in levels.iter().enumerate() {
+        let blocks_x = ((width >> mip_level).max(1) + block_width - 1) / block_width;
+        let blocks_y = ((height >> mip_level).max(1) + block_height - 1) / block_height;
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: mip_level as u32,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            level,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(blocks_x * info.block_size as u32),
+                rows_per_image: None,
+            },
+            // Copies of compressed levels cover whole blocks
+            wgpu::Extent3d {
+                width: blocks_x * block_width,
+                height: blocks_y * block_height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    Texture::new(texture, format, size, levels.len() as u32)
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug)]
+pub enum CompressedTextureError {
+    Parse(ktx2::ParseError),
+    Zstd(std::io::Error),
+    Unsupported(String),
+    Transcode(String),
+}
+
+impl std::error::Error for CompressedTextureError {
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        match *self {
+            CompressedTextureError::Parse(ref err) => Some(err),
+            CompressedTextureError::Zstd(ref err) => Some(err),
+            CompressedTextureError::Unsupported(_) => None,
+            CompressedTextureError::Transcode(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for CompressedTextureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            CompressedTextureError::Parse(ref err) => write!(f, "KTX2 parse error: {}", err),
+            CompressedTextureError::Zstd(ref err) => write!(f, "KTX2 zstd error: {}", err),
+            CompressedTextureError::Unsupported(ref err) => {
+                write!(f, "Unsupported KTX2 texture: {}", err)
+            }
+            CompressedTextureError::Transcode(ref err) => {
+                write!(f, "KTX2 transcode error: {}", err)
+            }
+        }
+    }
+}
+
+impl From<ktx2::ParseError> for CompressedTextureError {
+    fn from(err: ktx2::ParseError) -> CompressedTextureError {
+        CompressedTextureError::Parse(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An 8x8 texture with mips and alpha, encoded with `basisu -ktx2 -mipmap`. Red ramps up from
+    // left to right and green from top to bottom. The left half is opaque and the right has
+    // alpha 64.
+    const ETC1S_ALPHA: [u8; 528] = [
+        0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00,
+        0x00, 0x01, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01,
+        0x00, 0x00, 0x00, 0xb0, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x00, 0x00, 0xec, 0x00, 0x00, 0x00,
+        0x24, 0x00, 0x00, 0x00, 0x10, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xf4, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x08,
+        0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x06, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x04, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3c, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x38, 0x00, 0xa3, 0x01, 0x02, 0x00, 0x03, 0x03, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x40, 0x00, 0x3f, 0x0f, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff, 0x1f, 0x00, 0x00, 0x00,
+        0x4b, 0x54, 0x58, 0x77, 0x72, 0x69, 0x74, 0x65, 0x72, 0x00, 0x42, 0x61, 0x73, 0x69, 0x73,
+        0x20, 0x55, 0x6e, 0x69, 0x76, 0x65, 0x72, 0x73, 0x61, 0x6c, 0x20, 0x31, 0x2e, 0x31, 0x36,
+        0x00, 0x00, 0x0c, 0x00, 0x08, 0x00, 0x3f, 0x00, 0x00, 0x00, 0x21, 0x00, 0x00, 0x00, 0x30,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01,
+        0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x1f, 0x40, 0x14,
+        0x00, 0x00, 0x00, 0x00, 0x20, 0x90, 0xc7, 0xce, 0x0f, 0x04, 0x88, 0x02, 0x20, 0x00, 0x00,
+        0x20, 0x0c, 0x9b, 0xc2, 0xb3, 0xed, 0x49, 0x18, 0x04, 0x30, 0x05, 0x40, 0x00, 0x00, 0x00,
+        0x10, 0x86, 0x99, 0x0e, 0x4b, 0x1a, 0x00, 0x11, 0x00, 0x00, 0x00, 0x00, 0x80, 0x20, 0x36,
+        0x5b, 0x21, 0xf0, 0x0f, 0x8c, 0xd7, 0x01, 0xb5, 0xdb, 0x34, 0xa1, 0x90, 0xe9, 0x67, 0x37,
+        0x04, 0xaa, 0x54, 0xff, 0x07, 0xa0, 0x4a, 0xf5, 0x07, 0x02, 0x2a, 0xfd, 0x07, 0xf0, 0xf7,
+        0xf7, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfa, 0xfa, 0xfa, 0xaa, 0xaa, 0xaa, 0xaa, 0x1a, 0x18,
+        0x18, 0x18, 0x00, 0x00, 0xc1, 0x44, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x92, 0xea, 0xbf,
+        0x02, 0x03, 0x10, 0x81, 0x21, 0x00, 0x00, 0x60, 0x18, 0xb6, 0x30, 0xfa, 0x92, 0x80, 0x87,
+        0x01, 0x03, 0x00, 0x00, 0x42, 0x56, 0x89, 0x57, 0xf8, 0x44, 0x00, 0x30, 0x01, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x80, 0x00, 0x02, 0x0b, 0x47, 0x7d, 0x23, 0xb3, 0xf5, 0xaf, 0x80, 0x01,
+        0x1e, 0xd0, 0x01,
+    ];
+
+    fn etc1s_basis_file_for(ktx2: &[u8]) -> Result<(Vec<u8>, u32), CompressedTextureError> {
+        let reader = ktx2::Reader::new(ktx2)?;
+        let header = reader.header();
+        let levels: Vec<_> = reader.levels().map(|level| level.to_vec()).collect();
+        let basis_file = etc1s_basis_file(
+            reader.supercompression_global_data(),
+            header.level_count as usize,
+            true,
+            header.pixel_width, header.pixel_height,
+            &levels,
+        )?;
+        Ok((basis_file, levels.len() as u32))
+    }
+
+    #[test]
+    fn etc1s_to_rgba8() {
+        let (basis_file, level_count) = etc1s_basis_file_for(&ETC1S_ALPHA).unwrap();
+        let levels = transcode_etc1s(TranscoderTextureFormat::RGBA32, &basis_file, level_count)
+            .unwrap();
+        let sizes: Vec<_> = levels.iter().map(|level| level.len()).collect();
+        assert_eq!(sizes, [8 * 8 * 4, 4 * 4 * 4, 2 * 2 * 4, 4]);
+
+        // The corners as `basisu -unpack` decodes them from the KTX2 file
+        let texel = |x: usize, y: usize| &levels[0][(y * 8 + x) * 4..][..4];
+        assert_eq!(texel(0, 0), [7, 7, 81, 255]);
+        assert_eq!(texel(7, 0), [185, 36, 110, 64]);
+        assert_eq!(texel(0, 7), [99, 248, 174, 255]);
+        assert_eq!(texel(7, 7), [248, 248, 174, 64]);
+    }
+
+    #[test]
+    fn etc1s_to_blocks() {
+        let (basis_file, level_count) = etc1s_basis_file_for(&ETC1S_ALPHA).unwrap();
+        for &(format, block_size) in &[
+            (TranscoderTextureFormat::BC7_RGBA, 16),
+            (TranscoderTextureFormat::ASTC_4x4_RGBA, 16),
+            (TranscoderTextureFormat::ETC1_RGB, 8),
+        ] {
+            let levels = transcode_etc1s(format, &basis_file, level_count).unwrap();
+            let sizes: Vec<_> = levels.iter().map(|level| level.len()).collect();
+            assert_eq!(sizes, [4 * block_size, block_size, block_size, block_size]);
+        }
+    }
+
+    #[test]
+    fn etc1s_malformed() {
+        let reader = ktx2::Reader::new(&ETC1S_ALPHA[..]).unwrap();
+        let global_data = reader.supercompression_global_data();
+        let levels: Vec<_> = reader.levels().map(|level| level.to_vec()).collect();
+
+        // Truncated codebooks
+        let truncated = &global_data[..global_data.len() - 1];
+        assert!(etc1s_basis_file(truncated, 4, true, 8, 8, &levels).is_err());
+        // Slices past the end of their level
+        let mut short_levels = levels.clone();
+        short_levels[0].pop();
+        assert!(etc1s_basis_file(global_data, 4, true, 8, 8, &short_levels).is_err());
+        // Garbage codebooks
+        let mut garbage = global_data.to_vec();
+        for byte in &mut garbage[BASIS_LZ_HEADER_SIZE + 4 * BASIS_LZ_IMAGE_DESC_SIZE..] {
+            *byte = 0xFF;
+        }
+        let basis_file = etc1s_basis_file(&garbage, 4, true, 8, 8, &levels).unwrap();
+        assert!(transcode_etc1s(TranscoderTextureFormat::RGBA32, &basis_file, 4).is_err());
+    }
+}
@@ -1,12 +1,25 @@
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use ultraviolet::{Bivec3, Mat4, Rotor3, Vec3};
+use ultraviolet::{Mat4, Rotor3, Vec3};
 
-use crate::resources::ResourceLoader;
+use crate::animation::{
+    AnimationChannel, AnimationClip, AnimationProperty, Interpolation, Keyframes,
+};
+use crate::camera::Projection;
+use crate::light::{PointLight, SpotLight};
+use crate::resources::{ResourceLoader, SamplerData, TextureLoadError, TextureOptions};
+use crate::scene::NodeId;
+use crate::texture::Texture;
+use gltf::accessor::{DataType, Item, Iter};
+use gltf::Semantic;
 use super::compute_tangents::compute_tangents;
-use super::mesh::{Vertex, MeshPartData, MeshPartGeometry, MaterialData, MaterialFactors};
+use super::mesh::{
+    Vertex, ExtraVertex, MeshInstance, MeshPartData, MeshPartGeometry, MaterialData,
+    MaterialFactors, MorphVertex, SkinVertex, TextureSamplers, TextureSlot, TextureSlots,
+    TextureTransform, TextureTransforms, Topology, VertexFormat, MAX_JOINTS,
+};
 
 /// Load a single mesh by its node's name from a glTF file. If a mesh with the specified name
 /// doesn't exist, `None` is returned. On success, a tuple of the mesh parts and the node's
@@ -19,9 +32,7 @@ pub fn load_gltf_single_mesh(
     let base_path = path.as_ref().parent().expect("gltf base path");
 
     let file = std::fs::File::open(&path)?;
-    let gltf = gltf::Gltf::from_reader(&file)?;
-
-    let gltf_buffers = GltfBuffers::load_from_gltf(base_path, &gltf)?;
+    let (gltf, gltf_buffers) = read_gltf_with_buffers(base_path, &file)?;
 
     for node in gltf.nodes() {
         if let Some(ref mesh) = node.mesh() {
@@ -43,83 +54,554 @@ pub fn load_gltf_single_mesh(
     Ok(None)
 }
 
+/// Load every mesh instance of a glTF file's scene, with the node transforms baked into the
+/// vertices.
 pub fn load_gltf(
     resources: &mut ResourceLoader,
     path: impl AsRef<Path>,
+) -> Result<Vec<MeshPartData>, GltfLoadError> {
+    Ok(load_gltf_scene(resources, path)?.flatten())
+}
+
+pub fn load_gltf_from_reader(
+    resources: &mut ResourceLoader,
+    path: impl AsRef<Path>,
+    r: impl std::io::Read + std::io::Seek,
 ) -> Result<Vec<MeshPartData>, GltfLoadError> {
     let base_path = path.as_ref().parent().expect("gltf base path");
 
-    let file = std::fs::File::open(&path)?;
-    let gltf = gltf::Gltf::from_reader(&file)?;
+    let (gltf, gltf_buffers) = read_gltf_with_buffers(base_path, r)?;
 
-    let gltf_buffers = GltfBuffers::load_from_gltf(base_path, &gltf)?;
+    Ok(gltf_scene(resources, &gltf, &gltf_buffers, &path, base_path)?.flatten())
+}
 
-    let mut mesh_parts: Vec<MeshPartData> = Vec::new();
+/// Extensions the loader handles itself, which the glTF crate would reject when required.
+const LOADER_EXTENSIONS: &[&str] = &["KHR_mesh_quantization", "EXT_mesh_gpu_instancing"];
 
-    for node in gltf.nodes() {
-        if let Some(ref mesh) = node.mesh() {
-            let mut node_mesh_parts = load_gltf_mesh(
-                resources,
-                &gltf,
-                &gltf_buffers,
-                &mesh,
-                &path,
-                base_path,
-            )?;
+/// Extensions the loader handles when built with a cargo feature, along with the feature.
+const FEATURE_EXTENSIONS: &[(&str, &str, bool)] = &[
+    ("EXT_meshopt_compression", "meshopt", cfg!(feature = "meshopt")),
+];
 
-            let (part_pos, part_rot, part_scale) = node.transform().decomposed();
+/// Extensions the loader can't decode, along with a way to convert files that require them.
+/// Files that only use them still load from their uncompressed fallback data.
+const UNSUPPORTED_EXTENSIONS: &[(&str, &str)] = &[(
+    "KHR_draco_mesh_compression",
+    "re-compress it with EXT_meshopt_compression, e.g. `gltf-transform meshopt in.glb out.glb`, \
+     and enable the `meshopt` feature",
+)];
 
-            let transform =
-		Mat4::from_translation(Vec3::new(part_pos[0], part_pos[1], part_pos[2])) *
-		    Mat4::from_nonuniform_scale(
-                        Vec3::new(part_scale[0], part_scale[1], part_scale[2])
-                    ) *
-                    Rotor3::new(part_rot[3], Bivec3::new(-part_rot[2], part_rot[1], -part_rot[0]))
-                        .into_matrix().into_homogeneous();
+/// Parse and validate a glTF or GLB file, allowing the required extensions the loader handles.
+fn read_gltf(reader: impl io::Read + io::Seek) -> Result<gltf::Gltf, GltfLoadError> {
+    use gltf::json::validation::{Error, Validate};
 
-            for mesh_part in &mut node_mesh_parts {
-                mesh_part.geometry.transform(transform);
-            }
+    let gltf = gltf::Gltf::from_reader_without_validation(reader)?;
 
-            mesh_parts.extend(node_mesh_parts);
+    for required in gltf.extensions_required() {
+        let unsupported = UNSUPPORTED_EXTENSIONS.iter()
+            .find(|&&(extension, _)| extension == required);
+        if let Some((extension, conversion)) = unsupported {
+            return Err(GltfLoadError::Message(format!(
+                "{} is required, but isn't supported; {}", extension, conversion,
+            )));
+        }
+        let disabled = FEATURE_EXTENSIONS.iter()
+            .find(|&&(extension, _, enabled)| extension == required && !enabled);
+        if let Some((extension, feature, _)) = disabled {
+            return Err(GltfLoadError::Message(format!(
+                "{} is required, which needs the `{}` feature", extension, feature,
+            )));
         }
     }
 
-    Ok(mesh_parts)
+    let root = gltf.as_json();
+    let mut errors = Vec::new();
+    root.validate(root, gltf::json::Path::new, &mut |path, error| {
+        let path = path();
+        let handled = error == Error::Unsupported
+            && path.as_str().starts_with("extensionsRequired")
+            && LOADER_EXTENSIONS.iter()
+                .chain(FEATURE_EXTENSIONS.iter().filter(|e| e.2).map(|e| &e.0))
+                .any(|ext| path.as_str().ends_with(&format!("\"{}\"", ext)));
+        if !handled {
+            errors.push((path, error));
+        }
+    });
+    if !errors.is_empty() {
+        return Err(GltfLoadError::Gltf(gltf::Error::Validation(errors)));
+    }
+
+    Ok(gltf)
 }
 
-pub fn load_gltf_from_reader(
+/// Read a glTF file and load its buffers. Compressed buffer views are decoded into
+/// new buffers, and the document is rewritten to use them in place of the compressed data.
+fn read_gltf_with_buffers(
+    base_path: &Path,
+    reader: impl io::Read + io::Seek,
+) -> Result<(gltf::Gltf, GltfBuffers), GltfLoadError> {
+    let gltf = read_gltf(reader)?;
+    #[allow(unused_mut)]
+    let mut buffers = GltfBuffers::load_from_gltf(base_path, &gltf)?;
+
+    // Without the features, compressed data is only optional and the fallback data is used
+    let compressed = gltf.extensions_used().any(|used| {
+        FEATURE_EXTENSIONS.iter()
+            .any(|&(extension, _, enabled)| extension == used && enabled)
+    });
+    if !compressed {
+        return Ok((gltf, buffers));
+    }
+
+    let gltf::Gltf { document, blob } = gltf;
+    #[allow(unused_mut)]
+    let mut root = document.into_json();
+    #[cfg(feature = "meshopt")]
+    decompress_meshopt(&mut root, &mut buffers, blob.as_deref())?;
+    let document = gltf::Document::from_json_without_validation(root);
+
+    Ok((gltf::Gltf { document, blob }, buffers))
+}
+
+/// Decode the `EXT_meshopt_compression` buffer views.
+#[cfg(feature = "meshopt")]
+fn decompress_meshopt(
+    root: &mut gltf::json::Root,
+    buffers: &mut GltfBuffers,
+    blob: Option<&[u8]>,
+) -> Result<(), GltfLoadError> {
+    use crate::meshopt::{self, Filter};
+
+    for (index, view) in root.buffer_views.iter_mut().enumerate() {
+        let extension = match view.extensions.as_mut()
+            .and_then(|extensions| extensions.others.remove("EXT_meshopt_compression"))
+        {
+            Some(extension) => extension,
+            None => continue,
+        };
+        let error = |err: &str| GltfLoadError::Message(format!(
+            "Compressed buffer view {}: {}", index, err,
+        ));
+        let field = |name| extension.get(name).and_then(|v| v.as_u64()).map(|v| v as usize);
+
+        let (buffer, length, stride, count) = match (
+            field("buffer"), field("byteLength"), field("byteStride"), field("count"),
+        ) {
+            (Some(buffer), Some(length), Some(stride), Some(count)) => {
+                (buffer, length, stride, count)
+            }
+            _ => return Err(error("missing EXT_meshopt_compression properties")),
+        };
+        let offset = field("byteOffset").unwrap_or(0);
+        let source = buffers.data(blob, buffer)
+            .and_then(|data| data.get(offset..offset + length))
+            .ok_or_else(|| error("compressed data is out of bounds"))?;
+
+        let filter = match extension.get("filter").and_then(|v| v.as_str()) {
+            None | Some("NONE") => Filter::None,
+            Some("OCTAHEDRAL") => Filter::Octahedral,
+            Some("QUATERNION") => Filter::Quaternion,
+            Some("EXPONENTIAL") => Filter::Exponential,
+            Some(filter) => return Err(error(&format!("unknown filter {}", filter))),
+        };
+
+        let index_bytes = |indices: Vec<u32>| match stride {
+            2 => Ok(indices.iter().flat_map(|&i| (i as u16).to_le_bytes()).collect()),
+            4 => Ok(indices.iter().flat_map(|&i| i.to_le_bytes()).collect()),
+            _ => Err("index stride must be 2 or 4"),
+        };
+        let data = match extension.get("mode").and_then(|v| v.as_str()) {
+            Some("ATTRIBUTES") => meshopt::decode_vertex_buffer(count, stride, source)
+                .and_then(|mut data| {
+                    meshopt::decode_filter(filter, &mut data, count, stride)?;
+                    Ok(data)
+                }),
+            Some("TRIANGLES") => meshopt::decode_index_buffer(count, source).and_then(index_bytes),
+            Some("INDICES") => meshopt::decode_index_sequence(count, source).and_then(index_bytes),
+            _ => return Err(error("unknown compression mode")),
+        }.map_err(error)?;
+
+        // The view now covers the whole of a new buffer with the decoded data
+        view.buffer = gltf::json::Index::new(root.buffers.len() as u32);
+        view.byte_offset = None;
+        view.byte_length = data.len().into();
+        root.buffers.push(gltf::json::Buffer {
+            byte_length: data.len().into(),
+            name: None,
+            uri: None,
+            extensions: None,
+            extras: Default::default(),
+        });
+        buffers.uri_buffers.push(Some(data));
+    }
+
+    Ok(())
+}
+
+/// Load the node hierarchy of a glTF file's default scene, or its first scene if there's no
+/// default.
+pub fn load_gltf_scene(
     resources: &mut ResourceLoader,
     path: impl AsRef<Path>,
-    r: impl std::io::Read + std::io::Seek,
-) -> Result<Vec<MeshPartData>, GltfLoadError> {
+) -> Result<GltfScene, GltfLoadError> {
     let base_path = path.as_ref().parent().expect("gltf base path");
 
-    let gltf = gltf::Gltf::from_reader(r)?;
+    let file = std::fs::File::open(&path)?;
+    let (gltf, gltf_buffers) = read_gltf_with_buffers(base_path, &file)?;
 
-    let gltf_buffers = GltfBuffers::load_from_gltf(base_path, &gltf)?;
+    gltf_scene(resources, &gltf, &gltf_buffers, &path, base_path)
+}
 
-    let mut mesh_parts: Vec<MeshPartData> = Vec::new();
+fn gltf_scene(
+    resources: &mut ResourceLoader,
+    gltf: &gltf::Gltf,
+    buffers: &GltfBuffers,
+    path: impl AsRef<Path>,
+    base_dir: impl AsRef<Path>,
+) -> Result<GltfScene, GltfLoadError> {
+    let meshes = gltf.meshes()
+        .map(|mesh| load_gltf_mesh(resources, gltf, buffers, &mesh, &path, &base_dir))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    for node in gltf.nodes() {
-        if let Some(ref mesh) = node.mesh() {
-            mesh_parts.extend(load_gltf_mesh(
-                resources,
-                &gltf,
-                &gltf_buffers,
-                &mesh,
-                &path,
-                base_path,
-            )?);
+    let mut nodes = gltf.nodes()
+        .map(|node| {
+            let (translation, rotation, scale) = node.transform().decomposed();
+            GltfNode {
+                name: node.name().map(str::to_owned),
+                translation: translation.into(),
+                rotation: Rotor3::from_quaternion_array(rotation),
+                scale: scale.into(),
+                parent: None,
+                children: node.children().map(|child| child.index()).collect(),
+                mesh: node.mesh().map(|mesh| mesh.index()),
+                skin: node.skin().map(|skin| skin.index()),
+                camera: node.camera().map(|camera| camera.index()),
+                light: node.light().map(|light| light.index()),
+                morph_weights: node.weights()
+                    .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+                    .map_or(Vec::new(), |weights| weights.to_vec()),
+                instances: gltf_instances(gltf, buffers, &node),
+            }
+        })
+        .collect::<Vec<_>>();
+    for index in 0..nodes.len() {
+        for child in nodes[index].children.clone() {
+            nodes[child].parent = Some(index);
         }
     }
 
-    Ok(mesh_parts)
+    let roots = match gltf.default_scene().or_else(|| gltf.scenes().next()) {
+        Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+        None => (0..nodes.len()).filter(|&index| nodes[index].parent.is_none()).collect(),
+    };
+
+    let skins = gltf.skins()
+        .map(|skin| {
+            let inverse_bind_matrices = skin.reader(|buffer| buffers.buffer(gltf, &buffer))
+                .read_inverse_bind_matrices()
+                .map(|matrices| matrices.map(Mat4::from).collect())
+                .unwrap_or_else(|| vec![Mat4::identity(); skin.joints().count()]);
+            GltfSkin {
+                name: skin.name().map(str::to_owned),
+                joints: skin.joints().map(|joint| joint.index()).collect(),
+                inverse_bind_matrices,
+            }
+        })
+        .collect();
+
+    let animations = gltf.animations()
+        .map(|animation| GltfAnimation {
+            name: animation.name().map(str::to_owned),
+            channels: animation.channels()
+                .filter_map(|channel| {
+                    let node = channel.target().node().index();
+                    gltf_keyframes(gltf, buffers, &channel).map(|keyframes| (node, Rc::new(keyframes)))
+                })
+                .collect(),
+        })
+        .collect();
+
+    let cameras = gltf.cameras().map(|camera| gltf_camera(&camera)).collect();
+    let lights = gltf.lights()
+        .map(|lights| lights.map(|light| gltf_light(&light)).collect())
+        .unwrap_or_default();
+
+    Ok(GltfScene { nodes, roots, meshes, skins, animations, cameras, lights })
+}
+
+/// Read the instance transforms of a node's mesh from `EXT_mesh_gpu_instancing`. Attributes the
+/// extension leaves out default to the identity.
+fn gltf_instances(
+    gltf: &gltf::Gltf,
+    buffers: &GltfBuffers,
+    node: &gltf::Node<'_>,
+) -> Option<Vec<MeshInstance>> {
+    node.mesh()?;
+    let attributes = node.extension_value("EXT_mesh_gpu_instancing")?.get("attributes")?;
+    let accessor = |name: &str| {
+        let index = attributes.get(name)?.as_u64()?;
+        gltf.accessors().nth(index as usize)
+    };
+
+    let translations = accessor("TRANSLATION")
+        .and_then(|accessor| read_attribute::<3>(gltf, buffers, accessor).ok());
+    let rotations = accessor("ROTATION")
+        .and_then(|accessor| read_attribute::<4>(gltf, buffers, accessor).ok());
+    let scales = accessor("SCALE")
+        .and_then(|accessor| read_attribute::<3>(gltf, buffers, accessor).ok());
+    let count = [&translations, &scales].iter()
+        .filter_map(|values| values.as_ref().map(Vec::len))
+        .chain(rotations.as_ref().map(Vec::len))
+        .max()?;
+
+    let instances = (0..count)
+        .map(|i| {
+            let mut instance = MeshInstance::default();
+            if let Some(&translation) = translations.as_ref().and_then(|t| t.get(i)) {
+                instance.position = translation.into();
+            }
+            if let Some(&rotation) = rotations.as_ref().and_then(|r| r.get(i)) {
+                instance.rotation = Rotor3::from_quaternion_array(rotation).normalized();
+            }
+            if let Some(&scale) = scales.as_ref().and_then(|s| s.get(i)) {
+                instance.scale = scale.into();
+            }
+            instance
+        })
+        .collect();
+    Some(instances)
+}
+
+fn gltf_camera(camera: &gltf::Camera<'_>) -> GltfCamera {
+    use gltf::camera::Projection as GltfProjection;
+
+    let (projection, aspect_ratio) = match camera.projection() {
+        GltfProjection::Perspective(perspective) => {
+            let projection = Projection::Perspective {
+                fov_y: perspective.yfov(),
+                near: perspective.znear(),
+                far: perspective.zfar(),
+            };
+            (projection, perspective.aspect_ratio())
+        }
+        GltfProjection::Orthographic(orthographic) => {
+            let projection = Projection::Orthographic {
+                y_mag: orthographic.ymag(),
+                near: orthographic.znear(),
+                far: orthographic.zfar(),
+            };
+            (projection, Some(orthographic.xmag() / orthographic.ymag()))
+        }
+    };
+
+    GltfCamera { name: camera.name().map(str::to_owned), projection, aspect_ratio }
+}
+
+fn gltf_light(light: &gltf::khr_lights_punctual::Light<'_>) -> GltfLight {
+    use gltf::khr_lights_punctual::Kind;
+
+    let color = light.color();
+    let intensity = light.intensity();
+    // Lights without a range reach where their inverse square falloff drops below 1/256
+    let range = light.range().unwrap_or_else(|| 16.0 * intensity.sqrt());
+
+    match light.kind() {
+        Kind::Point => GltfLight::Point(PointLight { pos: [0.0; 3], intensity, color }),
+        Kind::Spot { inner_cone_angle, outer_cone_angle } => GltfLight::Spot(SpotLight {
+            pos: [0.0; 3],
+            dir: [0.0, 0.0, -1.0],
+            color,
+            intensity,
+            range,
+            angle: outer_cone_angle.cos(),
+            smoothness: spot_smoothness(inner_cone_angle, outer_cone_angle),
+        }),
+        Kind::Directional => GltfLight::Directional { color, intensity },
+    }
+}
+
+/// The spot light smoothness that gives 90% of a light's intensity at the inner cone angle,
+/// fading to nothing at the outer cone angle.
+fn spot_smoothness(inner_cone_angle: f32, outer_cone_angle: f32) -> f32 {
+    // The shaders attenuate spot lights by 1 - x^(1 - smoothness), where x goes from 0 at the
+    // center of the cone to 1 at its edge
+    let x = (1.0 - inner_cone_angle.cos()) / (1.0 - outer_cone_angle.cos()).max(1e-6);
+    let exponent = 0.1f32.ln() / x.max(1e-4).min(0.999).ln();
+    1.0 - exponent
+}
+
+fn gltf_keyframes(
+    gltf: &gltf::Gltf,
+    buffers: &GltfBuffers,
+    channel: &gltf::animation::Channel<'_>,
+) -> Option<Keyframes> {
+    use gltf::animation::util::ReadOutputs;
+
+    let reader = channel.reader(|buffer| buffers.buffer(gltf, &buffer));
+    let times = reader.read_inputs()?.collect();
+    let (property, values) = match reader.read_outputs()? {
+        ReadOutputs::Translations(values) => {
+            (AnimationProperty::Translation, values.flatten().collect())
+        }
+        ReadOutputs::Rotations(values) => {
+            (AnimationProperty::Rotation, values.into_f32().flatten().collect())
+        }
+        ReadOutputs::Scales(values) => (AnimationProperty::Scale, values.flatten().collect()),
+        ReadOutputs::MorphTargetWeights(values) => {
+            (AnimationProperty::MorphWeights, values.into_f32().collect())
+        }
+    };
+    let interpolation = match channel.sampler().interpolation() {
+        gltf::animation::Interpolation::Step => Interpolation::Step,
+        gltf::animation::Interpolation::Linear => Interpolation::Linear,
+        gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+    };
+
+    Some(Keyframes { property, interpolation, times, values })
+}
+
+/// The node hierarchy of a glTF scene. Nodes are indexed like in the glTF file, and meshes are
+/// loaded once and referenced by index, so nodes sharing a mesh share its data.
+#[derive(Clone)]
+pub struct GltfScene {
+    pub nodes: Vec<GltfNode>,
+    /// The top level nodes of the scene
+    pub roots: Vec<usize>,
+    pub meshes: Vec<Vec<MeshPartData>>,
+    pub skins: Vec<GltfSkin>,
+    pub animations: Vec<GltfAnimation>,
+    pub cameras: Vec<GltfCamera>,
+    pub lights: Vec<GltfLight>,
+}
+
+#[derive(Clone)]
+pub struct GltfCamera {
+    pub name: Option<String>,
+    pub projection: Projection,
+    /// The aspect ratio the camera was authored for, if the file specifies one
+    pub aspect_ratio: Option<f32>,
+}
+
+/// A KHR_lights_punctual light. Lights are positioned by the nodes holding them, and spot lights
+/// point down their node's -Z axis.
+#[derive(Clone)]
+pub enum GltfLight {
+    Point(PointLight),
+    Spot(SpotLight),
+    /// Directional lights aren't supported by the renderer yet, so they aren't instantiated
+    Directional { color: [f32; 3], intensity: f32 },
+}
+
+#[derive(Clone)]
+pub struct GltfAnimation {
+    pub name: Option<String>,
+    /// The keyframes of each channel, along with the node they animate
+    pub channels: Vec<(usize, Rc<Keyframes>)>,
+}
+
+impl GltfAnimation {
+    /// Create a clip animating the nodes of an instance of the scene, as returned by
+    /// `Renderer::instantiate_gltf_scene`. Channels of nodes that weren't instantiated are
+    /// dropped.
+    pub fn clip(&self, node_ids: &[Option<NodeId>]) -> AnimationClip {
+        AnimationClip {
+            name: self.name.clone(),
+            channels: self.channels.iter()
+                .filter_map(|(node, keyframes)| {
+                    node_ids[*node].map(|node| AnimationChannel {
+                        node,
+                        keyframes: keyframes.clone(),
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GltfSkin {
+    pub name: Option<String>,
+    /// The node of each joint
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+#[derive(Clone)]
+pub struct GltfNode {
+    pub name: Option<String>,
+    pub translation: Vec3,
+    pub rotation: Rotor3,
+    pub scale: Vec3,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub mesh: Option<usize>,
+    /// The skin deforming the node's mesh
+    pub skin: Option<usize>,
+    pub camera: Option<usize>,
+    pub light: Option<usize>,
+    /// The initial morph target weights of the node's mesh
+    pub morph_weights: Vec<f32>,
+    /// The placements of the node's mesh from `EXT_mesh_gpu_instancing`, relative to the node.
+    /// Instanced nodes are drawn with an `InstancedMesh`.
+    pub instances: Option<Vec<MeshInstance>>,
+}
+
+impl GltfNode {
+    pub fn local_transform(&self) -> Mat4 {
+        Mat4::from_translation(self.translation) *
+            self.rotation.into_matrix().into_homogeneous() *
+            Mat4::from_nonuniform_scale(self.scale)
+    }
+}
+
+impl GltfScene {
+    pub fn node_by_name(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name.as_deref() == Some(name))
+    }
+
+    /// The transform of a node relative to the scene root.
+    pub fn world_transform(&self, node: usize) -> Mat4 {
+        let local = self.nodes[node].local_transform();
+        match self.nodes[node].parent {
+            Some(parent) => self.world_transform(parent) * local,
+            None => local,
+        }
+    }
+
+    /// Copy every mesh instance in the scene, with its node's world transform baked into the
+    /// vertices. Meshes instanced with `EXT_mesh_gpu_instancing` are copied once per instance.
+    pub fn flatten(&self) -> Vec<MeshPartData> {
+        let mut parts = Vec::new();
+        let mut stack = self.roots.iter().map(|&root| (root, Mat4::identity())).collect::<Vec<_>>();
+        while let Some((index, parent_transform)) = stack.pop() {
+            let node = &self.nodes[index];
+            let transform = parent_transform * node.local_transform();
+            if let Some(mesh) = node.mesh {
+                let instance_transforms = match node.instances {
+                    Some(ref instances) => instances.iter()
+                        .map(|instance| transform * instance.transform())
+                        .collect(),
+                    None => vec![transform],
+                };
+                for &transform in &instance_transforms {
+                    for part in &self.meshes[mesh] {
+                        let mut part = part.clone();
+                        part.geometry.transform(transform);
+                        parts.push(part);
+                    }
+                }
+            }
+            stack.extend(node.children.iter().map(|&child| (child, transform)));
+        }
+        parts
+    }
 }
 
 ////////////////////////////////////////
 
 pub struct GltfBuffers {
+    /// The loaded external and data URI buffers, and buffers of decompressed data. `None` for the
+    /// GLB binary chunk.
     pub uri_buffers: Vec<Option<Vec<u8>>>,
 }
 
@@ -132,17 +614,23 @@ impl GltfBuffers {
         for (_index, buffer) in gltf.buffers().enumerate() {
             let data = match buffer.source() {
                 Source::Uri(uri) => {
-                    if uri.starts_with("data:") {
-                        unimplemented!();
+                    let data = if uri.starts_with("data:") {
+                        decode_data_uri(uri)?
                     } else {
                         let mut file = std::fs::File::open(base_path.as_ref().join(uri))?;
                         let mut data: Vec<u8> = Vec::with_capacity(file.metadata()?.len() as usize);
                         file.read_to_end(&mut data)?;
+                        data
+                    };
 
-                        assert!(data.len() >= buffer.length());
-
-                        Some(data)
+                    if data.len() < buffer.length() {
+                        return Err(GltfLoadError::Message(format!(
+                            "Buffer {} is {} bytes, but its byteLength is {}",
+                            buffer.index(), data.len(), buffer.length(),
+                        )));
                     }
+
+                    Some(data)
                 }
                 Source::Bin => {
                     None
@@ -158,17 +646,14 @@ impl GltfBuffers {
 
     /// Obtain the contents of a loaded buffer.
     pub fn buffer<'a>(&'a self, gltf: &'a gltf::Gltf, buffer: &gltf::Buffer<'_>) -> Option<&'a [u8]> {
-        use gltf::buffer::Source;
+        self.data(gltf.blob.as_deref(), buffer.index())
+    }
 
-        match buffer.source() {
-            Source::Uri(_) => {
-                self.uri_buffers.get(buffer.index())
-                    .map(Option::as_ref).flatten()
-                    .map(Vec::as_slice)
-            }
-            Source::Bin => {
-                gltf.blob.as_ref().map(Vec::as_slice)
-            }
+    fn data<'a>(&'a self, blob: Option<&'a [u8]>, index: usize) -> Option<&'a [u8]> {
+        match self.uri_buffers.get(index) {
+            Some(Some(data)) => Some(data),
+            Some(None) => blob,
+            None => None,
         }
     }
 
@@ -183,6 +668,116 @@ impl GltfBuffers {
     }
 }
 
+/// Read a vertex attribute as floats. Besides floats, this reads the integer component types
+/// `KHR_mesh_quantization` allows, normalized or not. 32 bit integers aren't valid for any vertex
+/// attribute, and are an error.
+fn read_attribute<const N: usize>(
+    gltf: &gltf::Gltf,
+    buffers: &GltfBuffers,
+    accessor: gltf::Accessor<'_>,
+) -> Result<Vec<[f32; N]>, GltfLoadError>
+where
+    [f32; N]: Item,
+    [i8; N]: Item,
+    [u8; N]: Item,
+    [i16; N]: Item,
+    [u16; N]: Item,
+{
+    fn convert<T: Copy + Into<f32>, const N: usize>(
+        gltf: &gltf::Gltf,
+        buffers: &GltfBuffers,
+        accessor: gltf::Accessor<'_>,
+        max: f32,
+    ) -> Vec<[f32; N]>
+    where
+        [T; N]: Item,
+    {
+        let normalized = accessor.normalized();
+        let iter = Iter::<[T; N]>::new(accessor, |buffer| buffers.buffer(gltf, &buffer));
+        iter.into_iter()
+            .flatten()
+            .map(|item| {
+                let mut value = [0.0; N];
+                for (v, &c) in value.iter_mut().zip(item.iter()) {
+                    // Signed values are clamped, as -128 and -32768 would go past -1
+                    *v = if normalized { (c.into() / max).max(-1.0) } else { c.into() };
+                }
+                value
+            })
+            .collect()
+    }
+
+    Ok(match accessor.data_type() {
+        DataType::F32 => Iter::<[f32; N]>::new(accessor, |buffer| buffers.buffer(gltf, &buffer))
+            .into_iter()
+            .flatten()
+            .collect(),
+        DataType::I8 => convert::<i8, N>(gltf, buffers, accessor, i8::MAX as f32),
+        DataType::U8 => convert::<u8, N>(gltf, buffers, accessor, u8::MAX as f32),
+        DataType::I16 => convert::<i16, N>(gltf, buffers, accessor, i16::MAX as f32),
+        DataType::U16 => convert::<u16, N>(gltf, buffers, accessor, u16::MAX as f32),
+        DataType::U32 => return Err(GltfLoadError::Message(format!(
+            "Accessor {} has unsigned int components, which vertex attributes can't use",
+            accessor.index(),
+        ))),
+    })
+}
+
+/// Convert the indices of a primitive to a list topology. Strips and fans become triangle lists,
+/// and line strips and loops become line lists.
+fn primitive_lists(mode: gltf::mesh::Mode, indices: Vec<u32>) -> (Topology, Vec<u32>) {
+    use gltf::mesh::Mode;
+
+    match mode {
+        Mode::Triangles => (Topology::Triangles, indices),
+        Mode::TriangleStrip => {
+            // Every other triangle is flipped to keep the winding consistent
+            let triangles = (0..indices.len().saturating_sub(2))
+                .flat_map(|i| match i % 2 {
+                    0 => [indices[i], indices[i + 1], indices[i + 2]],
+                    _ => [indices[i + 1], indices[i], indices[i + 2]],
+                })
+                .collect();
+            (Topology::Triangles, triangles)
+        }
+        Mode::TriangleFan => {
+            let triangles = (1..indices.len().saturating_sub(1))
+                .flat_map(|i| [indices[i], indices[i + 1], indices[0]])
+                .collect();
+            (Topology::Triangles, triangles)
+        }
+        Mode::Lines => (Topology::Lines, indices),
+        Mode::LineStrip => {
+            let lines = indices.windows(2).flatten().copied().collect();
+            (Topology::Lines, lines)
+        }
+        Mode::LineLoop => {
+            let closing = match (indices.last(), indices.first()) {
+                (Some(&last), Some(&first)) if indices.len() > 2 => vec![last, first],
+                _ => Vec::new(),
+            };
+            let lines = indices.windows(2).flatten().copied().chain(closing).collect();
+            (Topology::Lines, lines)
+        }
+        Mode::Points => (Topology::Points, indices),
+    }
+}
+
+/// Decode the contents of a base64 `data:` URI, e.g.
+/// `data:application/octet-stream;base64,AAAA`. The media type is ignored.
+fn decode_data_uri(uri: &str) -> Result<Vec<u8>, GltfLoadError> {
+    let (header, data) = uri.find(',')
+        .map(|comma| (&uri[..comma], &uri[comma + 1..]))
+        .ok_or_else(|| GltfLoadError::Message("Malformed data URI".into()))?;
+
+    if !header.ends_with(";base64") {
+        return Err(GltfLoadError::Message("Data URI is not base64 encoded".into()));
+    }
+
+    base64::decode(data)
+        .map_err(|err| GltfLoadError::Message(format!("Invalid base64 in data URI: {}", err)))
+}
+
 ////////////////////////////////////////
 
 pub fn load_gltf_mesh(
@@ -198,60 +793,176 @@ pub fn load_gltf_mesh(
     for primitive in mesh.primitives() {
         let reader = primitive.reader(|buf_id| buffers.buffer(gltf, &buf_id));
 
-        let indices = reader
-            .read_indices()
-            .ok_or(GltfLoadError::Message(format!("Mesh primitive does not contain indices")))?
-            .into_u32()
-            .collect::<Vec<u32>>();
-
-        let positions = reader
-            .read_positions()
-            .ok_or(GltfLoadError::Message(format!("Primitive does not have positions")))?;
-        let normals = reader
-            .read_normals()
-            .ok_or(GltfLoadError::Message(format!("Primitive does not have normals")))?;
-        let uvs = reader
-            .read_tex_coords(0)
-            .ok_or(GltfLoadError::Message(format!("Primitive does not have tex coords")))?
-            .into_f32();
-        let tangents = reader.read_tangents();
-
-        let geometry =
-            if let Some(tangents) = tangents {
-                let vertices = positions
-                    .zip(normals.zip(tangents.zip(uvs)))
-                    .map(|(pos, (norm, (tang, uv)))| Vertex {
-                        pos: pos.into(),
-                        norm: norm.into(),
-                        tang: tang.into(),
-                        tex_coord: uv.into(),
-                    })
-                    .collect::<Vec<_>>();
+        let positions = match primitive.get(&Semantic::Positions) {
+            Some(accessor) => read_attribute(gltf, buffers, accessor)?,
+            None => return Err(GltfLoadError::Message("Primitive does not have positions".into())),
+        };
+        // Non-indexed primitives use their vertices in order
+        let indices = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect::<Vec<u32>>(),
+            None => (0..positions.len() as u32).collect(),
+        };
+        let (topology, indices) = primitive_lists(primitive.mode(), indices);
+        let triangles = topology == Topology::Triangles;
+        let normals = primitive.get(&Semantic::Normals)
+            .map(|a| read_attribute(gltf, buffers, a)).transpose()?;
+        let uvs = primitive.get(&Semantic::TexCoords(0))
+            .map(|a| read_attribute(gltf, buffers, a)).transpose()?;
+        let tangents = primitive.get(&Semantic::Tangents)
+            .map(|a| read_attribute(gltf, buffers, a)).transpose()?;
+        let colors = reader.read_colors(0).map(|colors| colors.into_rgba_f32().collect::<Vec<_>>());
+        let uvs1 = primitive.get(&Semantic::TexCoords(1))
+            .map(|a| read_attribute(gltf, buffers, a)).transpose()?;
+
+        // Keep KHR_mesh_quantization primitives compact on the GPU
+        let quantized = [
+            Semantic::Positions, Semantic::Normals, Semantic::Tangents, Semantic::TexCoords(0),
+        ].iter().any(|semantic| {
+            primitive.get(semantic).map_or(false, |accessor| accessor.data_type() != DataType::F32)
+        });
 
-                MeshPartGeometry {
-                    vertices,
-                    indices,
+        let primitive_name = || format!(
+            "{}: primitive {} of mesh {}",
+            path.as_ref().display(),
+            primitive.index(),
+            mesh.name().map_or(mesh.index().to_string(), str::to_owned),
+        );
+        // Every attribute needs a value for each vertex
+        let attribute_lens = [
+            ("NORMAL", normals.as_ref().map(Vec::len)),
+            ("TANGENT", tangents.as_ref().map(Vec::len)),
+            ("TEXCOORD_0", uvs.as_ref().map(Vec::len)),
+            ("TEXCOORD_1", uvs1.as_ref().map(Vec::len)),
+            ("COLOR_0", colors.as_ref().map(Vec::len)),
+        ];
+        for &(name, len) in &attribute_lens {
+            match len {
+                Some(len) if len != positions.len() => {
+                    return Err(GltfLoadError::Message(format!(
+                        "{} has {} {} values for {} vertices",
+                        primitive_name(), len, name, positions.len(),
+                    )));
                 }
-            } else {
-                let vertices = positions
-                    .zip(normals.zip(uvs))
-                    .map(|(pos, (norm, uv))| Vertex {
-                        pos: pos.into(),
-                        norm: norm.into(),
-                        tang: [0.0, 0.0, 0.0, 0.0],
-                        tex_coord: uv.into(),
+                _ => { }
+            }
+        }
+
+        // Lines and points are drawn unlit, and often have neither
+        if normals.is_none() && triangles {
+            log::warn!("{} has no normals, generating flat normals", primitive_name());
+        }
+        if uvs.is_none() && triangles {
+            log::warn!("{} has no texture coordinates, textures won't map", primitive_name());
+        }
+
+        let vertices = (0..positions.len())
+            .map(|i| Vertex {
+                pos: positions[i],
+                norm: normals.as_ref().map_or([0.0; 3], |normals| normals[i]),
+                tang: tangents.as_ref().map_or([0.0; 4], |tangents| tangents[i]),
+                tex_coord: uvs.as_ref().map_or([0.0; 2], |uvs| uvs[i]),
+            })
+            .collect::<Vec<_>>();
+
+        let skin = match (reader.read_joints(0), reader.read_weights(0)) {
+            (Some(joints), Some(weights)) => Some(
+                joints.into_u16()
+                    .zip(weights.into_f32())
+                    .map(|(joints, weights)| SkinVertex { joints, weights })
+                    .collect::<Vec<_>>()
+            ),
+            _ => None,
+        };
+        if let Some(len) = skin.as_ref().map(Vec::len).filter(|&len| len != positions.len()) {
+            return Err(GltfLoadError::Message(format!(
+                "{} has {} JOINTS_0 and WEIGHTS_0 values for {} vertices",
+                primitive_name(), len, positions.len(),
+            )));
+        }
+        let max_joint = skin.iter().flatten().flat_map(|vertex| vertex.joints.iter()).max();
+        if let Some(&max_joint) = max_joint.filter(|&&joint| joint as usize >= MAX_JOINTS) {
+            return Err(GltfLoadError::Message(format!(
+                "{} uses joint {}, skinned meshes support up to {} joints",
+                primitive_name(), max_joint, MAX_JOINTS,
+            )));
+        }
+
+        let material = primitive.material();
+        let uv_sets = gltf_texture_uv_sets(gltf, &material);
+        let uses_uv1 = TextureSlot::ALL.iter().any(|&slot| *uv_sets.get(slot) == 1);
+        if uses_uv1 && uvs1.is_none() {
+            log::warn!("{} has no TEXCOORD_1, its material's texture set 1 uses TEXCOORD_0", primitive_name());
+        }
+
+        // Textures using set 1 fall back to the first set when it's missing
+        let extra_attributes = if colors.is_some() || uvs1.is_some() || uses_uv1 {
+            Some(
+                (0..positions.len())
+                    .map(|i| ExtraVertex {
+                        color: colors.as_ref().map_or([1.0; 4], |colors| colors[i]),
+                        tex_coord1: match (&uvs1, &uvs) {
+                            (Some(uvs1), _) => uvs1[i],
+                            (None, Some(uvs)) => uvs[i],
+                            (None, None) => [0.0; 2],
+                        },
                     })
-                    .collect::<Vec<_>>();
-                let mut geometry = MeshPartGeometry {
-                    vertices,
-                    indices,
-                };
-                compute_tangents(&mut geometry);
+                    .collect::<Vec<_>>()
+            )
+        } else {
+            None
+        };
 
-                geometry
+        let mut geometry = MeshPartGeometry {
+            vertices,
+            indices,
+            topology,
+            vertex_format: if quantized { VertexFormat::Compact } else { VertexFormat::Full },
+            skin,
+            extra_attributes,
+            morph_targets: Vec::new(),
+        };
+
+        // Missing attributes of a target don't move the vertices
+        for target in primitive.morph_targets() {
+            let vertex_count = geometry.vertices.len();
+            let read = |accessor: Option<gltf::Accessor<'_>>| {
+                let values = accessor.map_or(Ok(Vec::new()), |accessor| {
+                    read_attribute(gltf, buffers, accessor)
+                })?;
+                if !values.is_empty() && values.len() != vertex_count {
+                    return Err(GltfLoadError::Message(format!(
+                        "{} has a morph target with {} deltas for {} vertices",
+                        primitive_name(), values.len(), vertex_count,
+                    )));
+                }
+                Ok(values)
             };
+            let mut deltas = vec![MorphVertex::default(); vertex_count];
+            for (delta, pos) in deltas.iter_mut().zip(read(target.positions())?) {
+                delta.pos = pos;
+            }
+            for (delta, norm) in deltas.iter_mut().zip(read(target.normals())?) {
+                delta.norm = norm;
+            }
+            for (delta, tang) in deltas.iter_mut().zip(read(target.tangents())?) {
+                delta.tang = tang;
+            }
+            geometry.morph_targets.push(deltas);
+        }
 
-        let material = primitive.material();
+        if normals.is_none() && triangles {
+            geometry.compute_flat_normals();
+        }
+        match (&tangents, &uvs) {
+            (Some(_), _) => { }
+            _ if !triangles => { }
+            (None, Some(_)) => compute_tangents(&mut geometry),
+            // Tangents can't be derived without texture coordinates, and normal mapping needs
+            // them anyway. The lit vertex shaders still normalize the tangent, so it can't be
+            // left zero. Any tangent perpendicular to the normal shades the same with the flat
+            // normal map such a material ends up sampling.
+            (None, None) => geometry.compute_orthogonal_tangents(),
+        }
 
         let pbr_met_rough = material.pbr_metallic_roughness();
 
@@ -262,9 +973,9 @@ pub fn load_gltf_mesh(
                 &path,
                 &base_dir,
                 t.texture(),
-                true,
+                TextureOptions::srgb(),
             )
-        });
+        }).transpose()?;
 
         let metallic_roughness = pbr_met_rough.metallic_roughness_texture().map(|t| {
             load_gltf_texture(
@@ -273,9 +984,9 @@ pub fn load_gltf_mesh(
                 &path,
                 &base_dir,
                 t.texture(),
-                false,
+                TextureOptions::metallic_roughness(),
             )
-        });
+        }).transpose()?;
             
         let normal = material.normal_texture().map(|t| {
             load_gltf_texture(
@@ -284,9 +995,9 @@ pub fn load_gltf_mesh(
                 &path,
                 &base_dir,
                 t.texture(),
-                false,
+                TextureOptions::normal_map(),
             )
-        });
+        }).transpose()?;
 
         let ao = material.occlusion_texture().map(|t| {
             load_gltf_texture(
@@ -295,37 +1006,112 @@ pub fn load_gltf_mesh(
                 &path,
                 &base_dir,
                 t.texture(),
-                false,
+                TextureOptions::linear(),
             )
-        });
+        }).transpose()?;
 
         let emissive = material.emissive_texture().map(|emissive_info| {
-            load_gltf_texture(resources, gltf, buffers, &path, &base_dir, emissive_info.texture(), true)
-        });
+            load_gltf_texture(
+                resources,
+                gltf, buffers,
+                &path,
+                &base_dir,
+                emissive_info.texture(),
+                TextureOptions::srgb(),
+            )
+        }).transpose()?;
+
+        let texture_transforms = TextureTransforms {
+            texture: pbr_met_rough.base_color_texture()
+                .and_then(|t| t.texture_transform())
+                .map(gltf_texture_transform)
+                .unwrap_or_default(),
+            normal: material.normal_texture()
+                .map(|t| gltf_texture_transform_extension(t.extension_value("KHR_texture_transform")))
+                .unwrap_or_default(),
+            metallic_roughness: pbr_met_rough.metallic_roughness_texture()
+                .and_then(|t| t.texture_transform())
+                .map(gltf_texture_transform)
+                .unwrap_or_default(),
+            ao: material.occlusion_texture()
+                .map(|t| gltf_texture_transform_extension(t.extension_value("KHR_texture_transform")))
+                .unwrap_or_default(),
+            emissive: material.emissive_texture()
+                .and_then(|t| t.texture_transform())
+                .map(gltf_texture_transform)
+                .unwrap_or_default(),
+            ..Default::default()
+        };
+
+        let sampler = |t: &Option<(Rc<Texture>, SamplerData)>| {
+            t.as_ref().map(|(_, sampler)| *sampler).unwrap_or_default()
+        };
+        let samplers = TextureSamplers {
+            texture: sampler(&albedo),
+            normal: sampler(&normal),
+            metallic_roughness: sampler(&metallic_roughness),
+            ao: sampler(&ao),
+            emissive: sampler(&emissive),
+            ..Default::default()
+        };
+
+        let mut material_data = MaterialData {
+            factors: MaterialFactors {
+                diffuse: pbr_met_rough.base_color_factor(),
+                metal: pbr_met_rough.metallic_factor(),
+                rough: pbr_met_rough.roughness_factor(),
+                emissive: material.emissive_factor(),
+                extra_emissive: [0.0, 0.0, 0.0],
+                ..gltf_layer_factors(&material)
+            },
+            lighting: true,
+            texture: albedo.map(|(t, _)| t),
+            normal: normal.map(|(t, _)| t),
+            metallic_roughness: metallic_roughness.map(|(t, _)| t),
+            ao: ao.map(|(t, _)| t),
+            emissive: emissive.map(|(t, _)| t),
+            clearcoat: None,
+            clearcoat_roughness: None,
+            clearcoat_normal: None,
+            sheen_color: None,
+            sheen_roughness: None,
+            specular: None,
+            specular_color: None,
+            transmission: None,
+            thickness: None,
+            texture_transforms,
+            samplers,
+            texture_uv_sets: uv_sets,
+        };
+
+        for &(slot, extension, name, options) in LAYER_TEXTURES {
+            if let Some(info) = gltf_extension_texture(gltf, &material, extension, name) {
+                let (texture, sampler) = load_gltf_texture(
+                    resources,
+                    gltf, buffers,
+                    &path,
+                    &base_dir,
+                    info.texture,
+                    options(),
+                )?;
+                *material_data.texture_mut(slot) = Some(texture);
+                *material_data.samplers.get_mut(slot) = sampler;
+                *material_data.texture_transforms.get_mut(slot) = info.transform;
+            }
+        }
 
         mesh_parts.push(MeshPartData {
             geometry,
-            material: MaterialData {
-                factors: MaterialFactors {
-                    diffuse: pbr_met_rough.base_color_factor(),
-                    metal: pbr_met_rough.metallic_factor(),
-                    rough: pbr_met_rough.roughness_factor(),
-                    emissive: material.emissive_factor(),
-                    extra_emissive: [0.0, 0.0, 0.0],
-                },
-                lighting: true,
-                texture: albedo,
-                normal: normal,
-                metallic_roughness: metallic_roughness,
-                ao: ao,
-                emissive: emissive,
-            },
+            material: material_data,
         })
     }
 
     Ok(mesh_parts)
 }
 
+/// Load a texture along with the description of its sampler. The KTX2 image from
+/// `KHR_texture_basisu` is preferred, but the texture's regular source is used when it can't be
+/// loaded on this device.
 fn load_gltf_texture(
     resources: &mut ResourceLoader,
     gltf: &gltf::Gltf,
@@ -333,18 +1119,315 @@ fn load_gltf_texture(
     path: impl AsRef<Path>,
     base_dir: impl AsRef<Path>,
     texture: gltf::Texture<'_>,
-    srgba: bool,
-) -> Rc<wgpu::Texture> {
-    match texture.source().source() {
+    options: TextureOptions,
+) -> Result<(Rc<Texture>, SamplerData), GltfLoadError> {
+    let sampler = gltf_sampler(texture.sampler());
+    // Don't bother generating mips the sampler never reads
+    let options = if sampler.mipmaps { options } else { options.without_mipmaps() };
+
+    let basisu_image = texture.extension_value("KHR_texture_basisu")
+        .and_then(|ext| ext.get("source"))
+        .and_then(|source| source.as_u64())
+        .and_then(|index| gltf.images().nth(index as usize));
+    if let Some(image) = basisu_image {
+        let texture_name = base_dir.as_ref().join(&path).join(format!("texture{}-basisu", texture.index()));
+        match load_gltf_image(resources, gltf, buffers, &base_dir, image, texture_name, options) {
+            Ok(gpu_texture) => return Ok((gpu_texture, sampler)),
+            Err(err) if texture.source().is_some() => {
+                log::warn!("texture {}: {}, using its fallback image", texture.index(), err);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    let image = texture.source()
+        .ok_or_else(|| GltfLoadError::Message(format!(
+            "Texture {} has no supported image source", texture.index(),
+        )))?;
+    let texture_name = base_dir.as_ref().join(&path).join(format!("texture{}", texture.index()));
+    let gpu_texture = load_gltf_image(
+        resources, gltf, buffers, &base_dir, image, texture_name, options,
+    )?;
+
+    Ok((gpu_texture, sampler))
+}
+
+/// Upload an image. Embedded images are cached under `texture_name`, external ones under their
+/// path.
+fn load_gltf_image(
+    resources: &mut ResourceLoader,
+    gltf: &gltf::Gltf,
+    buffers: &GltfBuffers,
+    base_dir: impl AsRef<Path>,
+    image: gltf::Image<'_>,
+    texture_name: PathBuf,
+    options: TextureOptions,
+) -> Result<Rc<Texture>, GltfLoadError> {
+    let gpu_texture = match image.source() {
         gltf::image::Source::View { ref view, mime_type: _ } => {
-            let texture_name = base_dir.as_ref().join(path).join(format!("texture{}", texture.index()));
-            let bytes = buffers.view(gltf, view).expect("texture view bytes");
-            resources.texture_from_bytes(texture_name, bytes, srgba)
+            let bytes = buffers.view(gltf, view).ok_or_else(|| GltfLoadError::Message(format!(
+                "Image {} refers to a missing buffer", image.index(),
+            )))?;
+            resources.texture_from_bytes(texture_name, bytes, options)?
+        }
+        gltf::image::Source::Uri { uri, .. } if uri.starts_with("data:") => {
+            let bytes = decode_data_uri(uri)?;
+            resources.texture_from_bytes(texture_name, &bytes, options)?
         }
         gltf::image::Source::Uri { uri, .. } => {
-            resources.load_texture(base_dir.as_ref().join(uri), srgba)
+            resources.load_texture(base_dir.as_ref().join(uri), options)?
+        }
+    };
+
+    Ok(gpu_texture)
+}
+
+/// List the external image files used by a glTF file's materials, along with how each is used.
+/// Embedded images and KTX2 images from `KHR_texture_basisu` aren't included.
+pub fn gltf_texture_paths(
+    path: impl AsRef<Path>,
+) -> Result<Vec<(PathBuf, TextureOptions)>, GltfLoadError> {
+    let base_path = path.as_ref().parent().expect("gltf base path");
+
+    let file = std::fs::File::open(&path)?;
+    let gltf = read_gltf(&file)?;
+
+    let mut textures = Vec::new();
+    for material in gltf.materials() {
+        let pbr_met_rough = material.pbr_metallic_roughness();
+        let slots = [
+            (pbr_met_rough.base_color_texture().map(|t| t.texture()), TextureOptions::srgb()),
+            (material.normal_texture().map(|t| t.texture()), TextureOptions::normal_map()),
+            (
+                pbr_met_rough.metallic_roughness_texture().map(|t| t.texture()),
+                TextureOptions::metallic_roughness(),
+            ),
+            (material.occlusion_texture().map(|t| t.texture()), TextureOptions::linear()),
+            (material.emissive_texture().map(|t| t.texture()), TextureOptions::srgb()),
+        ];
+        let layers = LAYER_TEXTURES.iter().map(|&(_, extension, name, options)| {
+            let info = gltf_extension_texture(&gltf, &material, extension, name);
+            (info.map(|info| info.texture), options())
+        });
+
+        for (texture, options) in slots.iter().cloned().chain(layers) {
+            let image = match texture.as_ref().and_then(|t| t.source()) {
+                Some(image) => image,
+                None => continue,
+            };
+            if let gltf::image::Source::Uri { uri, .. } = image.source() {
+                if !uri.starts_with("data:") {
+                    textures.push((base_path.join(uri), options));
+                }
+            }
         }
     }
+
+    Ok(textures)
+}
+
+fn gltf_sampler(sampler: gltf::texture::Sampler<'_>) -> SamplerData {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+    let address_mode = |mode| match mode {
+        WrappingMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        WrappingMode::MirroredRepeat => wgpu::AddressMode::MirrorRepeat,
+        WrappingMode::Repeat => wgpu::AddressMode::Repeat,
+    };
+
+    let mag_filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => wgpu::FilterMode::Nearest,
+        Some(MagFilter::Linear) | None => wgpu::FilterMode::Linear,
+    };
+
+    // (min filter, mipmap filter, whether mipmaps are sampled at all)
+    let (min_filter, mipmap_filter, mipmaps) = match sampler.min_filter() {
+        Some(MinFilter::Nearest) => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest, false),
+        Some(MinFilter::Linear) => (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest, false),
+        Some(MinFilter::NearestMipmapNearest) =>
+            (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest, true),
+        Some(MinFilter::LinearMipmapNearest) =>
+            (wgpu::FilterMode::Linear, wgpu::FilterMode::Nearest, true),
+        Some(MinFilter::NearestMipmapLinear) =>
+            (wgpu::FilterMode::Nearest, wgpu::FilterMode::Linear, true),
+        Some(MinFilter::LinearMipmapLinear) | None =>
+            (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear, true),
+    };
+
+    SamplerData {
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+        mag_filter,
+        min_filter,
+        mipmap_filter,
+        mipmaps,
+    }
+}
+
+fn gltf_texture_transform(transform: gltf::texture::TextureTransform<'_>) -> TextureTransform {
+    TextureTransform {
+        offset: transform.offset(),
+        rotation: transform.rotation(),
+        scale: transform.scale(),
+    }
+}
+
+/// The UV set each texture of a material samples with. `KHR_texture_transform` can override the
+/// set of a texture. Only the first two sets are supported.
+fn gltf_texture_uv_sets(gltf: &gltf::Gltf, material: &gltf::Material<'_>) -> TextureSlots<u32> {
+    let info = |info: Option<gltf::texture::Info<'_>>| info.map_or(0, |info| {
+        gltf_uv_set(info.tex_coord(), info.extension_value("KHR_texture_transform"))
+    });
+    let pbr_met_rough = material.pbr_metallic_roughness();
+    let mut uv_sets = TextureSlots {
+        texture: info(pbr_met_rough.base_color_texture()),
+        normal: material.normal_texture().map_or(0, |t| {
+            gltf_uv_set(t.tex_coord(), t.extension_value("KHR_texture_transform"))
+        }),
+        metallic_roughness: info(pbr_met_rough.metallic_roughness_texture()),
+        ao: material.occlusion_texture().map_or(0, |t| {
+            gltf_uv_set(t.tex_coord(), t.extension_value("KHR_texture_transform"))
+        }),
+        emissive: info(material.emissive_texture()),
+        ..Default::default()
+    };
+    for &(slot, extension, name, _) in LAYER_TEXTURES {
+        if let Some(info) = gltf_extension_texture(gltf, material, extension, name) {
+            *uv_sets.get_mut(slot) = info.uv_set;
+        }
+    }
+    uv_sets
+}
+
+fn gltf_uv_set(tex_coord: u32, transform: Option<&gltf::json::Value>) -> u32 {
+    let tex_coord = transform
+        .and_then(|transform| transform.get("texCoord"))
+        .and_then(|tex_coord| tex_coord.as_u64())
+        .map_or(tex_coord, |tex_coord| tex_coord as u32);
+    if tex_coord > 1 {
+        log::warn!("texture coordinate set {} is not supported, using set 0", tex_coord);
+        return 0;
+    }
+    tex_coord
+}
+
+/// The textures of the clearcoat, sheen, specular, transmission and volume extensions, as
+/// (slot, extension, texture info name, how the texture is loaded).
+const LAYER_TEXTURES: &[(TextureSlot, &str, &str, fn() -> TextureOptions)] = &[
+    (TextureSlot::Clearcoat, "KHR_materials_clearcoat", "clearcoatTexture", TextureOptions::linear),
+    (
+        TextureSlot::ClearcoatRoughness, "KHR_materials_clearcoat", "clearcoatRoughnessTexture",
+        TextureOptions::linear,
+    ),
+    (
+        TextureSlot::ClearcoatNormal, "KHR_materials_clearcoat", "clearcoatNormalTexture",
+        TextureOptions::normal_map,
+    ),
+    (TextureSlot::SheenColor, "KHR_materials_sheen", "sheenColorTexture", TextureOptions::srgb),
+    (
+        TextureSlot::SheenRoughness, "KHR_materials_sheen", "sheenRoughnessTexture",
+        TextureOptions::linear,
+    ),
+    (TextureSlot::Specular, "KHR_materials_specular", "specularTexture", TextureOptions::linear),
+    (
+        TextureSlot::SpecularColor, "KHR_materials_specular", "specularColorTexture",
+        TextureOptions::srgb,
+    ),
+    (
+        TextureSlot::Transmission, "KHR_materials_transmission", "transmissionTexture",
+        TextureOptions::linear,
+    ),
+    (TextureSlot::Thickness, "KHR_materials_volume", "thicknessTexture", TextureOptions::linear),
+];
+
+/// A texture info of a material extension the `gltf` crate doesn't parse.
+struct ExtensionTextureInfo<'a> {
+    texture: gltf::Texture<'a>,
+    uv_set: u32,
+    transform: TextureTransform,
+}
+
+fn gltf_extension_texture<'a>(
+    gltf: &'a gltf::Gltf,
+    material: &gltf::Material<'_>,
+    extension: &str,
+    name: &str,
+) -> Option<ExtensionTextureInfo<'a>> {
+    let info = material.extension_value(extension)?.get(name)?;
+    let texture = gltf.textures().nth(info.get("index")?.as_u64()? as usize)?;
+    let tex_coord = info.get("texCoord").and_then(|t| t.as_u64()).unwrap_or(0) as u32;
+    let transform = info.get("extensions").and_then(|e| e.get("KHR_texture_transform"));
+    Some(ExtensionTextureInfo {
+        texture,
+        uv_set: gltf_uv_set(tex_coord, transform),
+        transform: gltf_texture_transform_extension(transform),
+    })
+}
+
+/// The clearcoat, sheen, specular, IOR, transmission and volume factors of a material. The other
+/// factors are left at their defaults.
+fn gltf_layer_factors(material: &gltf::Material<'_>) -> MaterialFactors {
+    let defaults = MaterialFactors::default();
+    let factor = |extension: &str, name: &str, default: f32| {
+        material.extension_value(extension)
+            .and_then(|extension| extension.get(name))
+            .and_then(|factor| factor.as_f64())
+            .map_or(default, |factor| factor as f32)
+    };
+    let color = |extension: &str, name: &str, default: [f32; 3]| {
+        let mut color = default;
+        if let Some(values) = material.extension_value(extension)
+            .and_then(|extension| extension.get(name))
+            .and_then(|values| values.as_array())
+        {
+            for (c, value) in color.iter_mut().zip(values) {
+                *c = value.as_f64().map_or(*c, |value| value as f32);
+            }
+        }
+        color
+    };
+
+    MaterialFactors {
+        clearcoat: factor("KHR_materials_clearcoat", "clearcoatFactor", defaults.clearcoat),
+        clearcoat_rough: factor(
+            "KHR_materials_clearcoat", "clearcoatRoughnessFactor", defaults.clearcoat_rough,
+        ),
+        sheen_color: color("KHR_materials_sheen", "sheenColorFactor", defaults.sheen_color),
+        sheen_rough: factor("KHR_materials_sheen", "sheenRoughnessFactor", defaults.sheen_rough),
+        specular: factor("KHR_materials_specular", "specularFactor", defaults.specular),
+        specular_color: color(
+            "KHR_materials_specular", "specularColorFactor", defaults.specular_color,
+        ),
+        ior: factor("KHR_materials_ior", "ior", defaults.ior),
+        transmission: factor(
+            "KHR_materials_transmission", "transmissionFactor", defaults.transmission,
+        ),
+        thickness: factor("KHR_materials_volume", "thicknessFactor", defaults.thickness),
+        attenuation_color: color(
+            "KHR_materials_volume", "attenuationColor", defaults.attenuation_color,
+        ),
+        attenuation_distance: factor(
+            "KHR_materials_volume", "attenuationDistance", defaults.attenuation_distance,
+        ),
+        ..defaults
+    }
+}
+
+/// Normal and occlusion texture infos don't expose `KHR_texture_transform` directly, so it's
+/// parsed from the raw extension value.
+fn gltf_texture_transform_extension(value: Option<&gltf::json::Value>) -> TextureTransform {
+    use gltf::json::extensions::texture::TextureTransform as JsonTextureTransform;
+
+    value
+        .and_then(|value| {
+            gltf::json::deserialize::from_value::<JsonTextureTransform>(value.clone()).ok()
+        })
+        .map(|transform| TextureTransform {
+            offset: transform.offset.0,
+            rotation: transform.rotation.0,
+            scale: transform.scale.0,
+        })
+        .unwrap_or_default()
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -353,6 +1436,7 @@ fn load_gltf_texture(
 pub enum GltfLoadError {
     Io(std::io::Error),
     Gltf(gltf::Error),
+    Texture(TextureLoadError),
     Message(String),
 }
 
@@ -361,6 +1445,7 @@ impl std::error::Error for GltfLoadError {
         match *self {
             GltfLoadError::Io(ref err) => Some(err),
             GltfLoadError::Gltf(ref err) => Some(err),
+            GltfLoadError::Texture(ref err) => Some(err),
             GltfLoadError::Message(_) => None,
         }
     }
@@ -371,6 +1456,7 @@ impl std::fmt::Display for GltfLoadError {
         match *self {
             GltfLoadError::Io(ref err) => write!(f, "GLTF load IO error: {}", err),
             GltfLoadError::Gltf(ref err) => write!(f, "GLTF load parse error: {}", err),
+            GltfLoadError::Texture(ref err) => write!(f, "GLTF load texture error: {}", err),
             GltfLoadError::Message(ref err) => write!(f, "GLTF load error: {}", err),
         }
     }
@@ -387,3 +1473,77 @@ impl From<gltf::Error> for GltfLoadError {
         GltfLoadError::Gltf(err)
     }
 }
+
+impl From<TextureLoadError> for GltfLoadError {
+    fn from(err: TextureLoadError) -> GltfLoadError {
+        GltfLoadError::Texture(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gltf::mesh::Mode;
+
+    #[test]
+    fn triangle_strips_alternate_winding() {
+        let (topology, indices) = primitive_lists(Mode::TriangleStrip, vec![0, 1, 2, 3, 4]);
+        assert_eq!(topology, Topology::Triangles);
+        // Odd triangles swap their first two vertices, keeping every triangle counter-clockwise
+        assert_eq!(indices, vec![0, 1, 2, 2, 1, 3, 2, 3, 4]);
+
+        assert!(primitive_lists(Mode::TriangleStrip, vec![0, 1]).1.is_empty());
+        assert!(primitive_lists(Mode::TriangleStrip, Vec::new()).1.is_empty());
+    }
+
+    #[test]
+    fn triangle_fans_share_the_first_vertex() {
+        let (topology, indices) = primitive_lists(Mode::TriangleFan, vec![0, 1, 2, 3, 4]);
+        assert_eq!(topology, Topology::Triangles);
+        assert_eq!(indices, vec![1, 2, 0, 2, 3, 0, 3, 4, 0]);
+
+        assert!(primitive_lists(Mode::TriangleFan, vec![0, 1]).1.is_empty());
+        assert!(primitive_lists(Mode::TriangleFan, Vec::new()).1.is_empty());
+    }
+
+    #[test]
+    fn line_strips_and_loops() {
+        let (topology, indices) = primitive_lists(Mode::LineStrip, vec![0, 1, 2]);
+        assert_eq!(topology, Topology::Lines);
+        assert_eq!(indices, vec![0, 1, 1, 2]);
+
+        let (topology, indices) = primitive_lists(Mode::LineLoop, vec![0, 1, 2]);
+        assert_eq!(topology, Topology::Lines);
+        assert_eq!(indices, vec![0, 1, 1, 2, 2, 0]);
+
+        // A loop of two indices is a single line, not the same line twice
+        assert_eq!(primitive_lists(Mode::LineLoop, vec![0, 1]).1, vec![0, 1]);
+        assert!(primitive_lists(Mode::LineLoop, vec![0]).1.is_empty());
+        assert!(primitive_lists(Mode::LineLoop, Vec::new()).1.is_empty());
+    }
+
+    #[test]
+    fn data_uris() {
+        let bytes = decode_data_uri("data:application/octet-stream;base64,SGVsbG8=").unwrap();
+        assert_eq!(bytes, b"Hello");
+        // The media type is optional
+        assert_eq!(decode_data_uri("data:;base64,AAAA").unwrap(), vec![0, 0, 0]);
+        assert!(decode_data_uri("data:application/octet-stream;base64,").unwrap().is_empty());
+
+        let message = |uri| match decode_data_uri(uri) {
+            Err(GltfLoadError::Message(message)) => message,
+            _ => panic!("{} should be a load error", uri),
+        };
+        assert_eq!(message("data:application/octet-stream;base64"), "Malformed data URI");
+        assert_eq!(message("data:text/plain,Hello"), "Data URI is not base64 encoded");
+        assert!(message("data:image/png;base64,!!!!").starts_with("Invalid base64"));
+    }
+
+    #[test]
+    fn lists_are_unchanged() {
+        let triangles = primitive_lists(Mode::Triangles, vec![0, 1, 2]);
+        assert_eq!(triangles, (Topology::Triangles, vec![0, 1, 2]));
+        assert_eq!(primitive_lists(Mode::Lines, vec![0, 1]), (Topology::Lines, vec![0, 1]));
+        assert_eq!(primitive_lists(Mode::Points, vec![0, 1]), (Topology::Points, vec![0, 1]));
+    }
+}
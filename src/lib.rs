@@ -1,19 +1,43 @@
-pub use camera::Camera;
+pub use animation::{
+    AnimationChannel, AnimationClip, AnimationPlayer, AnimationProperty, Interpolation, Keyframes,
+    NodePose, Pose,
+};
+pub use camera::{Camera, Projection};
 pub use light::{PointLight, SpotLight};
-pub use mesh::{Mesh, MeshPass};
+pub use mesh::{InstancedMesh, MeshAsset, MeshInstance};
+pub use mesh::{Mesh, MeshPass, Material, MaterialData, MaterialFactors, MaterialKind, TextureSlot};
+pub use mesh::{TextureSamplers, TextureSlots, TextureTransform, TextureTransforms};
+pub use mesh::{CustomMaterialDescriptor, CustomMaterialId, MaterialRenderState};
 pub use renderer::Renderer;
-pub use scene::{Scene, MeshId};
-pub use mesh::{MeshPartData, MeshPartGeometry, mesh_parts_bbox};
-pub use self::gltf::GltfLoadError;
-pub use resources::{ResourceLoader, Resources};
+pub use scene::{Scene, InstancedMeshId, MeshId, NodeId, PointLightId, SpotLightId};
+pub use node::{Node, NodeContent, Skin};
+pub use mesh::{ExtraVertex, MeshPartData, MeshPartGeometry, MorphVertex, PartLayout, SkinVertex};
+pub use mesh::{Topology, Vertex, VertexFormat};
+pub use mesh::mesh_parts_bbox;
+pub use self::gltf::{
+    GltfAnimation, GltfCamera, GltfLight, GltfLoadError, GltfNode, GltfScene, GltfSkin,
+};
+pub use compressed_texture::CompressedTextureError;
+pub use resources::{ResourceLoader, Resources, SamplerData, TextureLoadError, TextureOptions};
+pub use texture::Texture;
 
+pub mod bake;
+
+mod animation;
+mod bcn;
 mod camera;
+mod compressed_texture;
 mod compute_tangents;
 mod gltf;
 mod light;
 mod mesh;
+#[cfg(feature = "meshopt")]
+mod meshopt;
+mod mipmaps;
+mod node;
 mod obj;
 mod renderer;
 mod scene;
 mod resources;
+mod texture;
 
@@ -1 +1,12 @@
 pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+/// The most joints a skinned mesh can have. Joint matrices are uploaded as a uniform buffer, and
+/// 256 of them fill the 16KiB minimum uniform binding size.
+pub const MAX_JOINTS: usize = 256;
+
+/// The most morph targets a mesh part can blend. Their weights are packed in a uniform buffer.
+pub const MAX_MORPH_TARGETS: usize = 64;
+
+/// The spacing of each part's uniforms in a mesh's part buffer, bound with a dynamic offset. This
+/// is the largest minimum uniform buffer offset alignment wgpu allows.
+pub const PART_UNIFORM_STRIDE: usize = 256;
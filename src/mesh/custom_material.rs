@@ -0,0 +1,56 @@
+pub type CustomMaterialId = slotmap::DefaultKey;
+
+/// Describes a user-defined material pipeline, registered with `MeshPass::register_material`.
+///
+/// Custom pipelines share the built-in bind group sets, so their shaders may use:
+///
+/// - set 0: the global uniforms (camera and lights), same as the built-in shaders
+/// - set 1: the mesh transform
+/// - set 2, binding 0: the `MaterialFactors` uniform of the material
+/// - set 3: the bind group described by `bind_group_layout_entries`
+///
+/// Shader entry points must be named `main`. When no vertex shader is given, the built-in PBR
+/// vertex shader is used; it outputs the world position at location 0, the UV at location 1 and
+/// the TBN basis vectors at locations 2, 3 and 4. The fragment shader must write the color at
+/// location 0 and may write the bloom color at location 1.
+///
+/// Skinned mesh parts additionally bind their joint indices (`uvec4`) at location 4 and weights
+/// at location 5, with the joint matrices at set 1, binding 1. They're drawn with
+/// `skinned_vertex_shader`, or the built-in skinned PBR vertex shader when neither vertex shader
+/// is given. Materials with only a custom `vertex_shader` can't be used on skinned parts.
+///
+/// Parts of an `InstancedMesh` additionally bind the columns of their instance's transform at
+/// locations 8 to 11 and its color at location 12, and are drawn with `instanced_vertex_shader`
+/// under the same rules.
+pub struct CustomMaterialDescriptor<'a> {
+    pub label: Option<&'a str>,
+    pub vertex_shader: Option<wgpu::ShaderModuleDescriptor<'a>>,
+    pub skinned_vertex_shader: Option<wgpu::ShaderModuleDescriptor<'a>>,
+    pub instanced_vertex_shader: Option<wgpu::ShaderModuleDescriptor<'a>>,
+    pub fragment_shader: wgpu::ShaderModuleDescriptor<'a>,
+    pub bind_group_layout_entries: &'a [wgpu::BindGroupLayoutEntry],
+    pub render_state: MaterialRenderState,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialRenderState {
+    pub blend: wgpu::BlendState,
+    pub cull_mode: Option<wgpu::Face>,
+    pub depth_write_enabled: bool,
+    pub depth_compare: wgpu::CompareFunction,
+}
+
+impl Default for MaterialRenderState {
+    /// The render state used by the built-in materials.
+    fn default() -> Self {
+        MaterialRenderState {
+            blend: wgpu::BlendState {
+                color: wgpu::BlendComponent::REPLACE,
+                alpha: wgpu::BlendComponent::REPLACE,
+            },
+            cull_mode: None,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+        }
+    }
+}
@@ -12,10 +12,127 @@ pub struct Vertex {
 unsafe impl bytemuck::Pod for Vertex { }
 unsafe impl bytemuck::Zeroable for Vertex { }
 
+/// A `Vertex` in 20 bytes instead of 48: positions as normalized i16s within the part's bounds,
+/// normals and tangents as normalized i8s and UVs as normalized u16s within the part's UV
+/// bounds. See `VertexFormat::Compact`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct QuantizedVertex {
+    pub pos: [i16; 4],
+    pub norm: [i8; 4],
+    pub tang: [i8; 4],
+    pub tex_coord: [u16; 2],
+}
+
+unsafe impl bytemuck::Pod for QuantizedVertex { }
+unsafe impl bytemuck::Zeroable for QuantizedVertex { }
+
+/// How a part's vertices are stored on the GPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VertexFormat {
+    /// `Vertex`, 32 bit floats throughout
+    Full,
+    /// Quantized to less than half the size, for scenes bound by vertex memory. Positions keep
+    /// 16 bits of precision across the part's bounding box and normals 8 bits per component.
+    /// Parts with morph targets always use the full format.
+    Compact,
+}
+
+impl Default for VertexFormat {
+    fn default() -> Self { VertexFormat::Full }
+}
+
+/// The scale and offset restoring the positions and UVs of quantized vertices.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Dequantization {
+    pub position_scale: [f32; 3],
+    pub position_offset: [f32; 3],
+    pub uv_scale: [f32; 2],
+    pub uv_offset: [f32; 2],
+}
+
+impl Default for Dequantization {
+    fn default() -> Self {
+        Dequantization {
+            position_scale: [1.0; 3],
+            position_offset: [0.0; 3],
+            uv_scale: [1.0; 2],
+            uv_offset: [0.0; 2],
+        }
+    }
+}
+
+/// The joints influencing a skinned vertex and their weights, which should sum to 1.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct SkinVertex {
+    pub joints: [u16; 4],
+    pub weights: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for SkinVertex { }
+unsafe impl bytemuck::Zeroable for SkinVertex { }
+
+/// Optional per-vertex attributes. Parts without them are drawn with a color of white and a
+/// second UV set of (0, 0).
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct ExtraVertex {
+    /// Linear RGBA, multiplied into the base color
+    pub color: [f32; 4],
+    /// A second UV set, e.g. for ambient occlusion or lightmaps
+    pub tex_coord1: [f32; 2],
+}
+
+impl Default for ExtraVertex {
+    fn default() -> Self {
+        ExtraVertex { color: [1.0; 4], tex_coord1: [0.0; 2] }
+    }
+}
+
+unsafe impl bytemuck::Pod for ExtraVertex { }
+unsafe impl bytemuck::Zeroable for ExtraVertex { }
+
+/// How much a morph target moves a vertex at full weight.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+pub struct MorphVertex {
+    pub pos: [f32; 3],
+    pub norm: [f32; 3],
+    pub tang: [f32; 3],
+}
+
+unsafe impl bytemuck::Pod for MorphVertex { }
+unsafe impl bytemuck::Zeroable for MorphVertex { }
+
+/// How the indices of a mesh part form primitives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topology {
+    /// Every 3 indices form a triangle
+    Triangles,
+    /// Every 2 indices form a line. Lines are drawn unlit by the built-in materials.
+    Lines,
+    /// Every index is a point. Points are drawn unlit by the built-in materials.
+    Points,
+}
+
+impl Default for Topology {
+    fn default() -> Self { Topology::Triangles }
+}
+
 #[derive(Clone)]
 pub struct MeshPartGeometry {
     pub vertices: Vec<Vertex>,
     pub indices: Vec<u32>,
+    pub topology: Topology,
+    pub vertex_format: VertexFormat,
+    /// Per-vertex skinning data, parallel to `vertices`, for parts deformed by joints
+    pub skin: Option<Vec<SkinVertex>>,
+    /// Per-vertex colors and second UV set, parallel to `vertices`
+    pub extra_attributes: Option<Vec<ExtraVertex>>,
+    /// The deltas of each morph target, each parallel to `vertices`. Blended by the owning
+    /// mesh's `morph_weights`.
+    pub morph_targets: Vec<Vec<MorphVertex>>,
 }
 
 impl MeshPartGeometry {
@@ -35,7 +152,9 @@ impl MeshPartGeometry {
         (mins, maxes)
     }
 
+    /// Transform the vertices. Normals and tangents are transformed by the normal matrix.
     pub fn transform(&mut self, transform: Mat4) {
+        let normal_matrix = transform.inversed().transposed();
         for vertex in &mut self.vertices {
             let position = Vec3::new(vertex.pos[0], vertex.pos[1], vertex.pos[2]);
             let transformed_position = transform.transform_point3(position);
@@ -44,7 +163,233 @@ impl MeshPartGeometry {
                 transformed_position.y,
                 transformed_position.z,
             ];
+
+            let normal = Vec3::new(vertex.norm[0], vertex.norm[1], vertex.norm[2]);
+            let normal = normal_matrix.transform_vec3(normal);
+            if normal.mag_sq() > 0.0 {
+                let normal = normal.normalized();
+                vertex.norm = [normal.x, normal.y, normal.z];
+            }
+
+            let tangent = Vec3::new(vertex.tang[0], vertex.tang[1], vertex.tang[2]);
+            let tangent = transform.transform_vec3(tangent);
+            if tangent.mag_sq() > 0.0 {
+                let tangent = tangent.normalized();
+                vertex.tang = [tangent.x, tangent.y, tangent.z, vertex.tang[3]];
+            }
+        }
+
+        // Deltas are directions, so they aren't translated
+        for target in &mut self.morph_targets {
+            for delta in target {
+                let pos = transform.transform_vec3(delta.pos.into());
+                let norm = normal_matrix.transform_vec3(delta.norm.into());
+                let tang = transform.transform_vec3(delta.tang.into());
+                delta.pos = pos.into();
+                delta.norm = norm.into();
+                delta.tang = tang.into();
+            }
+        }
+    }
+    /// Give every triangle its own vertices, with normals perpendicular to the triangle. Only
+    /// meaningful for triangles, like `compute_smooth_normals`.
+    pub fn compute_flat_normals(&mut self) {
+        self.unweld();
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+                .map(|i| Vec3::from(self.vertices[i as usize].pos));
+            let normal = unit_or_up((b - a).cross(c - a));
+            for &i in triangle {
+                self.vertices[i as usize].norm = normal.into();
+            }
         }
     }
+
+    /// Set the normal of each vertex to the average of the normals of the triangles using it,
+    /// weighted by their area.
+    pub fn compute_smooth_normals(&mut self) {
+        let mut normals = vec![Vec3::zero(); self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [triangle[0], triangle[1], triangle[2]]
+                .map(|i| Vec3::from(self.vertices[i as usize].pos));
+            // The cross product's length is twice the triangle's area
+            let normal = (b - a).cross(c - a);
+            for &i in triangle {
+                normals[i as usize] += normal;
+            }
+        }
+        for (vertex, normal) in self.vertices.iter_mut().zip(normals) {
+            vertex.norm = unit_or_up(normal).into();
+        }
+    }
+
+    /// Give each vertex some tangent perpendicular to its normal, for geometry without texture
+    /// coordinates to derive tangents from.
+    pub fn compute_orthogonal_tangents(&mut self) {
+        for vertex in &mut self.vertices {
+            let normal = Vec3::from(vertex.norm);
+            let axis = if normal.x.abs() < 0.9 { Vec3::unit_x() } else { Vec3::unit_y() };
+            let tangent = unit_or_up(axis - normal * normal.dot(axis));
+            vertex.tang = [tangent.x, tangent.y, tangent.z, 1.0];
+        }
+    }
+
+    /// The vertices in the compact format, and the transform restoring their positions and UVs.
+    pub(crate) fn quantized_vertices(&self) -> (Vec<QuantizedVertex>, Dequantization) {
+        let (pos_mins, pos_maxes) = self.bounding_box();
+        let mut uv_mins = [f32::MAX; 2];
+        let mut uv_maxes = [f32::MIN; 2];
+        for vertex in &self.vertices {
+            for i in 0..2 {
+                uv_mins[i] = f32::min(uv_mins[i], vertex.tex_coord[i]);
+                uv_maxes[i] = f32::max(uv_maxes[i], vertex.tex_coord[i]);
+            }
+        }
+
+        // Positions map to [-1, 1] around the center of the bounds, UVs to [0, 1]
+        let nonzero = |extent: f32| if extent > 0.0 { extent } else { 1.0 };
+        let mut dequantization = Dequantization::default();
+        for i in 0..3 {
+            dequantization.position_scale[i] = nonzero((pos_maxes[i] - pos_mins[i]) / 2.0);
+            dequantization.position_offset[i] = (pos_maxes[i] + pos_mins[i]) / 2.0;
+        }
+        for i in 0..2 {
+            dequantization.uv_scale[i] = nonzero(uv_maxes[i] - uv_mins[i]);
+            dequantization.uv_offset[i] = uv_mins[i];
+        }
+
+        let snorm16 = |v: f32| (v.max(-1.0).min(1.0) * i16::MAX as f32).round() as i16;
+        let snorm8 = |v: f32| (v.max(-1.0).min(1.0) * i8::MAX as f32).round() as i8;
+        let unorm16 = |v: f32| (v.max(0.0).min(1.0) * u16::MAX as f32).round() as u16;
+        let d = &dequantization;
+        let vertices = self.vertices.iter()
+            .map(|vertex| {
+                let pos = |i: usize| {
+                    snorm16((vertex.pos[i] - d.position_offset[i]) / d.position_scale[i])
+                };
+                let uv = |i: usize| unorm16((vertex.tex_coord[i] - d.uv_offset[i]) / d.uv_scale[i]);
+                QuantizedVertex {
+                    pos: [pos(0), pos(1), pos(2), 0],
+                    norm: [snorm8(vertex.norm[0]), snorm8(vertex.norm[1]), snorm8(vertex.norm[2]), 0],
+                    tang: [
+                        snorm8(vertex.tang[0]), snorm8(vertex.tang[1]), snorm8(vertex.tang[2]),
+                        snorm8(vertex.tang[3]),
+                    ],
+                    tex_coord: [uv(0), uv(1)],
+                }
+            })
+            .collect();
+
+        (vertices, dequantization)
+    }
+
+    /// Duplicate shared vertices so each index refers to its own vertex.
+    fn unweld(&mut self) {
+        let indices = &self.indices;
+        self.vertices = indices.iter().map(|&i| self.vertices[i as usize]).collect();
+        if let Some(ref mut skin) = self.skin {
+            *skin = indices.iter().map(|&i| skin[i as usize]).collect();
+        }
+        if let Some(ref mut extra) = self.extra_attributes {
+            *extra = indices.iter().map(|&i| extra[i as usize]).collect();
+        }
+        for target in &mut self.morph_targets {
+            *target = indices.iter().map(|&i| target[i as usize]).collect();
+        }
+        self.indices = (0..self.indices.len() as u32).collect();
+    }
 }
 
+fn unit_or_up(v: Vec3) -> Vec3 {
+    if v.mag_sq() > 0.0 { v.normalized() } else { Vec3::unit_y() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(pos: [f32; 3]) -> Vertex {
+        Vertex { pos, norm: [0.0; 3], tang: [0.0; 4], tex_coord: [0.0; 2] }
+    }
+
+    fn geometry(positions: &[[f32; 3]], indices: &[u32]) -> MeshPartGeometry {
+        MeshPartGeometry {
+            vertices: positions.iter().copied().map(vertex).collect(),
+            indices: indices.to_vec(),
+            topology: Topology::Triangles,
+            vertex_format: VertexFormat::Full,
+            skin: None,
+            extra_attributes: None,
+            morph_targets: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn flat_normals_unweld_every_attribute() {
+        // Two triangles folded along their shared edge, one facing +Z and one facing +X
+        let mut geometry = geometry(
+            &[[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [-1.0, 0.0, 0.0], [0.0, 0.0, -1.0]],
+            &[0, 1, 2, 0, 3, 1],
+        );
+        geometry.skin = Some(
+            (0..4).map(|i| SkinVertex { joints: [i; 4], weights: [1.0, 0.0, 0.0, 0.0] }).collect()
+        );
+        geometry.extra_attributes = Some(
+            (0..4).map(|i| ExtraVertex { color: [i as f32; 4], tex_coord1: [0.0; 2] }).collect()
+        );
+        geometry.morph_targets = vec![
+            (0..4).map(|i| MorphVertex { pos: [i as f32; 3], ..Default::default() }).collect()
+        ];
+
+        geometry.compute_flat_normals();
+
+        let original = [0, 1, 2, 0, 3, 1];
+        assert_eq!(geometry.indices, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(geometry.vertices.len(), 6);
+        let skin = geometry.skin.as_ref().unwrap();
+        let extra = geometry.extra_attributes.as_ref().unwrap();
+        let target = &geometry.morph_targets[0];
+        for (i, &from) in original.iter().enumerate() {
+            assert_eq!(skin[i].joints, [from as u16; 4]);
+            assert_eq!(extra[i].color, [from as f32; 4]);
+            assert_eq!(target[i].pos, [from as f32; 3]);
+        }
+        for vertex in &geometry.vertices[..3] {
+            assert_eq!(vertex.norm, [0.0, 0.0, 1.0]);
+        }
+        for vertex in &geometry.vertices[3..] {
+            assert_eq!(vertex.norm, [1.0, 0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn degenerate_triangles_face_up() {
+        let positions = [[0.0, 0.0, 0.0], [1.0, 1.0, 1.0], [2.0, 2.0, 2.0]];
+
+        let mut flat = geometry(&positions, &[0, 1, 2]);
+        flat.compute_flat_normals();
+        assert!(flat.vertices.iter().all(|vertex| vertex.norm == [0.0, 1.0, 0.0]));
+
+        let mut smooth = geometry(&positions, &[0, 1, 2]);
+        smooth.compute_smooth_normals();
+        assert!(smooth.vertices.iter().all(|vertex| vertex.norm == [0.0, 1.0, 0.0]));
+    }
+
+    #[test]
+    fn smooth_normals_weight_by_area() {
+        // A large triangle facing +Z and a small one facing +X share vertices 0 and 1
+        let mut geometry = geometry(
+            &[[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [-4.0, 0.0, 0.0], [0.0, 0.0, -1.0]],
+            &[0, 1, 2, 0, 3, 1],
+        );
+        geometry.compute_smooth_normals();
+
+        assert_eq!(geometry.vertices.len(), 4);
+        assert_eq!(geometry.vertices[2].norm, [0.0, 0.0, 1.0]);
+        assert_eq!(geometry.vertices[3].norm, [1.0, 0.0, 0.0]);
+        let shared = Vec3::from(geometry.vertices[0].norm);
+        let expected = Vec3::new(1.0, 0.0, 4.0).normalized();
+        assert!((shared - expected).mag() < 1e-6);
+        assert_eq!(geometry.vertices[0].norm, geometry.vertices[1].norm);
+    }
+}
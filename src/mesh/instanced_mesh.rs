@@ -0,0 +1,207 @@
+use std::rc::Rc;
+
+use ultraviolet::{Mat4, Rotor3, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::resources::Resources;
+use super::{
+    consts::PART_UNIFORM_STRIDE,
+    geometry::ExtraVertex,
+    mesh::create_mesh_bind_group,
+    mesh_asset::MeshAsset,
+    mesh_part::{MeshPart, MeshPartData},
+    mesh_pass::MeshPass,
+    mesh_pipeline::PartLayout,
+};
+
+/// A placement of an `InstancedMesh`, relative to the mesh's own transform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshInstance {
+    pub position: Vec3,
+    pub rotation: Rotor3,
+    pub scale: Vec3,
+    /// Multiplies the vertex colors of every part. White by default.
+    pub color: [f32; 4],
+}
+
+impl Default for MeshInstance {
+    fn default() -> Self {
+        MeshInstance {
+            position: Vec3::zero(),
+            rotation: Rotor3::identity(),
+            scale: Vec3::broadcast(1.0),
+            color: [1.0; 4],
+        }
+    }
+}
+
+impl MeshInstance {
+    pub fn transform(&self) -> Mat4 {
+        Mat4::from_translation(self.position) *
+            self.rotation.into_matrix().into_homogeneous() *
+            Mat4::from_nonuniform_scale(self.scale)
+    }
+}
+
+/// The per-instance vertex attributes of instanced parts: the columns of the instance transform
+/// at locations 8 to 11, and the color at location 12.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct InstanceVertex {
+    pub transform: [f32; 16],
+    pub color: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for InstanceVertex { }
+unsafe impl bytemuck::Zeroable for InstanceVertex { }
+
+impl From<&MeshInstance> for InstanceVertex {
+    fn from(instance: &MeshInstance) -> Self {
+        InstanceVertex {
+            transform: *instance.transform().as_array(),
+            color: instance.color,
+        }
+    }
+}
+
+/// A mesh drawn many times with a single draw call per part. Each instance has its own transform
+/// and color, read from a per-instance vertex buffer, while the parts' buffers and materials are
+/// shared. The parts come from a `MeshAsset`, which may also be drawn by other meshes.
+///
+/// Instanced parts are drawn without their skin and morph targets.
+pub struct InstancedMesh {
+    pub position: Vec3,
+    pub rotation: Rotor3,
+    pub scale: Vec3,
+
+    asset: Rc<MeshAsset>,
+    instance_count: usize,
+    instance_capacity: usize,
+    bind_group: wgpu::BindGroup,
+    uniform_buf: wgpu::Buffer,
+    instance_buf: wgpu::Buffer,
+    // Bound as the extra attributes of parts without them, one default per instance
+    default_extra_buf: wgpu::Buffer,
+}
+
+impl InstancedMesh {
+    /// Create an instanced mesh from its parts, placed at each of `instances`.
+    pub fn from_parts(
+        device: &mut wgpu::Device,
+        resources: &mut Resources,
+        mesh_pass: &MeshPass,
+        mesh_parts: &[MeshPartData],
+        instances: &[MeshInstance],
+    ) -> InstancedMesh {
+        let asset = MeshAsset::from_parts(device, resources, mesh_pass, mesh_parts);
+        InstancedMesh::from_asset(device, mesh_pass, Rc::new(asset), instances)
+    }
+
+    /// Create an instanced mesh drawing a shared asset at each of `instances`.
+    pub fn from_asset(
+        device: &mut wgpu::Device,
+        mesh_pass: &MeshPass,
+        asset: Rc<MeshAsset>,
+        instances: &[MeshInstance],
+    ) -> InstancedMesh {
+        let transform = Mat4::identity();
+        let transform_ref: &[f32; 16] = transform.as_array();
+        let uniform_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(transform_ref),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group =
+            create_mesh_bind_group(device, mesh_pass, &uniform_buf, None, asset.part_buf());
+
+        let instance_capacity = instances.len().max(1);
+        let (instance_buf, default_extra_buf) =
+            create_instance_bufs(device, instance_capacity, instances);
+
+        InstancedMesh {
+            position: Vec3::zero(),
+            rotation: Rotor3::identity(),
+            scale: Vec3::broadcast(1.0),
+
+            asset,
+            instance_count: instances.len(),
+            instance_capacity,
+            bind_group,
+            uniform_buf,
+            instance_buf,
+            default_extra_buf,
+        }
+    }
+
+    /// Replace the instances. The instance buffer is only recreated when it needs to grow.
+    pub fn set_instances(
+        &mut self,
+        device: &mut wgpu::Device,
+        queue: &wgpu::Queue,
+        instances: &[MeshInstance],
+    ) {
+        if instances.len() > self.instance_capacity {
+            // Grow geometrically, so adding instances one at a time doesn't reallocate each time
+            let capacity = instances.len().max(self.instance_capacity * 2);
+            let (instance_buf, default_extra_buf) =
+                create_instance_bufs(device, capacity, instances);
+            self.instance_buf = instance_buf;
+            self.default_extra_buf = default_extra_buf;
+            self.instance_capacity = capacity;
+        } else if !instances.is_empty() {
+            let data = instances.iter().map(InstanceVertex::from).collect::<Vec<_>>();
+            queue.write_buffer(&self.instance_buf, 0, bytemuck::cast_slice(&data));
+        }
+        self.instance_count = instances.len();
+    }
+
+    pub fn asset(&self) -> &Rc<MeshAsset> { &self.asset }
+    pub fn parts(&self) -> &[MeshPart] { self.asset.parts() }
+    /// The parts of the mesh, if its asset isn't shared with other meshes.
+    pub fn parts_mut(&mut self) -> Option<&mut [MeshPart]> {
+        Rc::get_mut(&mut self.asset).map(MeshAsset::parts_mut)
+    }
+    pub fn instance_count(&self) -> usize { self.instance_count }
+    pub fn bind_group(&self) -> &wgpu::BindGroup { &self.bind_group }
+    pub fn uniform_buf(&self) -> &wgpu::Buffer { &self.uniform_buf }
+    pub fn instance_buf(&self) -> &wgpu::Buffer { &self.instance_buf }
+    pub(crate) fn default_extra_buf(&self) -> &wgpu::Buffer { &self.default_extra_buf }
+    /// The dynamic offset of a part's uniforms in the mesh bind group.
+    pub fn part_uniform_offset(&self, part: usize) -> wgpu::DynamicOffset {
+        (part * PART_UNIFORM_STRIDE) as wgpu::DynamicOffset
+    }
+
+    /// The vertex layout a part is drawn with.
+    pub fn part_layout(&self, part: usize) -> PartLayout {
+        PartLayout { skinned: false, instanced: true, ..self.parts()[part].layout() }
+    }
+
+    pub fn transform(&self) -> Mat4 {
+        Mat4::from_translation(self.position) *
+            self.rotation.into_matrix().into_homogeneous() *
+            Mat4::from_nonuniform_scale(self.scale)
+    }
+}
+
+// The instance buffer, holding `instances` with room for `capacity`, and a matching buffer of
+// default extra attributes
+fn create_instance_bufs(
+    device: &mut wgpu::Device,
+    capacity: usize,
+    instances: &[MeshInstance],
+) -> (wgpu::Buffer, wgpu::Buffer) {
+    let mut data = instances.iter().map(InstanceVertex::from).collect::<Vec<_>>();
+    data.resize(capacity, InstanceVertex::from(&MeshInstance::default()));
+    let instance_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("mesh-instance-buf"),
+        contents: bytemuck::cast_slice(&data),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    });
+    let default_extra_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("mesh-instance-extra-buf"),
+        contents: bytemuck::cast_slice(&vec![ExtraVertex::default(); capacity]),
+        usage: wgpu::BufferUsages::VERTEX,
+    });
+    (instance_buf, default_extra_buf)
+}
@@ -1,18 +1,27 @@
+use std::cell::Cell;
 use std::rc::Rc;
 
 use bytemuck::{Pod, Zeroable};
 use wgpu::util::DeviceExt;
 
+use crate::resources::{Resources, SamplerData};
+use crate::texture::Texture;
+use super::custom_material::CustomMaterialId;
 use super::mesh_pass::MeshPass;
 
 #[derive(Copy, Clone)]
 pub enum MaterialKind {
-    Untextured,
     TexturedUnlit,
     Textured,
     TexturedNorm,
     TexturedNormMat,
     TexturedEmissive,
+    /// Any of the clearcoat, sheen or specular layers, on top of every map of `TexturedEmissive`
+    TexturedLayered,
+    /// Transmission and volume, on top of every map of `TexturedLayered`. Drawn after the opaque
+    /// parts, which it sees through.
+    TexturedTransmissive,
+    Custom(CustomMaterialId),
 }
 
 #[derive(Clone)]
@@ -20,11 +29,197 @@ pub struct MaterialData {
     pub factors: MaterialFactors,
     pub lighting: bool,
 
-    pub texture: Option<Rc<wgpu::Texture>>,
-    pub normal: Option<Rc<wgpu::Texture>>,
-    pub metallic_roughness: Option<Rc<wgpu::Texture>>,
-    pub ao: Option<Rc<wgpu::Texture>>,
-    pub emissive: Option<Rc<wgpu::Texture>>,
+    pub texture: Option<Rc<Texture>>,
+    pub normal: Option<Rc<Texture>>,
+    pub metallic_roughness: Option<Rc<Texture>>,
+    pub ao: Option<Rc<Texture>>,
+    pub emissive: Option<Rc<Texture>>,
+
+    pub clearcoat: Option<Rc<Texture>>,
+    pub clearcoat_roughness: Option<Rc<Texture>>,
+    pub clearcoat_normal: Option<Rc<Texture>>,
+    pub sheen_color: Option<Rc<Texture>>,
+    pub sheen_roughness: Option<Rc<Texture>>,
+    pub specular: Option<Rc<Texture>>,
+    pub specular_color: Option<Rc<Texture>>,
+    pub transmission: Option<Rc<Texture>>,
+    pub thickness: Option<Rc<Texture>>,
+
+    pub texture_transforms: TextureTransforms,
+    pub samplers: TextureSamplers,
+    /// Which UV set each texture slot samples with, 0 or 1
+    pub texture_uv_sets: TextureSlots<u32>,
+}
+
+impl MaterialData {
+    pub fn texture(&self, slot: TextureSlot) -> &Option<Rc<Texture>> {
+        match slot {
+            TextureSlot::BaseColor => &self.texture,
+            TextureSlot::Normal => &self.normal,
+            TextureSlot::MetallicRoughness => &self.metallic_roughness,
+            TextureSlot::Ao => &self.ao,
+            TextureSlot::Emissive => &self.emissive,
+            TextureSlot::Clearcoat => &self.clearcoat,
+            TextureSlot::ClearcoatRoughness => &self.clearcoat_roughness,
+            TextureSlot::ClearcoatNormal => &self.clearcoat_normal,
+            TextureSlot::SheenColor => &self.sheen_color,
+            TextureSlot::SheenRoughness => &self.sheen_roughness,
+            TextureSlot::Specular => &self.specular,
+            TextureSlot::SpecularColor => &self.specular_color,
+            TextureSlot::Transmission => &self.transmission,
+            TextureSlot::Thickness => &self.thickness,
+        }
+    }
+
+    pub fn texture_mut(&mut self, slot: TextureSlot) -> &mut Option<Rc<Texture>> {
+        match slot {
+            TextureSlot::BaseColor => &mut self.texture,
+            TextureSlot::Normal => &mut self.normal,
+            TextureSlot::MetallicRoughness => &mut self.metallic_roughness,
+            TextureSlot::Ao => &mut self.ao,
+            TextureSlot::Emissive => &mut self.emissive,
+            TextureSlot::Clearcoat => &mut self.clearcoat,
+            TextureSlot::ClearcoatRoughness => &mut self.clearcoat_roughness,
+            TextureSlot::ClearcoatNormal => &mut self.clearcoat_normal,
+            TextureSlot::SheenColor => &mut self.sheen_color,
+            TextureSlot::SheenRoughness => &mut self.sheen_roughness,
+            TextureSlot::Specular => &mut self.specular,
+            TextureSlot::SpecularColor => &mut self.specular_color,
+            TextureSlot::Transmission => &mut self.transmission,
+            TextureSlot::Thickness => &mut self.thickness,
+        }
+    }
+
+    /// Whether any of the layers differ from the plain metallic roughness model, which needs the
+    /// layered pipeline.
+    pub fn layered(&self) -> bool {
+        let f = &self.factors;
+        f.clearcoat > 0.0
+            || f.sheen_color != [0.0; 3]
+            || f.specular != 1.0
+            || f.specular_color != [1.0; 3]
+            || f.ior != 1.5
+            || TextureSlot::LAYERS.iter().any(|&slot| self.texture(slot).is_some())
+    }
+
+    /// Whether the material transmits light, which needs the transmissive pipeline.
+    pub fn transmissive(&self) -> bool {
+        self.factors.transmission > 0.0
+    }
+}
+
+/// A UV transform as defined by `KHR_texture_transform`. UVs are scaled, then rotated
+/// counter-clockwise by `rotation` radians, then offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureTransform {
+    pub offset: [f32; 2],
+    pub rotation: f32,
+    pub scale: [f32; 2],
+}
+
+impl TextureTransform {
+    /// The rows of the 2x3 matrix `translation * rotation * scale`, padded for std140.
+    fn rows(&self) -> [[f32; 4]; 2] {
+        let (sin, cos) = self.rotation.sin_cos();
+        [
+            [cos * self.scale[0], sin * self.scale[1], self.offset[0], 0.0],
+            [-sin * self.scale[0], cos * self.scale[1], self.offset[1], 0.0],
+        ]
+    }
+}
+
+impl Default for TextureTransform {
+    fn default() -> Self {
+        TextureTransform {
+            offset: [0.0, 0.0],
+            rotation: 0.0,
+            scale: [1.0, 1.0],
+        }
+    }
+}
+
+/// A value for each texture slot of a material.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TextureSlots<T> {
+    pub texture: T,
+    pub normal: T,
+    pub metallic_roughness: T,
+    pub ao: T,
+    pub emissive: T,
+    pub clearcoat: T,
+    pub clearcoat_roughness: T,
+    pub clearcoat_normal: T,
+    pub sheen_color: T,
+    pub sheen_roughness: T,
+    pub specular: T,
+    pub specular_color: T,
+    pub transmission: T,
+    pub thickness: T,
+}
+
+/// The UV transform of each texture slot of a material.
+pub type TextureTransforms = TextureSlots<TextureTransform>;
+
+/// The sampler of each texture slot of a material.
+pub type TextureSamplers = TextureSlots<SamplerData>;
+
+impl<T> TextureSlots<T> {
+    pub fn get(&self, slot: TextureSlot) -> &T {
+        match slot {
+            TextureSlot::BaseColor => &self.texture,
+            TextureSlot::Normal => &self.normal,
+            TextureSlot::MetallicRoughness => &self.metallic_roughness,
+            TextureSlot::Ao => &self.ao,
+            TextureSlot::Emissive => &self.emissive,
+            TextureSlot::Clearcoat => &self.clearcoat,
+            TextureSlot::ClearcoatRoughness => &self.clearcoat_roughness,
+            TextureSlot::ClearcoatNormal => &self.clearcoat_normal,
+            TextureSlot::SheenColor => &self.sheen_color,
+            TextureSlot::SheenRoughness => &self.sheen_roughness,
+            TextureSlot::Specular => &self.specular,
+            TextureSlot::SpecularColor => &self.specular_color,
+            TextureSlot::Transmission => &self.transmission,
+            TextureSlot::Thickness => &self.thickness,
+        }
+    }
+
+    pub fn get_mut(&mut self, slot: TextureSlot) -> &mut T {
+        match slot {
+            TextureSlot::BaseColor => &mut self.texture,
+            TextureSlot::Normal => &mut self.normal,
+            TextureSlot::MetallicRoughness => &mut self.metallic_roughness,
+            TextureSlot::Ao => &mut self.ao,
+            TextureSlot::Emissive => &mut self.emissive,
+            TextureSlot::Clearcoat => &mut self.clearcoat,
+            TextureSlot::ClearcoatRoughness => &mut self.clearcoat_roughness,
+            TextureSlot::ClearcoatNormal => &mut self.clearcoat_normal,
+            TextureSlot::SheenColor => &mut self.sheen_color,
+            TextureSlot::SheenRoughness => &mut self.sheen_roughness,
+            TextureSlot::Specular => &mut self.specular,
+            TextureSlot::SpecularColor => &mut self.specular_color,
+            TextureSlot::Transmission => &mut self.transmission,
+            TextureSlot::Thickness => &mut self.thickness,
+        }
+    }
+
+    pub fn map<U>(&self, mut f: impl FnMut(&T) -> U) -> TextureSlots<U> {
+        TextureSlots {
+            texture: f(&self.texture),
+            normal: f(&self.normal),
+            metallic_roughness: f(&self.metallic_roughness),
+            ao: f(&self.ao),
+            emissive: f(&self.emissive),
+            clearcoat: f(&self.clearcoat),
+            clearcoat_roughness: f(&self.clearcoat_roughness),
+            clearcoat_normal: f(&self.clearcoat_normal),
+            sheen_color: f(&self.sheen_color),
+            sheen_roughness: f(&self.sheen_roughness),
+            specular: f(&self.specular),
+            specular_color: f(&self.specular_color),
+            transmission: f(&self.transmission),
+            thickness: f(&self.thickness),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -35,131 +230,334 @@ pub struct MaterialFactors {
     pub rough: f32,
     pub emissive: [f32; 3],
     pub extra_emissive: [f32; 3],
+
+    // The layers below are only evaluated by materials that were created with one of them, see
+    // `MaterialData::layered` and `MaterialData::transmissive`
+
+    /// Strength and roughness of the clearcoat layer, see `KHR_materials_clearcoat`
+    pub clearcoat: f32,
+    pub clearcoat_rough: f32,
+    /// Color and roughness of the sheen layer, see `KHR_materials_sheen`
+    pub sheen_color: [f32; 3],
+    pub sheen_rough: f32,
+    /// Strength and color of the specular reflection of dielectrics, see `KHR_materials_specular`
+    pub specular: f32,
+    pub specular_color: [f32; 3],
+    /// Index of refraction of dielectrics, see `KHR_materials_ior`
+    pub ior: f32,
+    /// How much light passes through the surface instead of being diffusely reflected, see
+    /// `KHR_materials_transmission`
+    pub transmission: f32,
+    /// Thickness of the volume beneath the surface in the mesh's coordinate space, 0 for thin
+    /// surfaces, see `KHR_materials_volume`
+    pub thickness: f32,
+    /// The color white light turns into after traveling `attenuation_distance` through the volume
+    pub attenuation_color: [f32; 3],
+    /// Infinite for volumes that don't absorb any light
+    pub attenuation_distance: f32,
+}
+
+/// A texture slot of a built-in material, see `Material::set_texture`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextureSlot {
+    BaseColor,
+    Normal,
+    MetallicRoughness,
+    Ao,
+    Emissive,
+    /// Clearcoat strength in the red channel
+    Clearcoat,
+    /// Clearcoat roughness in the green channel
+    ClearcoatRoughness,
+    ClearcoatNormal,
+    SheenColor,
+    /// Sheen roughness in the alpha channel
+    SheenRoughness,
+    /// Specular strength in the alpha channel
+    Specular,
+    SpecularColor,
+    /// Transmission in the red channel
+    Transmission,
+    /// Thickness in the green channel
+    Thickness,
+}
+
+impl TextureSlot {
+    /// Every slot, in the order the factors uniform stores them.
+    pub const ALL: [TextureSlot; 14] = [
+        TextureSlot::BaseColor,
+        TextureSlot::Normal,
+        TextureSlot::MetallicRoughness,
+        TextureSlot::Ao,
+        TextureSlot::Emissive,
+        TextureSlot::Clearcoat,
+        TextureSlot::ClearcoatRoughness,
+        TextureSlot::ClearcoatNormal,
+        TextureSlot::SheenColor,
+        TextureSlot::SheenRoughness,
+        TextureSlot::Specular,
+        TextureSlot::SpecularColor,
+        TextureSlot::Transmission,
+        TextureSlot::Thickness,
+    ];
+
+    /// The slots only sampled by the layered and transmissive pipelines. Transmission and
+    /// thickness come last, as only the transmissive pipeline samples them.
+    pub const LAYERS: [TextureSlot; 9] = [
+        TextureSlot::Clearcoat,
+        TextureSlot::ClearcoatRoughness,
+        TextureSlot::ClearcoatNormal,
+        TextureSlot::SheenColor,
+        TextureSlot::SheenRoughness,
+        TextureSlot::Specular,
+        TextureSlot::SpecularColor,
+        TextureSlot::Transmission,
+        TextureSlot::Thickness,
+    ];
 }
 
 pub struct Material {
-    pub factors: MaterialFactors,
+    data: MaterialData,
+    factors_dirty: Cell<bool>,
 
     kind: MaterialKind,
     factors_buf: wgpu::Buffer,
     bind_group: wgpu::BindGroup,
+    custom_bind_group: Option<wgpu::BindGroup>,
 }
 
 impl Material {
+    /// Create a material from its description. Any map missing from `data` is bound to one of
+    /// the neutral fallback textures from `resources`, so every combination of maps is valid.
     pub fn new(
         device: &mut wgpu::Device,
+        resources: &mut Resources,
         mesh_pass: &MeshPass,
         data: &MaterialData,
     ) -> Self {
-        if data.lighting {
-            if let Some(ref texture) = data.texture {
-                if let Some(ref emissive) = data.emissive {
-                    let normal = data.normal.as_ref().expect("textured model without normal map");
-                    let metallic_roughness = data.metallic_roughness.as_ref().expect("textured model without metallic roughness map");
-                    let ao = data.ao.as_ref().expect("textured model without ao map");
-                    Material::textured_emissive(
-                        device, mesh_pass,
-                        data.factors.into(),
-                        &texture,
-                        normal,
-                        metallic_roughness,
-                        ao,
-                        &emissive
-                    )
-                } else if let Some(ref normal) = data.normal {
-                    if let Some(metallic_roughness) = data.metallic_roughness.as_ref() {
-                        let ao = data.ao.as_ref().expect("textured model without ao map");
-                        Material::textured_norm_mat(
-                            device, mesh_pass,
-                            data.factors.into(),
-                            &texture,
-                            &normal,
-                            metallic_roughness,
-                            ao,
-                        )
-                    } else {
-                        Material::textured_norm(
-                            device, mesh_pass,
-                            data.factors.into(),
-                            &texture,
-                            &normal,
-                        )
-                    }
-                } else {
-                    Material::textured(
-                        device, mesh_pass,
-                        data.factors.into(),
-                        &texture,
-                    )
-                }
-            } else {
-                Material::untextured(
-                    device, mesh_pass,
-                    data.factors.into(),
-                )
-            }
-        } else {
-            if let Some(ref texture) = data.texture {
-                Material::textured_unlit(
-                    device, mesh_pass,
-                    data.factors.into(),
-                    &texture,
-                )
-            } else {
-                Material::untextured(
-                    device, mesh_pass,
-                    data.factors.into(),
-                )
-            }
+        let factors_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(&[MaterialFactorsUpload::from(data)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let (kind, bind_group) =
+            Material::create_bind_group(device, resources, mesh_pass, data, &factors_buf);
+
+        Material {
+            data: data.clone(),
+            factors_dirty: Cell::new(false),
+
+            kind,
+            factors_buf,
+            bind_group,
+            custom_bind_group: None,
         }
     }
 
-    pub fn upload_factors_to_gpu(
-        &self,
+    pub fn factors(&self) -> &MaterialFactors { &self.data.factors }
+
+    /// Mutable access to the material factors. Changes are uploaded to the GPU during the next
+    /// `Renderer::render`.
+    pub fn factors_mut(&mut self) -> &mut MaterialFactors {
+        self.factors_dirty.set(true);
+        &mut self.data.factors
+    }
+
+    pub fn set_factors(&mut self, factors: MaterialFactors) {
+        *self.factors_mut() = factors;
+    }
+
+    pub fn factors_dirty(&self) -> bool { self.factors_dirty.get() }
+
+    pub fn upload_factors_to_gpu(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(
+            &self.factors_buf, 0,
+            bytemuck::cast_slice(&[MaterialFactorsUpload::from(&self.data)]),
+        );
+        self.factors_dirty.set(false);
+    }
+
+    pub fn texture_transform(&self, slot: TextureSlot) -> &TextureTransform {
+        self.data.texture_transforms.get(slot)
+    }
+
+    /// Change the UV transform of a texture slot. Like the factors, it's uploaded to the GPU during
+    /// the next `Renderer::render`.
+    pub fn set_texture_transform(&mut self, slot: TextureSlot, transform: TextureTransform) {
+        *self.data.texture_transforms.get_mut(slot) = transform;
+        self.factors_dirty.set(true);
+    }
+
+    pub fn texture(&self, slot: TextureSlot) -> Option<&Rc<Texture>> {
+        self.data.texture(slot).as_ref()
+    }
+
+    pub fn sampler(&self, slot: TextureSlot) -> &SamplerData {
+        self.data.samplers.get(slot)
+    }
+
+    /// Replace a single texture of a built-in material, or clear it with `None` to bind the
+    /// neutral fallback instead. Only the bind group is rebuilt; the material may switch to a
+    /// different built-in pipeline if the set of provided maps changes. The slot keeps its sampler.
+    pub fn set_texture(
+        &mut self,
         device: &mut wgpu::Device,
-        encoder: &mut wgpu::CommandEncoder,
+        resources: &mut Resources,
+        mesh_pass: &MeshPass,
+        slot: TextureSlot,
+        texture: Option<Rc<Texture>>,
     ) {
-        let factors_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&[MaterialFactorsUpload::from(self.factors)]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_SRC,
-        });
-        encoder.copy_buffer_to_buffer(
-            &factors_buf, 0, &self.factors_buf, 0,
-            std::mem::size_of::<MaterialFactorsUpload>() as wgpu::BufferAddress,
+        if let MaterialKind::Custom(_) = self.kind {
+            panic!("custom material textures must be replaced with set_custom_bind_group");
+        }
+
+        *self.data.texture_mut(slot) = texture;
+        // The texture's channel count is part of the factors uniform
+        self.factors_dirty.set(true);
+
+        let (kind, bind_group) = Material::create_bind_group(
+            device, resources, mesh_pass, &self.data, &self.factors_buf,
         );
+        self.kind = kind;
+        self.bind_group = bind_group;
     }
 
-    fn textured_unlit(
+    /// Change how a single texture slot of a built-in material is sampled.
+    pub fn set_sampler(
+        &mut self,
         device: &mut wgpu::Device,
+        resources: &mut Resources,
         mesh_pass: &MeshPass,
-        factors: MaterialFactors,
-        texture: &wgpu::Texture,
-    ) -> Self {
-        let factors_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&[MaterialFactorsUpload::from(factors)]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
+        slot: TextureSlot,
+        sampler: SamplerData,
+    ) {
+        if let MaterialKind::Custom(_) = self.kind {
+            panic!("custom material samplers must be replaced with set_custom_bind_group");
+        }
 
-        let texture_view = texture.create_view(&Default::default());
+        *self.data.samplers.get_mut(slot) = sampler;
 
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: None,
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Linear,
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
-            compare: None,
-            ..Default::default()
-        });
+        let (kind, bind_group) = Material::create_bind_group(
+            device, resources, mesh_pass, &self.data, &self.factors_buf,
+        );
+        self.kind = kind;
+        self.bind_group = bind_group;
+    }
+
+    fn create_bind_group(
+        device: &mut wgpu::Device,
+        resources: &mut Resources,
+        mesh_pass: &MeshPass,
+        data: &MaterialData,
+        factors_buf: &wgpu::Buffer,
+    ) -> (MaterialKind, wgpu::BindGroup) {
+        let samplers = data.samplers.map(|sampler| resources.sampler(device, sampler));
+        let texture = data.texture.as_ref().unwrap_or(resources.white_texture());
+
+        if !data.lighting {
+            return (
+                MaterialKind::TexturedUnlit,
+                Material::textured_unlit_bind_group(
+                    device, mesh_pass, factors_buf, texture, &samplers.texture,
+                ),
+            );
+        }
+
+        let normal = data.normal.as_ref().unwrap_or(resources.flat_normal_texture());
+        let metallic_roughness =
+            data.metallic_roughness.as_ref().unwrap_or(resources.white_texture());
+        let ao = data.ao.as_ref().unwrap_or(resources.white_texture());
+        let emissive = data.emissive.as_ref().unwrap_or(resources.black_texture());
+
+        // Pick the cheapest pipeline that samples every map that was provided
+        let textures = TextureSlot::ALL.iter()
+            .map(|&slot| {
+                data.texture(slot).as_ref().unwrap_or(Material::fallback_texture(resources, slot))
+            })
+            .collect::<Vec<_>>();
+        if data.transmissive() {
+            (
+                MaterialKind::TexturedTransmissive,
+                Material::textured_layered_bind_group(
+                    device,
+                    &mesh_pass.textured_transmissive.part_bind_group_layout,
+                    factors_buf,
+                    &samplers,
+                    &textures,
+                ),
+            )
+        } else if data.layered() {
+            // Everything but transmission and thickness
+            (
+                MaterialKind::TexturedLayered,
+                Material::textured_layered_bind_group(
+                    device,
+                    &mesh_pass.textured_layered.part_bind_group_layout,
+                    factors_buf,
+                    &samplers,
+                    &textures[..TextureSlot::ALL.len() - 2],
+                ),
+            )
+        } else if data.emissive.is_some() {
+            (
+                MaterialKind::TexturedEmissive,
+                Material::textured_emissive_bind_group(
+                    device, mesh_pass,
+                    factors_buf,
+                    &samplers,
+                    texture,
+                    normal,
+                    metallic_roughness,
+                    ao,
+                    emissive,
+                ),
+            )
+        } else if data.metallic_roughness.is_some() || data.ao.is_some() {
+            (
+                MaterialKind::TexturedNormMat,
+                Material::textured_norm_mat_bind_group(
+                    device, mesh_pass,
+                    factors_buf,
+                    &samplers,
+                    texture,
+                    normal,
+                    metallic_roughness,
+                    ao,
+                ),
+            )
+        } else if data.normal.is_some() {
+            (
+                MaterialKind::TexturedNorm,
+                Material::textured_norm_bind_group(
+                    device, mesh_pass,
+                    factors_buf,
+                    &samplers,
+                    texture,
+                    normal,
+                ),
+            )
+        } else {
+            (
+                MaterialKind::Textured,
+                Material::textured_bind_group(
+                    device, mesh_pass, factors_buf, texture, &samplers.texture,
+                ),
+            )
+        }
+    }
+
+    fn textured_unlit_bind_group(
+        device: &mut wgpu::Device,
+        mesh_pass: &MeshPass,
+        factors_buf: &wgpu::Buffer,
+        texture: &wgpu::Texture,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        let texture_view = texture.create_view(&Default::default());
 
         // Create bind group
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout: &mesh_pass.textured_unlit.part_bind_group_layout,
             entries: &[
@@ -169,53 +567,27 @@ impl Material {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+                    resource: wgpu::BindingResource::Sampler(sampler),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
                     resource: wgpu::BindingResource::TextureView(&texture_view),
                 },
             ],
-        });
-
-        Material {
-            factors_buf,
-            kind: MaterialKind::TexturedUnlit,
-            bind_group,
-            factors,
-        }
+        })
     }
 
-    fn textured(
+    fn textured_bind_group(
         device: &mut wgpu::Device,
         mesh_pass: &MeshPass,
-        factors: MaterialFactors,
+        factors_buf: &wgpu::Buffer,
         texture: &wgpu::Texture,
-    ) -> Self {
-        let factors_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&[MaterialFactorsUpload::from(factors)]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
         let texture_view = texture.create_view(&Default::default());
 
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: None,
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Linear,
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
-            compare: None,
-            ..Default::default()
-        });
-
         // Create bind group
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout: &mesh_pass.textured.part_bind_group_layout,
             entries: &[
@@ -225,55 +597,29 @@ impl Material {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+                    resource: wgpu::BindingResource::Sampler(sampler),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
                     resource: wgpu::BindingResource::TextureView(&texture_view),
                 },
             ],
-        });
-
-        Material {
-            factors_buf,
-            kind: MaterialKind::Textured,
-            bind_group,
-            factors,
-        }
+        })
     }
 
-    fn textured_norm(
+    fn textured_norm_bind_group(
         device: &mut wgpu::Device,
         mesh_pass: &MeshPass,
-        factors: MaterialFactors,
+        factors_buf: &wgpu::Buffer,
+        samplers: &TextureSlots<Rc<wgpu::Sampler>>,
         texture: &wgpu::Texture,
         normal_texture: &wgpu::Texture,
-    ) -> Self {
-        let factors_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&[MaterialFactorsUpload::from(factors)]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
+    ) -> wgpu::BindGroup {
         let texture_view = texture.create_view(&Default::default());
         let normal_map_view = normal_texture.create_view(&Default::default());
 
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: None,
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Linear,
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
-            compare: None,
-            ..Default::default()
-        });
-
         // Create bind group
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout: &mesh_pass.textured_norm.part_bind_group_layout,
             entries: &[
@@ -283,7 +629,7 @@ impl Material {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+                    resource: wgpu::BindingResource::Sampler(&samplers.texture),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
@@ -293,53 +639,31 @@ impl Material {
                     binding: 3,
                     resource: wgpu::BindingResource::TextureView(&normal_map_view),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::Sampler(&samplers.normal),
+                },
             ],
-        });
-
-        Material {
-            factors_buf,
-            kind: MaterialKind::TexturedNorm,
-            bind_group,
-            factors,
-        }
+        })
     }
 
-    fn textured_norm_mat(
+    fn textured_norm_mat_bind_group(
         device: &mut wgpu::Device,
         mesh_pass: &MeshPass,
-        factors: MaterialFactors,
+        factors_buf: &wgpu::Buffer,
+        samplers: &TextureSlots<Rc<wgpu::Sampler>>,
         texture: &wgpu::Texture,
         normal_texture: &wgpu::Texture,
         metallic_roughness_texture: &wgpu::Texture,
         ao_texture: &wgpu::Texture,
-    ) -> Self {
-        let factors_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&[MaterialFactorsUpload::from(factors)]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
+    ) -> wgpu::BindGroup {
         let texture_view = texture.create_view(&Default::default());
         let normal_map_view = normal_texture.create_view(&Default::default());
         let metallic_roughness_map_view = metallic_roughness_texture.create_view(&Default::default());
         let ao_map_view = ao_texture.create_view(&Default::default());
 
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: None,
-            address_mode_u: wgpu::AddressMode::Repeat,
-            address_mode_v: wgpu::AddressMode::Repeat,
-            address_mode_w: wgpu::AddressMode::Repeat,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Linear,
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
-            compare: None,
-            ..Default::default()
-        });
-
         // Create bind group
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout: &mesh_pass.textured_norm_mat.part_bind_group_layout,
             entries: &[
@@ -349,7 +673,7 @@ impl Material {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+                    resource: wgpu::BindingResource::Sampler(&samplers.texture),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
@@ -367,55 +691,41 @@ impl Material {
                     binding: 5,
                     resource: wgpu::BindingResource::TextureView(&ao_map_view),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::Sampler(&samplers.normal),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::Sampler(&samplers.metallic_roughness),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::Sampler(&samplers.ao),
+                },
             ],
-        });
-
-        Material {
-            factors_buf,
-            kind: MaterialKind::TexturedNormMat,
-            bind_group,
-            factors,
-        }
+        })
     }
 
-    fn textured_emissive(
+    fn textured_emissive_bind_group(
         device: &mut wgpu::Device,
         mesh_pass: &MeshPass,
-        factors: MaterialFactors,
+        factors_buf: &wgpu::Buffer,
+        samplers: &TextureSlots<Rc<wgpu::Sampler>>,
         texture: &wgpu::Texture,
         normal_texture: &wgpu::Texture,
         metallic_roughness_texture: &wgpu::Texture,
         ao_texture: &wgpu::Texture,
         emissive_texture: &wgpu::Texture,
-    ) -> Self {
-        let factors_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&[MaterialFactorsUpload::from(factors)]),
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-        });
-
+    ) -> wgpu::BindGroup {
         let texture_view = texture.create_view(&Default::default());
         let normal_map_view = normal_texture.create_view(&Default::default());
         let metallic_roughness_map_view = metallic_roughness_texture.create_view(&Default::default());
         let ao_map_view = ao_texture.create_view(&Default::default());
         let emissive_map_view = emissive_texture.create_view(&Default::default());
 
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            label: None,
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Linear,
-            lod_min_clamp: -100.0,
-            lod_max_clamp: 100.0,
-            compare: None,
-            ..Default::default()
-        });
-
         // Create bind group
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout: &mesh_pass.textured_emissive.part_bind_group_layout,
             entries: &[
@@ -425,7 +735,7 @@ impl Material {
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
+                    resource: wgpu::BindingResource::Sampler(&samplers.texture),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
@@ -447,32 +757,127 @@ impl Material {
                     binding: 6,
                     resource: wgpu::BindingResource::TextureView(&emissive_map_view),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::Sampler(&samplers.normal),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::Sampler(&samplers.metallic_roughness),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: wgpu::BindingResource::Sampler(&samplers.ao),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: wgpu::BindingResource::Sampler(&samplers.emissive),
+                },
             ],
-        });
+        })
+    }
 
-        Material {
-            factors,
-            kind: MaterialKind::TexturedEmissive,
-            factors_buf,
-            bind_group,
+    /// Bind the first `textures.len()` slots in `TextureSlot::ALL` order.
+    fn textured_layered_bind_group(
+        device: &mut wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        factors_buf: &wgpu::Buffer,
+        samplers: &TextureSlots<Rc<wgpu::Sampler>>,
+        textures: &[&Rc<Texture>],
+    ) -> wgpu::BindGroup {
+        let views = textures.iter()
+            .map(|texture| texture.create_view(&Default::default()))
+            .collect::<Vec<_>>();
+
+        // The base slots are bound like `textured_emissive_bind_group`, each layer slot gets a
+        // texture and sampler binding pair after them
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(factors_buf.as_entire_buffer_binding()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&samplers.texture),
+            },
+        ];
+        for (i, (&slot, view)) in TextureSlot::ALL.iter().zip(&views).enumerate() {
+            let (texture_binding, sampler_binding) = match i {
+                0 => (2, None),
+                1..=4 => (2 + i as u32, Some(6 + i as u32)),
+                _ => (11 + 2 * (i as u32 - 5), Some(12 + 2 * (i as u32 - 5))),
+            };
+            entries.push(wgpu::BindGroupEntry {
+                binding: texture_binding,
+                resource: wgpu::BindingResource::TextureView(view),
+            });
+            if let Some(binding) = sampler_binding {
+                entries.push(wgpu::BindGroupEntry {
+                    binding,
+                    resource: wgpu::BindingResource::Sampler(samplers.get(slot)),
+                });
+            }
+        }
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout,
+            entries: &entries,
+        })
+    }
+
+    /// The neutral texture bound to a slot without a map.
+    fn fallback_texture(resources: &Resources, slot: TextureSlot) -> &Rc<Texture> {
+        match slot {
+            TextureSlot::Normal | TextureSlot::ClearcoatNormal => resources.flat_normal_texture(),
+            TextureSlot::Emissive => resources.black_texture(),
+            _ => resources.white_texture(),
         }
     }
 
-    fn untextured(
+    /// Create a material rendered by a pipeline registered with `MeshPass::register_material`.
+    /// `entries` must match the bind group layout entries the material was registered with.
+    pub fn custom(
         device: &mut wgpu::Device,
         mesh_pass: &MeshPass,
+        id: CustomMaterialId,
         factors: MaterialFactors,
+        entries: &[wgpu::BindGroupEntry],
     ) -> Self {
+        let pipeline = &mesh_pass.custom_materials[id];
+
+        let data = MaterialData {
+            factors,
+            lighting: true,
+            texture: None,
+            normal: None,
+            metallic_roughness: None,
+            ao: None,
+            emissive: None,
+            clearcoat: None,
+            clearcoat_roughness: None,
+            clearcoat_normal: None,
+            sheen_color: None,
+            sheen_roughness: None,
+            specular: None,
+            specular_color: None,
+            transmission: None,
+            thickness: None,
+            texture_transforms: Default::default(),
+            samplers: Default::default(),
+            texture_uv_sets: Default::default(),
+        };
+
         let factors_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
-            contents: bytemuck::cast_slice(&[MaterialFactorsUpload::from(factors)]),
+            contents: bytemuck::cast_slice(&[MaterialFactorsUpload::from(&data)]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        // Create bind group
+        // Create bind groups
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
-            layout: &mesh_pass.untextured.part_bind_group_layout,
+            layout: &pipeline.part_bind_group_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
@@ -480,18 +885,51 @@ impl Material {
                 },
             ],
         });
+        let custom_bind_group = Material::custom_bind_group_for(device, mesh_pass, id, entries);
 
         Material {
-            factors,
-            kind: MaterialKind::Untextured,
+            data,
+            factors_dirty: Cell::new(false),
+
+            kind: MaterialKind::Custom(id),
             factors_buf,
             bind_group,
+            custom_bind_group: Some(custom_bind_group),
         }
     }
 
+    /// Rebuild the bind group of a custom material, e.g. to swap its textures at runtime.
+    pub fn set_custom_bind_group(
+        &mut self,
+        device: &mut wgpu::Device,
+        mesh_pass: &MeshPass,
+        entries: &[wgpu::BindGroupEntry],
+    ) {
+        let id = match self.kind {
+            MaterialKind::Custom(id) => id,
+            _ => panic!("built-in material textures must be replaced with set_texture"),
+        };
+        self.custom_bind_group = Some(Material::custom_bind_group_for(device, mesh_pass, id, entries));
+    }
+
+    fn custom_bind_group_for(
+        device: &mut wgpu::Device,
+        mesh_pass: &MeshPass,
+        id: CustomMaterialId,
+        entries: &[wgpu::BindGroupEntry],
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: mesh_pass.custom_materials[id].custom_bind_group_layout.as_ref()
+                .expect("custom material pipeline without custom bind group layout"),
+            entries,
+        })
+    }
+
     pub fn kind(&self) -> MaterialKind { self.kind }
     pub fn factors_buf(&self) -> &wgpu::Buffer { &self.factors_buf }
     pub fn bind_group(&self) -> &wgpu::BindGroup { &self.bind_group }
+    pub fn custom_bind_group(&self) -> Option<&wgpu::BindGroup> { self.custom_bind_group.as_ref() }
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -508,6 +946,29 @@ pub struct MaterialFactorsUpload {
     pub pad2: [u32; 1],
     pub extra_emissive: [f32; 3],
     pub pad3: [u32; 1],
+    // Two rows per texture slot, in `TextureSlot` order
+    pub uv_transforms: [[f32; 4]; 10],
+    // Channel count of each texture slot, in `TextureSlot` order
+    pub texture_channels: [[i32; 4]; 2],
+    // UV set of each texture slot, in `TextureSlot` order
+    pub texture_uv_sets: [[i32; 4]; 2],
+    // The layers are appended so the offsets above stay valid for custom shaders
+    pub clearcoat: f32,
+    pub clearcoat_rough: f32,
+    pub specular: f32,
+    pub ior: f32,
+    pub sheen_color: [f32; 3],
+    pub sheen_rough: f32,
+    pub specular_color: [f32; 3],
+    pub transmission: f32,
+    pub attenuation_color: [f32; 3],
+    pub thickness: f32,
+    pub attenuation_distance: f32,
+    pub pad4: [u32; 3],
+    // Same as the arrays above, for the slots in `TextureSlot::LAYERS`
+    pub layer_uv_transforms: [[f32; 4]; 18],
+    pub layer_texture_channels: [[i32; 4]; 3],
+    pub layer_texture_uv_sets: [[i32; 4]; 3],
 }
 
 unsafe impl Pod for MaterialFactorsUpload { }
@@ -521,24 +982,84 @@ impl Default for MaterialFactors {
             rough: 1.0,
             emissive: [1.0, 1.0, 1.0],
             extra_emissive: [0.0, 0.0, 0.0],
+            clearcoat: 0.0,
+            clearcoat_rough: 0.0,
+            sheen_color: [0.0, 0.0, 0.0],
+            sheen_rough: 0.0,
+            specular: 1.0,
+            specular_color: [1.0, 1.0, 1.0],
+            ior: 1.5,
+            transmission: 0.0,
+            thickness: 0.0,
+            attenuation_color: [1.0, 1.0, 1.0],
+            attenuation_distance: f32::INFINITY,
         }
     }
 }
 
-impl From<MaterialFactors> for MaterialFactorsUpload {
-    fn from(v: MaterialFactors) -> Self {
+impl From<&MaterialData> for MaterialFactorsUpload {
+    fn from(data: &MaterialData) -> Self {
+        let v = data.factors;
+
+        let mut uv_transforms = [[0.0; 4]; 2 * TextureSlot::ALL.len()];
+        let mut texture_channels = [4; TextureSlot::ALL.len()];
+        let mut texture_uv_sets = [0; TextureSlot::ALL.len()];
+        for (i, &slot) in TextureSlot::ALL.iter().enumerate() {
+            let rows = data.texture_transforms.get(slot).rows();
+            uv_transforms[i * 2] = rows[0];
+            uv_transforms[i * 2 + 1] = rows[1];
+
+            if let Some(texture) = data.texture(slot) {
+                texture_channels[i] = texture.channel_count() as i32;
+            }
+            texture_uv_sets[i] = *data.texture_uv_sets.get(slot) as i32;
+        }
+
+        // The base slots come first, followed by the layer slots
+        let base_slots = TextureSlot::ALL.len() - TextureSlot::LAYERS.len();
+        let mut base_uv_transforms = [[0.0; 4]; 10];
+        base_uv_transforms.copy_from_slice(&uv_transforms[..2 * base_slots]);
+        let mut layer_uv_transforms = [[0.0; 4]; 18];
+        layer_uv_transforms.copy_from_slice(&uv_transforms[2 * base_slots..]);
+
         MaterialFactorsUpload {
             diffuse: v.diffuse,
             metal: v.metal,
             rough: v.rough,
             emissive: v.emissive,
             extra_emissive: v.extra_emissive,
+            uv_transforms: base_uv_transforms,
+            texture_channels: ivec4s(&texture_channels[..base_slots]),
+            texture_uv_sets: ivec4s(&texture_uv_sets[..base_slots]),
+            clearcoat: v.clearcoat,
+            clearcoat_rough: v.clearcoat_rough,
+            specular: v.specular,
+            ior: v.ior,
+            sheen_color: v.sheen_color,
+            sheen_rough: v.sheen_rough,
+            specular_color: v.specular_color,
+            transmission: v.transmission,
+            attenuation_color: v.attenuation_color,
+            thickness: v.thickness,
+            attenuation_distance: v.attenuation_distance,
+            layer_uv_transforms,
+            layer_texture_channels: ivec4s(&texture_channels[base_slots..]),
+            layer_texture_uv_sets: ivec4s(&texture_uv_sets[base_slots..]),
 
             pad0: [0; 3],
             pad1: [0; 3],
             pad2: [0; 1],
             pad3: [0; 1],
+            pad4: [0; 3],
         }
     }
 }
 
+/// Pack one value per texture slot into an array of std140 `ivec4`s.
+fn ivec4s<const N: usize>(values: &[i32]) -> [[i32; 4]; N] {
+    let mut packed = [[0; 4]; N];
+    for (i, &value) in values.iter().enumerate() {
+        packed[i / 4][i % 4] = value;
+    }
+    packed
+}
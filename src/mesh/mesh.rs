@@ -1,28 +1,59 @@
+use std::mem;
+use std::rc::Rc;
+
 use ultraviolet::{Mat4, Rotor3, Vec3};
 use wgpu::util::DeviceExt;
 
+use crate::resources::Resources;
 use super::{
-    mesh_part::{MeshPart, MeshPartData},
-    mesh_pass::MeshPass,
+    consts::{MAX_JOINTS, PART_UNIFORM_STRIDE},
+    mesh_asset::MeshAsset,
+    mesh_part::{MeshPart, MeshPartData, MorphBuffers},
+    mesh_pass::{MeshPass, PartUniforms},
 };
 
 pub struct Mesh {
     pub position: Vec3,
     pub rotation: Rotor3,
     pub scale: Vec3,
-    pub parts: Vec<MeshPart>,
+    /// The pose of a skinned mesh: for each joint, the transform from the mesh's bind pose to
+    /// the posed mesh, in mesh space. Starts as the bind pose (all identity). Ignored when the
+    /// mesh has a skin in its `Scene`, which poses it from joint nodes instead.
+    pub joint_matrices: Vec<Mat4>,
+    /// The weight of each morph target of the mesh's parts, usually set by animations
+    pub morph_weights: Vec<f32>,
 
+    asset: Rc<MeshAsset>,
     bind_group: wgpu::BindGroup,
     uniform_buf: wgpu::Buffer,
+    joint_buf: Option<wgpu::Buffer>,
+    // The vertices each morphed part is blended into, indexed like the parts
+    morph_buffers: Vec<Option<MorphBuffers>>,
 }
 
 impl Mesh {
     /// Create a new mesh from its "parts" (aka primitives). A `MeshPart` has a triangle mesh and a
     /// single material. A multi-material `Mesh` must be composed of multiple `MeshPart`s.
+    ///
+    /// The parts are uploaded for this mesh alone. Use `MeshAsset` and `Mesh::from_asset` to
+    /// place the same model many times.
     pub fn from_parts(
         device: &mut wgpu::Device,
+        resources: &mut Resources,
         mesh_pass: &MeshPass,
         mesh_parts: &[MeshPartData],
+    ) -> Mesh {
+        let asset = MeshAsset::from_parts(device, resources, mesh_pass, mesh_parts);
+        Mesh::from_asset(device, mesh_pass, Rc::new(asset))
+    }
+
+    /// Create a mesh drawing a shared asset. Only the mesh's transform, joint matrices and
+    /// morphed vertices are allocated. Panics if the asset's skin vertices reference more than
+    /// 256 joints, which the glTF loader reports as an error.
+    pub fn from_asset(
+        device: &mut wgpu::Device,
+        mesh_pass: &MeshPass,
+        asset: Rc<MeshAsset>,
     ) -> Mesh {
         let transform = Mat4::identity();
         let transform_ref: &[f32; 16] = transform.as_array();
@@ -32,44 +63,118 @@ impl Mesh {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        // Create bind group
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: None,
-            layout: &mesh_pass.mesh_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer(uniform_buf.as_entire_buffer_binding()),
-                },
-            ],
+        let joint_count = asset.joint_count();
+        let joint_buf = joint_count.map(|joint_count| {
+            assert!(joint_count <= MAX_JOINTS, "skinned meshes support up to {} joints", MAX_JOINTS);
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("mesh-joint-buf"),
+                size: (MAX_JOINTS * mem::size_of::<Mat4>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
         });
 
-        let mut parts = Vec::new();
-        for part_data in mesh_parts {
-            parts.push(MeshPart::new(
-                device, mesh_pass,
-                &part_data,
-            ));
-        }
+        let morph_buffers = asset.parts().iter()
+            .map(|part| part.create_morph_buffers(device, mesh_pass))
+            .collect();
+
+        let bind_group = create_mesh_bind_group(
+            device, mesh_pass, &uniform_buf, joint_buf.as_ref(), asset.part_buf(),
+        );
 
         Mesh {
             position: Vec3::zero(),
             rotation: Rotor3::identity(),
             scale: Vec3::broadcast(1.0),
 
-            parts,
+            joint_matrices: vec![Mat4::identity(); joint_count.unwrap_or(0)],
+            morph_weights: vec![0.0; asset.morph_target_count()],
+            asset,
             bind_group,
             uniform_buf,
+            joint_buf,
+            morph_buffers,
         }
     }
 
+    pub fn asset(&self) -> &Rc<MeshAsset> { &self.asset }
+    pub fn parts(&self) -> &[MeshPart] { self.asset.parts() }
+    /// The parts of the mesh, if its asset isn't shared with other meshes.
+    pub fn parts_mut(&mut self) -> Option<&mut [MeshPart]> {
+        Rc::get_mut(&mut self.asset).map(MeshAsset::parts_mut)
+    }
     pub fn bind_group(&self) -> &wgpu::BindGroup { &self.bind_group }
     pub fn uniform_buf(&self) -> &wgpu::Buffer { &self.uniform_buf }
+    pub fn joint_buf(&self) -> Option<&wgpu::Buffer> { self.joint_buf.as_ref() }
+    /// The dynamic offset of a part's uniforms in the mesh bind group.
+    pub fn part_uniform_offset(&self, part: usize) -> wgpu::DynamicOffset {
+        (part * PART_UNIFORM_STRIDE) as wgpu::DynamicOffset
+    }
+    /// The vertices a part is drawn with: its morphed vertices if it has morph targets.
+    pub fn part_vertex_buf(&self, part: usize) -> &wgpu::Buffer {
+        match self.morph_buffers[part] {
+            Some(ref morph_buffers) => morph_buffers.vertex_buf(),
+            None => self.parts()[part].vertex_buf(),
+        }
+    }
+
+    pub(crate) fn morph_buffers(&self, part: usize) -> Option<&MorphBuffers> {
+        self.morph_buffers[part].as_ref()
+    }
 
     pub fn transform(&self) -> Mat4 {
         Mat4::from_translation(self.position) *
-            Mat4::from_nonuniform_scale(self.scale.into()) *
-            self.rotation.into_matrix().into_homogeneous()
+            self.rotation.into_matrix().into_homogeneous() *
+            Mat4::from_nonuniform_scale(self.scale.into())
     }
 }
 
+/// Each part's uniforms, spaced out so they can be bound with dynamic offsets.
+pub(crate) fn create_part_buf(device: &mut wgpu::Device, parts: &[MeshPart]) -> wgpu::Buffer {
+    let mut part_uniforms = vec![0u8; PART_UNIFORM_STRIDE * parts.len().max(1)];
+    for (i, part) in parts.iter().enumerate() {
+        let uniforms = PartUniforms::from(part.dequantization());
+        let bytes = bytemuck::bytes_of(&uniforms);
+        part_uniforms[i * PART_UNIFORM_STRIDE..][..bytes.len()].copy_from_slice(bytes);
+    }
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("mesh-part-buf"),
+        contents: &part_uniforms,
+        usage: wgpu::BufferUsages::UNIFORM,
+    })
+}
+
+/// The set 1 bind group of a mesh. Meshes without a skin bind the mesh pass's empty joints.
+pub(crate) fn create_mesh_bind_group(
+    device: &mut wgpu::Device,
+    mesh_pass: &MeshPass,
+    uniform_buf: &wgpu::Buffer,
+    joint_buf: Option<&wgpu::Buffer>,
+    part_buf: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: None,
+        layout: &mesh_pass.mesh_bind_group_layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(uniform_buf.as_entire_buffer_binding()),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Buffer(
+                    joint_buf.unwrap_or(&mesh_pass.empty_joint_buf).as_entire_buffer_binding(),
+                ),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: part_buf,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(mem::size_of::<PartUniforms>() as u64),
+                }),
+            },
+        ],
+    })
+}
+
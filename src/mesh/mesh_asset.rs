@@ -0,0 +1,56 @@
+use crate::resources::Resources;
+use super::{
+    mesh::create_part_buf,
+    mesh_part::{MeshPart, MeshPartData},
+    mesh_pass::MeshPass,
+};
+
+/// The GPU geometry and materials of a model, uploaded once and shared through an `Rc` by every
+/// `Mesh` or `InstancedMesh` placing it. Each placement only adds its own transform, joints and
+/// morph weights.
+///
+/// Changing a part's material changes it for every placement. `Rc::get_mut` gives access to the
+/// parts of an asset that isn't shared.
+pub struct MeshAsset {
+    parts: Vec<MeshPart>,
+    joint_count: Option<usize>,
+    morph_target_count: usize,
+    part_buf: wgpu::Buffer,
+}
+
+impl MeshAsset {
+    /// Upload a model's "parts" (aka primitives). A `MeshPart` has a triangle mesh and a single
+    /// material. A multi-material model must be composed of multiple `MeshPart`s.
+    pub fn from_parts(
+        device: &mut wgpu::Device,
+        resources: &mut Resources,
+        mesh_pass: &MeshPass,
+        mesh_parts: &[MeshPartData],
+    ) -> MeshAsset {
+        // Skinned meshes get room for every joint any of their parts reference
+        let joint_count = mesh_parts.iter()
+            .filter_map(|part| part.geometry.skin.as_ref())
+            .flat_map(|skin| skin.iter().flat_map(|vertex| vertex.joints.iter()))
+            .map(|&joint| joint as usize + 1)
+            .max();
+
+        let parts = mesh_parts.iter()
+            .map(|part_data| MeshPart::new(device, resources, mesh_pass, part_data))
+            .collect::<Vec<_>>();
+        let morph_target_count = parts.iter()
+            .map(MeshPart::morph_target_count)
+            .max()
+            .unwrap_or(0);
+        let part_buf = create_part_buf(device, &parts);
+
+        MeshAsset { parts, joint_count, morph_target_count, part_buf }
+    }
+
+    pub fn parts(&self) -> &[MeshPart] { &self.parts }
+    pub fn parts_mut(&mut self) -> &mut [MeshPart] { &mut self.parts }
+    /// The number of joints the skin vertices of the parts reference, if any part is skinned.
+    pub fn joint_count(&self) -> Option<usize> { self.joint_count }
+    /// The most morph targets of any part.
+    pub fn morph_target_count(&self) -> usize { self.morph_target_count }
+    pub(crate) fn part_buf(&self) -> &wgpu::Buffer { &self.part_buf }
+}
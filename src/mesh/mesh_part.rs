@@ -1,9 +1,15 @@
+use std::cell::RefCell;
+use std::mem;
+
 use wgpu::util::DeviceExt;
 
+use crate::resources::Resources;
 use super::{
-    geometry::MeshPartGeometry,
+    consts::MAX_MORPH_TARGETS,
+    geometry::{Dequantization, MeshPartGeometry, MorphVertex, Topology, Vertex, VertexFormat},
     material::{Material, MaterialData},
-    mesh_pass::MeshPass,
+    mesh_pass::{MeshPass, MorphUniforms},
+    mesh_pipeline::PartLayout,
 };
 
 #[derive(Clone)]
@@ -17,23 +23,100 @@ pub struct MeshPart {
 
     // Geometry
     vertex_buf: wgpu::Buffer,
+    extra_buf: Option<wgpu::Buffer>,
+    skin_buf: Option<wgpu::Buffer>,
     index_buf: wgpu::Buffer,
     index_count: usize,
+    vertex_count: usize,
+    topology: Topology,
+    vertex_format: VertexFormat,
+    dequantization: Dequantization,
+    morph: Option<MorphTargets>,
+}
+
+// The morph target deltas of a part, shared by every mesh drawing it
+enum MorphTargets {
+    // Blended by the morph compute pass
+    Gpu {
+        target_count: usize,
+        delta_buf: wgpu::Buffer,
+    },
+    // Blended on the CPU, on devices without storage buffers or when the deltas don't fit in one
+    Cpu {
+        target_count: usize,
+        vertices: Vec<Vertex>,
+        deltas: Vec<MorphVertex>,
+    },
+}
+
+/// The copy of a morphed part's vertices a mesh blends its morph weights into. Each mesh has its
+/// own, so meshes sharing a part can be posed independently.
+pub(crate) struct MorphBuffers {
+    vertex_buf: wgpu::Buffer,
+    kind: MorphBuffersKind,
+}
+
+enum MorphBuffersKind {
+    Gpu {
+        uniform_buf: wgpu::Buffer,
+        bind_group: wgpu::BindGroup,
+    },
+    // The weights last blended, so unchanged poses aren't blended again
+    Cpu {
+        weights: RefCell<Option<Vec<f32>>>,
+    },
 }
 
 impl MeshPart {
     pub fn new(
         device: &mut wgpu::Device,
+        resources: &mut Resources,
         mesh_pass: &MeshPass,
         data: &MeshPartData,
     ) -> Self {
-        let material = Material::new(device, mesh_pass, &data.material);
+        let material = Material::new(device, resources, mesh_pass, &data.material);
 
-        // Create the vertex and index buffers
-        let vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: None,
-            contents: bytemuck::cast_slice(&data.geometry.vertices),
-            usage: wgpu::BufferUsages::VERTEX,
+        // Create the vertex and index buffers. Morph targets are blended into full vertices.
+        let morphed = !data.geometry.morph_targets.is_empty();
+        let vertex_format = if morphed { VertexFormat::Full } else { data.geometry.vertex_format };
+        let (vertex_buf, dequantization) = match vertex_format {
+            VertexFormat::Full => {
+                let vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(&data.geometry.vertices),
+                    usage: if morphed && mesh_pass.morph_bind_group_layout.is_some() {
+                        wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE
+                    } else {
+                        wgpu::BufferUsages::VERTEX
+                    },
+                });
+                (vertex_buf, Dequantization::default())
+            }
+            VertexFormat::Compact => {
+                let (vertices, dequantization) = data.geometry.quantized_vertices();
+                let vertex_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: None,
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                });
+                (vertex_buf, dequantization)
+            }
+        };
+        let extra_buf = data.geometry.extra_attributes.as_ref().map(|extra| {
+            assert_eq!(extra.len(), data.geometry.vertices.len(), "one extra vertex per vertex");
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(extra),
+                usage: wgpu::BufferUsages::VERTEX,
+            })
+        });
+        let skin_buf = data.geometry.skin.as_ref().map(|skin| {
+            assert_eq!(skin.len(), data.geometry.vertices.len(), "one skin vertex per vertex");
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: bytemuck::cast_slice(skin),
+                usage: wgpu::BufferUsages::VERTEX,
+            })
         });
         let index_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
@@ -41,16 +124,241 @@ impl MeshPart {
             usage: wgpu::BufferUsages::INDEX,
         });
 
+        let morph = if morphed {
+            Some(MorphTargets::new(device, mesh_pass, &data.geometry))
+        } else {
+            None
+        };
+
         MeshPart {
             material,
-            vertex_buf, index_buf,
+            vertex_buf, extra_buf, skin_buf, index_buf,
             index_count: data.geometry.indices.len(),
+            vertex_count: data.geometry.vertices.len(),
+            topology: data.geometry.topology,
+            vertex_format,
+            dequantization,
+            morph,
         }
     }
 
     pub fn vertex_buf(&self) -> &wgpu::Buffer { &self.vertex_buf }
+    /// The colors and second UV set of a part with `extra_attributes`.
+    pub fn extra_buf(&self) -> Option<&wgpu::Buffer> { self.extra_buf.as_ref() }
+    /// The joints and weights of a skinned part, drawn with the skinned pipeline variant.
+    pub fn skin_buf(&self) -> Option<&wgpu::Buffer> { self.skin_buf.as_ref() }
     pub fn index_buf(&self) -> &wgpu::Buffer { &self.index_buf }
     pub fn index_count(&self) -> usize { self.index_count }
+    pub fn vertex_count(&self) -> usize { self.vertex_count }
+    pub fn topology(&self) -> Topology { self.topology }
+    pub fn vertex_format(&self) -> VertexFormat { self.vertex_format }
+    /// The vertex layout this part is drawn with. Skins are ignored on lines and points.
+    pub fn layout(&self) -> PartLayout {
+        PartLayout {
+            topology: self.topology,
+            skinned: self.skin_buf.is_some() && self.topology == Topology::Triangles,
+            extra_attributes: self.extra_buf.is_some(),
+            vertex_format: self.vertex_format,
+            instanced: false,
+        }
+    }
+    pub fn morph_target_count(&self) -> usize {
+        self.morph.as_ref().map_or(0, MorphTargets::target_count)
+    }
+
+    pub(crate) fn dequantization(&self) -> Dequantization { self.dequantization }
+
+    /// Create the buffers a mesh blends this part's morph targets into, if it has any.
+    pub(crate) fn create_morph_buffers(
+        &self,
+        device: &mut wgpu::Device,
+        mesh_pass: &MeshPass,
+    ) -> Option<MorphBuffers> {
+        let morph = self.morph.as_ref()?;
+        let vertex_buf_size = (self.vertex_count * mem::size_of::<Vertex>()) as wgpu::BufferAddress;
+
+        let delta_buf = match *morph {
+            MorphTargets::Gpu { ref delta_buf, .. } => delta_buf,
+            MorphTargets::Cpu { .. } => {
+                // Written whenever the mesh's weights change
+                let vertex_buf = device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some("morph-vertex-buf"),
+                    size: vertex_buf_size,
+                    usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                });
+                return Some(MorphBuffers {
+                    vertex_buf,
+                    kind: MorphBuffersKind::Cpu { weights: RefCell::new(None) },
+                });
+            }
+        };
+
+        let uniform_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("morph-uniform-buf"),
+            size: mem::size_of::<MorphUniforms>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        // Filled by the morph pre-pass every frame before the part is drawn
+        let vertex_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("morph-vertex-buf"),
+            size: vertex_buf_size,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: mesh_pass.morph_bind_group_layout.as_ref()
+                .expect("GPU morph targets without a morph pipeline"),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.vertex_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: delta_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: vertex_buf.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: uniform_buf.as_entire_binding(),
+                },
+            ],
+        });
+
+        Some(MorphBuffers { vertex_buf, kind: MorphBuffersKind::Gpu { uniform_buf, bind_group } })
+    }
+
+    /// Upload a mesh's morph weights for the compute pass to blend, or blend them now if the
+    /// part is blended on the CPU and they changed.
+    pub(crate) fn upload_morph_weights(
+        &self,
+        queue: &wgpu::Queue,
+        morph_buffers: &MorphBuffers,
+        weights: &[f32],
+    ) {
+        match (&morph_buffers.kind, &self.morph) {
+            (MorphBuffersKind::Gpu { uniform_buf, .. }, _) => {
+                let mut uniforms = MorphUniforms {
+                    vertex_count: self.vertex_count as u32,
+                    target_count: self.morph_target_count() as u32,
+                    _pad: [0; 2],
+                    weights: [0.0; MAX_MORPH_TARGETS],
+                };
+                for (weight, &value) in uniforms.weights.iter_mut().zip(weights) {
+                    *weight = value;
+                }
+                queue.write_buffer(uniform_buf, 0, bytemuck::cast_slice(&[uniforms]));
+            }
+            (
+                MorphBuffersKind::Cpu { weights: blended_weights },
+                Some(MorphTargets::Cpu { vertices, deltas, .. }),
+            ) => {
+                let mut blended_weights = blended_weights.borrow_mut();
+                if blended_weights.as_deref() == Some(weights) {
+                    return;
+                }
+                let morphed = blend_morph_targets(vertices, deltas, weights);
+                queue.write_buffer(&morph_buffers.vertex_buf, 0, bytemuck::cast_slice(&morphed));
+                *blended_weights = Some(weights.to_vec());
+            }
+            _ => unreachable!("morph buffers don't match the part's morph targets"),
+        }
+    }
+}
+
+impl MorphBuffers {
+    /// The vertices of a part with morph targets after blending, drawn in place of its
+    /// `vertex_buf`.
+    pub(crate) fn vertex_buf(&self) -> &wgpu::Buffer { &self.vertex_buf }
+    /// The bind group of the morph compute pass, if the part is blended on the GPU.
+    pub(crate) fn bind_group(&self) -> Option<&wgpu::BindGroup> {
+        match self.kind {
+            MorphBuffersKind::Gpu { ref bind_group, .. } => Some(bind_group),
+            MorphBuffersKind::Cpu { .. } => None,
+        }
+    }
+}
+
+impl MorphTargets {
+    fn new(device: &mut wgpu::Device, mesh_pass: &MeshPass, geometry: &MeshPartGeometry) -> Self {
+        let mut targets = &geometry.morph_targets[..];
+        if targets.len() > MAX_MORPH_TARGETS {
+            log::warn!(
+                "mesh part has {} morph targets, only the first {} are blended",
+                targets.len(), MAX_MORPH_TARGETS,
+            );
+            targets = &targets[..MAX_MORPH_TARGETS];
+        }
+        for target in targets {
+            assert_eq!(target.len(), geometry.vertices.len(), "one morph delta per vertex");
+        }
+
+        let target_count = targets.len();
+        let deltas = targets.concat();
+        let deltas_size = (deltas.len() * mem::size_of::<MorphVertex>()) as u64;
+        let fits_storage_buffer =
+            deltas_size <= device.limits().max_storage_buffer_binding_size as u64;
+        if mesh_pass.morph_bind_group_layout.is_none() || !fits_storage_buffer {
+            return MorphTargets::Cpu { target_count, vertices: geometry.vertices.clone(), deltas };
+        }
+
+        let delta_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("morph-delta-buf"),
+            contents: bytemuck::cast_slice(&deltas),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        MorphTargets::Gpu { target_count, delta_buf }
+    }
+
+    fn target_count(&self) -> usize {
+        match *self {
+            MorphTargets::Gpu { target_count, .. } | MorphTargets::Cpu { target_count, .. } => {
+                target_count
+            }
+        }
+    }
+}
+
+/// Blend morph target deltas into vertices like the morph compute shader: positions, normals
+/// and tangents are offset by each target's delta times its weight, and directions are
+/// renormalized.
+fn blend_morph_targets(vertices: &[Vertex], deltas: &[MorphVertex], weights: &[f32]) -> Vec<Vertex> {
+    let mut morphed = vertices.to_vec();
+    if vertices.is_empty() {
+        return morphed;
+    }
+    for (target, &weight) in deltas.chunks(vertices.len()).zip(weights) {
+        if weight == 0.0 {
+            continue;
+        }
+        for (vertex, delta) in morphed.iter_mut().zip(target) {
+            for c in 0..3 {
+                vertex.pos[c] += weight * delta.pos[c];
+                vertex.norm[c] += weight * delta.norm[c];
+                vertex.tang[c] += weight * delta.tang[c];
+            }
+        }
+    }
+
+    let normalize = |v: &mut [f32]| {
+        let length = v.iter().map(|c| c * c).sum::<f32>().sqrt();
+        if length > 0.0 {
+            v.iter_mut().for_each(|c| *c /= length);
+        }
+    };
+    for vertex in &mut morphed {
+        normalize(&mut vertex.norm);
+        normalize(&mut vertex.tang[..3]);
+    }
+    morphed
 }
 
 pub fn mesh_parts_bbox(parts: &[MeshPartData]) -> ([f32; 3], [f32; 3]) {
@@ -71,3 +379,36 @@ pub fn mesh_parts_bbox(parts: &[MeshPartData]) -> ([f32; 3], [f32; 3]) {
     (mins, maxes)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_morph_blending() {
+        let vertex = Vertex {
+            pos: [0.0, 0.0, 0.0],
+            norm: [0.0, 0.0, 1.0],
+            tang: [1.0, 0.0, 0.0, -1.0],
+            tex_coord: [0.25, 0.75],
+        };
+        let up = MorphVertex { pos: [0.0, 2.0, 0.0], norm: [0.0, 1.0, -1.0], tang: [0.0; 3] };
+        let right = MorphVertex { pos: [1.0, 0.0, 0.0], norm: [0.0; 3], tang: [0.0; 3] };
+        // Two vertices, with the targets' deltas back to back
+        let deltas = [up, MorphVertex::default(), right, right];
+
+        let morphed = blend_morph_targets(&[vertex, vertex], &deltas, &[0.5, 2.0]);
+        assert_eq!(morphed[0].pos, [2.0, 1.0, 0.0]);
+        assert_eq!(morphed[1].pos, [2.0, 0.0, 0.0]);
+        // Directions are renormalized, the rest of the vertex is kept
+        let half = std::f32::consts::FRAC_1_SQRT_2;
+        assert!(morphed[0].norm.iter().zip(&[0.0, half, half]).all(|(a, b)| (a - b).abs() < 1e-6));
+        assert_eq!(morphed[0].tang, [1.0, 0.0, 0.0, -1.0]);
+        assert_eq!(morphed[0].tex_coord, [0.25, 0.75]);
+
+        // Missing and zero weights leave the vertices alone
+        let morphed = blend_morph_targets(&[vertex, vertex], &deltas, &[0.0]);
+        assert_eq!(morphed[0].pos, [0.0, 0.0, 0.0]);
+        assert_eq!(morphed[1].norm, [0.0, 0.0, 1.0]);
+    }
+}
@@ -1,30 +1,47 @@
 use std::mem;
 
+use slotmap::DenseSlotMap;
 use wgpu::util::DeviceExt;
 
 use crate::{PointLight, SpotLight}; 
 use super::{
     super::Scene,
-    consts::DEPTH_FORMAT,
+    consts::{DEPTH_FORMAT, MAX_JOINTS, MAX_MORPH_TARGETS},
+    custom_material::{CustomMaterialDescriptor, CustomMaterialId},
+    geometry::{Dequantization, ExtraVertex},
     material::MaterialKind,
+    mesh_part::{MeshPart, MorphBuffers},
     mesh_pipeline::MeshPipeline,
+    scene_color::SceneColor,
 };
 
 pub struct MeshPass {
     pub(crate) global_bind_group_layout: wgpu::BindGroupLayout,
     pub(crate) mesh_bind_group_layout: wgpu::BindGroupLayout,
     pub(crate) global_bind_group: wgpu::BindGroup,
+    // Bound in place of the joint matrices of meshes without a skin
+    pub(crate) empty_joint_buf: wgpu::Buffer,
+    // Bound as the extra attributes of every vertex of parts without them
+    default_extra_buf: wgpu::Buffer,
+    // Only created if the device can run the morph compute shader, otherwise morph targets are
+    // blended on the CPU
+    pub(crate) morph_bind_group_layout: Option<wgpu::BindGroupLayout>,
+    morph_pipeline: Option<wgpu::ComputePipeline>,
 
-    pub(crate) untextured: MeshPipeline,
     pub(crate) textured_unlit: MeshPipeline,
     pub(crate) textured: MeshPipeline,
     pub(crate) textured_norm: MeshPipeline,
     pub(crate) textured_norm_mat: MeshPipeline,
     pub(crate) textured_emissive: MeshPipeline,
+    pub(crate) textured_layered: MeshPipeline,
+    pub(crate) textured_transmissive: MeshPipeline,
+    pub(crate) custom_materials: DenseSlotMap<CustomMaterialId, MeshPipeline>,
 
+    target_format: wgpu::TextureFormat,
     global_buf: wgpu::Buffer,
     pub(crate) depth_texture: wgpu::TextureView,
     pub(crate) bloom_texture: wgpu::TextureView,
+    scene_color: SceneColor,
 }
 
 impl MeshPass {
@@ -41,8 +58,20 @@ impl MeshPass {
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 label: None,
                 entries: &[
+                    // Transform, also read by the transmissive shader to scale the volume thickness
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    // Joint matrices, only read by the skinned vertex shaders
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
                         visibility: wgpu::ShaderStages::VERTEX,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
@@ -51,6 +80,19 @@ impl MeshPass {
                         },
                         count: None,
                     },
+                    // The uniforms of the part being drawn, offset into the mesh's part buffer
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: true,
+                            min_binding_size: wgpu::BufferSize::new(
+                                mem::size_of::<PartUniforms>() as wgpu::BufferAddress,
+                            ),
+                        },
+                        count: None,
+                    },
                 ],
             });
         let global_bind_group_layout =
@@ -72,6 +114,66 @@ impl MeshPass {
                 ],
             });
 
+        // Morph targets are blended into a copy of a part's vertices before it's drawn, by a
+        // compute shader reading them from storage buffers. Devices without storage buffers, like
+        // WebGL2, blend them on the CPU instead.
+        let gpu_morphing = device.limits().max_storage_buffers_per_shader_stage >= 3;
+        let (morph_bind_group_layout, morph_pipeline) = if gpu_morphing {
+            let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+                binding,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            };
+            let morph_bind_group_layout =
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("morph-bind-group-layout"),
+                    entries: &[
+                        // Base vertices
+                        storage_entry(0, true),
+                        // Morph target deltas
+                        storage_entry(1, true),
+                        // Morphed vertices
+                        storage_entry(2, false),
+                        // Vertex count and weights
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: wgpu::ShaderStages::COMPUTE,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: wgpu::BufferSize::new(
+                                    mem::size_of::<MorphUniforms>() as wgpu::BufferAddress,
+                                ),
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+            let morph_pipeline_layout =
+                device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                    label: None,
+                    push_constant_ranges: &[],
+                    bind_group_layouts: &[&morph_bind_group_layout],
+                });
+            let morph_module = device.create_shader_module(
+                &wgpu::include_spirv!("shaders/morph_comp.spv")
+            );
+            let morph_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("morph-pipeline"),
+                layout: Some(&morph_pipeline_layout),
+                module: &morph_module,
+                entry_point: "main",
+            });
+            (Some(morph_bind_group_layout), Some(morph_pipeline))
+        } else {
+            (None, None)
+        };
+
         let global_buf = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("mesh-global-buf"),
             size: std::mem::size_of::<GlobalUniforms>() as wgpu::BufferAddress,
@@ -79,6 +181,19 @@ impl MeshPass {
             mapped_at_creation: false,
         });
 
+        let empty_joint_buf = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("mesh-empty-joint-buf"),
+            size: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+
+        let default_extra_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("mesh-default-extra-buf"),
+            contents: bytemuck::cast_slice(&[ExtraVertex::default()]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
         let global_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: None,
             layout: &global_bind_group_layout,
@@ -121,9 +236,6 @@ impl MeshPass {
         // Done
         queue.submit(Some(init_encoder.finish()));
 
-        let untextured = MeshPipeline::untextured(
-            surface_config.format, device, &global_bind_group_layout, &mesh_bind_group_layout,
-        );
         let textured_unlit = MeshPipeline::textured_unlit(
             surface_config.format, device, &global_bind_group_layout, &mesh_bind_group_layout,
         );
@@ -139,22 +251,36 @@ impl MeshPass {
         let textured_emissive = MeshPipeline::textured_emissive(
             surface_config.format, device, &global_bind_group_layout, &mesh_bind_group_layout,
         );
+        let textured_layered = MeshPipeline::textured_layered(
+            surface_config.format, device, &global_bind_group_layout, &mesh_bind_group_layout,
+        );
+        let textured_transmissive = MeshPipeline::textured_transmissive(
+            surface_config.format, device, &global_bind_group_layout, &mesh_bind_group_layout,
+        );
 
         MeshPass {
             global_bind_group_layout,
             mesh_bind_group_layout,
             global_bind_group,
             global_buf,
+            empty_joint_buf,
+            default_extra_buf,
+            morph_bind_group_layout,
+            morph_pipeline,
 
-            untextured,
             textured_unlit,
             textured,
             textured_norm,
             textured_norm_mat,
             textured_emissive,
+            textured_layered,
+            textured_transmissive,
+            custom_materials: DenseSlotMap::new(),
 
+            target_format: surface_config.format,
             depth_texture: depth_texture.create_view(&Default::default()),
             bloom_texture: bloom_texture.create_view(&Default::default()),
+            scene_color: SceneColor::new(device, surface_config),
         }
     }
 
@@ -190,23 +316,45 @@ impl MeshPass {
             format: surface_config.format,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
         }).create_view(&Default::default());
+
+        self.scene_color = SceneColor::new(device, surface_config);
+    }
+
+    /// Build the pipeline for a user-defined material. The returned id can be used to create
+    /// materials with `Material::custom`.
+    pub fn register_material(
+        &mut self,
+        device: &mut wgpu::Device,
+        desc: &CustomMaterialDescriptor,
+    ) -> CustomMaterialId {
+        let pipeline = MeshPipeline::custom(
+            self.target_format,
+            device,
+            &self.global_bind_group_layout,
+            &self.mesh_bind_group_layout,
+            desc,
+        );
+        self.custom_materials.insert(pipeline)
     }
 
     pub fn render(
         &mut self,
         device: &wgpu::Device,
+        queue: &wgpu::Queue,
         render_target: &wgpu::TextureView,
         encoder: &mut wgpu::CommandEncoder,
         scene: &Scene,
     ) {
+        scene.update_transforms();
+
         // Prepare to upload point lights
         let null_point_light = PointLightUpload {
             pos: [0.0; 3], intensity: 0.0, color: [0.0; 3], _pad: 0,
         };
         let mut point_lights = [null_point_light; 32];
-        for (i, light) in scene.point_lights.values().enumerate() {
+        for (i, light) in scene.world_point_lights().enumerate() {
             if i >= 32 { break; }
-            point_lights[i] = light.into();
+            point_lights[i] = (&light).into();
         }
 
         // Prepare to upload spot lights
@@ -221,20 +369,16 @@ impl MeshPass {
             _pad0: 0, _pad1: 0, _pad2: 0,
         };
         let mut spot_lights = [null_spot_light; 32];
-        for (i, light) in scene.spot_lights.values().enumerate() {
+        for (i, light) in scene.world_spot_lights().enumerate() {
             if i >= 32 { break; }
-            spot_lights[i] = light.into();
+            spot_lights[i] = (&light).into();
         }
 
         // Upload global uniforms
-        let view_proj = scene.camera.total_matrix();
+        let (view_proj, camera_pos) = scene.camera_view();
         let global_uniforms = GlobalUniforms {
             view_proj: *view_proj.as_array(),
-            camera_pos: [
-                scene.camera.position().x,
-                scene.camera.position().y,
-                scene.camera.position().z,
-            ],
+            camera_pos: [camera_pos.x, camera_pos.y, camera_pos.z],
             num_point_lights: scene.point_lights.len() as i32,
             point_lights: point_lights,
             num_spot_lights: scene.spot_lights.len() as i32,
@@ -252,91 +396,257 @@ impl MeshPass {
         );
 
         // Upload mesh transform matrices
-        for mesh in scene.meshes.values() {
-            let transform = mesh.transform();
+        for (id, mesh) in scene.meshes.iter() {
+            let transform = scene.mesh_world_transform(id);
             let temp_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: None,
                 contents: bytemuck::cast_slice(transform.as_slice()),
                 usage: wgpu::BufferUsages::COPY_SRC,
             });
             encoder.copy_buffer_to_buffer(&temp_buf, 0, &mesh.uniform_buf(), 0, 64);
+
+            if let Some(joint_buf) = mesh.joint_buf() {
+                let joint_matrices = scene.mesh_joint_matrices(id);
+                let joint_data = joint_matrices.iter()
+                    .take(MAX_JOINTS)
+                    .flat_map(|matrix| matrix.as_slice().iter().copied())
+                    .collect::<Vec<f32>>();
+                if !joint_data.is_empty() {
+                    queue.write_buffer(joint_buf, 0, bytemuck::cast_slice(&joint_data));
+                }
+            }
+        }
+        for (id, mesh) in scene.instanced_meshes.iter() {
+            let transform = scene.instanced_mesh_world_transform(id);
+            queue.write_buffer(mesh.uniform_buf(), 0, bytemuck::cast_slice(transform.as_slice()));
+        }
+
+        // Blend the morph targets of morphed parts into their vertices. Parts blended on the
+        // CPU are uploaded here, the rest are blended by the compute pass.
+        for mesh in scene.meshes.values() {
+            for (i, part) in mesh.parts().iter().enumerate() {
+                if let Some(morph_buffers) = mesh.morph_buffers(i) {
+                    part.upload_morph_weights(queue, morph_buffers, &mesh.morph_weights);
+                }
+            }
+        }
+        if let Some(ref morph_pipeline) = self.morph_pipeline {
+            let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None });
+            cpass.set_pipeline(morph_pipeline);
+            for mesh in scene.meshes.values() {
+                for (i, part) in mesh.parts().iter().enumerate() {
+                    let bind_group = mesh.morph_buffers(i).and_then(MorphBuffers::bind_group);
+                    if let Some(bind_group) = bind_group {
+                        cpass.set_bind_group(0, bind_group, &[]);
+                        cpass.dispatch(((part.vertex_count() + 63) / 64) as u32, 1, 1);
+                    }
+                }
+            }
+        }
+
+        // Upload material factors that changed since the last frame. Shared materials are only
+        // dirty the first time they're seen.
+        let instanced_parts = scene.instanced_meshes.values().flat_map(|mesh| mesh.parts().iter());
+        for part in scene.meshes.values().flat_map(|mesh| mesh.parts().iter()).chain(instanced_parts) {
+            if part.material.factors_dirty() {
+                part.material.upload_factors_to_gpu(queue);
+            }
+        }
+
+        // Build the pipeline variants parts are drawn with that haven't been used yet
+        for mesh in scene.meshes.values() {
+            for part in mesh.parts() {
+                self.material_pipeline_mut(part.material.kind()).prepare(device, part.layout());
+            }
+        }
+        for mesh in scene.instanced_meshes.values() {
+            for (i, part) in mesh.parts().iter().enumerate() {
+                let layout = mesh.part_layout(i);
+                self.material_pipeline_mut(part.material.kind()).prepare(device, layout);
+            }
         }
 
+        // Transmissive parts see through a copy of the opaque parts, so when there are any the
+        // opaque parts are drawn to the copy first
+        let transmissive = scene.meshes.values().flat_map(|mesh| mesh.parts().iter())
+            .chain(scene.instanced_meshes.values().flat_map(|mesh| mesh.parts().iter()))
+            .any(is_transmissive);
+        let opaque_target = if transmissive { self.scene_color.view() } else { render_target };
+
         {
-            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
-                color_attachments: &[
-                    wgpu::RenderPassColorAttachment {
-                        view: &render_target,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.0,
-                                g: 0.0,
-                                b: 0.0,
-                                a: 1.0,
-                            }),
-                            store: true,
-                        },
-                    },
-                    wgpu::RenderPassColorAttachment {
-                        view: &self.bloom_texture,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: wgpu::LoadOp::Clear(wgpu::Color {
-                                r: 0.0,
-                                g: 0.0,
-                                b: 0.0,
-                                a: 1.0,
-                            }),
-                            store: true,
-                        },
+            let mut rpass = self.begin_render_pass(encoder, opaque_target, true);
+            self.draw_parts(&mut rpass, scene, |part| !is_transmissive(part));
+        }
+
+        if transmissive {
+            self.scene_color.generate_mipmaps(encoder);
+            self.scene_color.copy_to(encoder, render_target);
+
+            let mut rpass = self.begin_render_pass(encoder, render_target, false);
+            self.draw_parts(&mut rpass, scene, is_transmissive);
+        }
+    }
+
+    /// Begin a pass drawing to `target` and the bloom texture, either clearing them and the depth
+    /// texture or drawing over what's already there.
+    fn begin_render_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        target: &'a wgpu::TextureView,
+        clear: bool,
+    ) -> wgpu::RenderPass<'a> {
+        let color_load = if clear {
+            wgpu::LoadOp::Clear(wgpu::Color {
+                r: 0.0,
+                g: 0.0,
+                b: 0.0,
+                a: 1.0,
+            })
+        } else {
+            wgpu::LoadOp::Load
+        };
+
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[
+                wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: color_load,
+                        store: true,
                     },
-                ],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.depth_texture,
-                    depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                },
+                wgpu::RenderPassColorAttachment {
+                    view: &self.bloom_texture,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: color_load,
                         store: true,
-                    }),
-                    stencil_ops: None,
+                    },
+                },
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture,
+                depth_ops: Some(wgpu::Operations {
+                    load: if clear { wgpu::LoadOp::Clear(1.0) } else { wgpu::LoadOp::Load },
+                    store: true,
                 }),
-            });
-            //rpass.set_blend_color(wgpu::Color::TRANSPARENT);
-            rpass.set_bind_group(0, &self.global_bind_group, &[]);
-            for mesh in scene.meshes.values() {
-                rpass.set_bind_group(1, &mesh.bind_group(), &[]);
-                for part in &mesh.parts {
-                    match part.material.kind() {
-                        MaterialKind::Untextured => {
-                            rpass.set_pipeline(&self.untextured.pipeline);
-                        }
-                        MaterialKind::TexturedUnlit => {
-                            rpass.set_pipeline(&self.textured_unlit.pipeline);
-                        }
-                        MaterialKind::Textured => {
-                            rpass.set_pipeline(&self.textured.pipeline);
-                        }
-                        MaterialKind::TexturedNorm => {
-                            rpass.set_pipeline(&self.textured_norm.pipeline);
-                        }
-                        MaterialKind::TexturedNormMat => {
-                            rpass.set_pipeline(&self.textured_norm_mat.pipeline);
-                        }
-                        MaterialKind::TexturedEmissive => {
-                            rpass.set_pipeline(&self.textured_emissive.pipeline);
-                        }
-                    }
-                    rpass.set_bind_group(2, &part.material.bind_group(), &[]);
-                    rpass.set_index_buffer(part.index_buf().slice(..), wgpu::IndexFormat::Uint32);
-                    rpass.set_vertex_buffer(0, part.vertex_buf().slice(..));
-                    rpass.draw_indexed(0 .. part.index_count() as u32, 0, 0 .. 1);
+                stencil_ops: None,
+            }),
+        })
+    }
+
+    /// Draw the parts of every mesh and instanced mesh that pass `filter`.
+    fn draw_parts<'a>(
+        &'a self,
+        rpass: &mut wgpu::RenderPass<'a>,
+        scene: &'a Scene,
+        filter: impl Fn(&MeshPart) -> bool,
+    ) {
+        //rpass.set_blend_color(wgpu::Color::TRANSPARENT);
+        rpass.set_bind_group(0, &self.global_bind_group, &[]);
+        for mesh in scene.meshes.values() {
+            for (i, part) in mesh.parts().iter().enumerate() {
+                if !filter(part) {
+                    continue;
                 }
+                let layout = part.layout();
+                let material_pipeline = self.material_pipeline(part.material.kind());
+                let pipeline = match material_pipeline.pipeline(layout) {
+                    Some(pipeline) => pipeline,
+                    None => continue,
+                };
+                rpass.set_bind_group(1, &mesh.bind_group(), &[mesh.part_uniform_offset(i)]);
+                rpass.set_pipeline(pipeline);
+                let extra_buf = part.extra_buf().unwrap_or(&self.default_extra_buf);
+                rpass.set_vertex_buffer(1, extra_buf.slice(..));
+                if let (true, Some(skin_buf)) = (layout.skinned, part.skin_buf()) {
+                    rpass.set_vertex_buffer(2, skin_buf.slice(..));
+                }
+                self.set_material_bind_groups(rpass, part);
+                rpass.set_index_buffer(part.index_buf().slice(..), wgpu::IndexFormat::Uint32);
+                rpass.set_vertex_buffer(0, mesh.part_vertex_buf(i).slice(..));
+                rpass.draw_indexed(0 .. part.index_count() as u32, 0, 0 .. 1);
+            }
+        }
+
+        // Each part of an instanced mesh is drawn once for every instance
+        for mesh in scene.instanced_meshes.values() {
+            if mesh.instance_count() == 0 {
+                continue;
             }
+            for (i, part) in mesh.parts().iter().enumerate() {
+                if !filter(part) {
+                    continue;
+                }
+                let layout = mesh.part_layout(i);
+                let material_pipeline = self.material_pipeline(part.material.kind());
+                let pipeline = match material_pipeline.pipeline(layout) {
+                    Some(pipeline) => pipeline,
+                    None => continue,
+                };
+                rpass.set_bind_group(1, &mesh.bind_group(), &[mesh.part_uniform_offset(i)]);
+                rpass.set_pipeline(pipeline);
+                let extra_buf = part.extra_buf().unwrap_or(mesh.default_extra_buf());
+                rpass.set_vertex_buffer(1, extra_buf.slice(..));
+                rpass.set_vertex_buffer(2, mesh.instance_buf().slice(..));
+                self.set_material_bind_groups(rpass, part);
+                rpass.set_index_buffer(part.index_buf().slice(..), wgpu::IndexFormat::Uint32);
+                rpass.set_vertex_buffer(0, part.vertex_buf().slice(..));
+                rpass.draw_indexed(
+                    0 .. part.index_count() as u32, 0, 0 .. mesh.instance_count() as u32,
+                );
+            }
+        }
+    }
+
+    /// Bind a part's material at set 2, and at set 3 the custom bind group of custom materials or
+    /// the scene color copy of transmissive ones.
+    fn set_material_bind_groups<'a>(
+        &'a self,
+        rpass: &mut wgpu::RenderPass<'a>,
+        part: &'a MeshPart,
+    ) {
+        rpass.set_bind_group(2, &part.material.bind_group(), &[]);
+        if let Some(custom_bind_group) = part.material.custom_bind_group() {
+            rpass.set_bind_group(3, custom_bind_group, &[]);
+        } else if is_transmissive(part) {
+            rpass.set_bind_group(3, self.scene_color.bind_group(), &[]);
+        }
+    }
+
+    fn material_pipeline(&self, kind: MaterialKind) -> &MeshPipeline {
+        match kind {
+            MaterialKind::TexturedUnlit => &self.textured_unlit,
+            MaterialKind::Textured => &self.textured,
+            MaterialKind::TexturedNorm => &self.textured_norm,
+            MaterialKind::TexturedNormMat => &self.textured_norm_mat,
+            MaterialKind::TexturedEmissive => &self.textured_emissive,
+            MaterialKind::TexturedLayered => &self.textured_layered,
+            MaterialKind::TexturedTransmissive => &self.textured_transmissive,
+            MaterialKind::Custom(id) => &self.custom_materials[id],
+        }
+    }
+
+    fn material_pipeline_mut(&mut self, kind: MaterialKind) -> &mut MeshPipeline {
+        match kind {
+            MaterialKind::TexturedUnlit => &mut self.textured_unlit,
+            MaterialKind::Textured => &mut self.textured,
+            MaterialKind::TexturedNorm => &mut self.textured_norm,
+            MaterialKind::TexturedNormMat => &mut self.textured_norm_mat,
+            MaterialKind::TexturedEmissive => &mut self.textured_emissive,
+            MaterialKind::TexturedLayered => &mut self.textured_layered,
+            MaterialKind::TexturedTransmissive => &mut self.textured_transmissive,
+            MaterialKind::Custom(id) => &mut self.custom_materials[id],
         }
     }
 }
 
+fn is_transmissive(part: &MeshPart) -> bool {
+    matches!(part.material.kind(), MaterialKind::TexturedTransmissive)
+}
+
 #[repr(C)]
 #[derive(Clone, Copy)]
 struct GlobalUniforms {
@@ -352,6 +662,42 @@ struct GlobalUniforms {
 unsafe impl bytemuck::Pod for GlobalUniforms { }
 unsafe impl bytemuck::Zeroable for GlobalUniforms { }
 
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct MorphUniforms {
+    pub vertex_count: u32,
+    pub target_count: u32,
+    pub _pad: [u32; 2],
+    pub weights: [f32; MAX_MORPH_TARGETS],
+}
+
+unsafe impl bytemuck::Pod for MorphUniforms { }
+unsafe impl bytemuck::Zeroable for MorphUniforms { }
+
+/// Per-part vertex shader uniforms: the dequantization of compact vertices.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub(crate) struct PartUniforms {
+    pub position_scale: [f32; 4],
+    pub position_offset: [f32; 4],
+    pub uv_scale_offset: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for PartUniforms { }
+unsafe impl bytemuck::Zeroable for PartUniforms { }
+
+impl From<Dequantization> for PartUniforms {
+    fn from(d: Dequantization) -> Self {
+        let [sx, sy, sz] = d.position_scale;
+        let [ox, oy, oz] = d.position_offset;
+        PartUniforms {
+            position_scale: [sx, sy, sz, 0.0],
+            position_offset: [ox, oy, oz, 0.0],
+            uv_scale_offset: [d.uv_scale[0], d.uv_scale[1], d.uv_offset[0], d.uv_offset[1]],
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub(crate) struct PointLightUpload {
@@ -1,12 +1,46 @@
+use std::collections::HashMap;
 use std::mem;
 
-use super::geometry::Vertex;
+use super::geometry::{ExtraVertex, QuantizedVertex, SkinVertex, Topology, Vertex, VertexFormat};
 use super::consts::DEPTH_FORMAT;
-use super::material::MaterialFactorsUpload;
+use super::custom_material::{CustomMaterialDescriptor, MaterialRenderState};
+use super::instanced_mesh::InstanceVertex;
+use super::material::{MaterialFactorsUpload, TextureSlot};
+use super::scene_color::scene_color_bind_group_layout;
 
 pub struct MeshPipeline {
     pub part_bind_group_layout: wgpu::BindGroupLayout,
-    pub pipeline: wgpu::RenderPipeline,
+    pub custom_bind_group_layout: Option<wgpu::BindGroupLayout>,
+    // Variants are built the first time a part with their layout is drawn. Layouts the material
+    // can't draw map to `None`, so they're only warned about once.
+    pipelines: HashMap<PartLayout, Option<wgpu::RenderPipeline>>,
+
+    label: Option<String>,
+    swapchain_format: wgpu::TextureFormat,
+    pipeline_layout: wgpu::PipelineLayout,
+    vs_module: wgpu::ShaderModule,
+    skinned_vs_module: Option<wgpu::ShaderModule>,
+    instanced_vs_module: Option<wgpu::ShaderModule>,
+    fs_module: wgpu::ShaderModule,
+    non_triangle_modules: Option<(wgpu::ShaderModule, wgpu::ShaderModule, wgpu::ShaderModule)>,
+    render_state: MaterialRenderState,
+}
+
+/// The vertex buffers and primitives of a mesh part, which select the pipeline variant it's
+/// drawn with.
+///
+/// Vertex buffer 0 holds `Vertex`es, or quantized vertices in the compact format. Buffer 1 holds
+/// `ExtraVertex`es, per vertex if the part has `extra_attributes`, otherwise a single default for
+/// every vertex, or one per instance. Buffer 2 holds `SkinVertex`es for skinned triangles, or the
+/// per-instance transforms and colors of instanced parts, which aren't skinned. Lines and points
+/// are drawn unlit by the built-in materials, and aren't skinned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PartLayout {
+    pub topology: Topology,
+    pub skinned: bool,
+    pub extra_attributes: bool,
+    pub vertex_format: VertexFormat,
+    pub instanced: bool,
 }
 
 impl MeshPipeline {
@@ -16,47 +50,205 @@ impl MeshPipeline {
         global_bind_group_layout: &wgpu::BindGroupLayout,
         mesh_bind_group_layout: &wgpu::BindGroupLayout,
         part_bind_group_layout: wgpu::BindGroupLayout,
+        // Bound at set 3, which custom materials use for their own bind group
+        extra_bind_group_layout: Option<wgpu::BindGroupLayout>,
+        vs_module: wgpu::ShaderModule,
+        skinned_vs_module: wgpu::ShaderModule,
+        instanced_vs_module: wgpu::ShaderModule,
+        fs_module: wgpu::ShaderModule,
+    ) -> Self {
+        // Lines and points have no surface to light. Every built-in material's bind group
+        // layout starts with the bindings the unlit shaders use.
+        let unlit_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/tex_unlit_vert.spv")
+        );
+        let unlit_instanced_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/tex_unlit_instanced_vert.spv")
+        );
+        let unlit_fs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/tex_unlit_frag.spv")
+        );
+
+        MeshPipeline::build(
+            None,
+            swapchain_format,
+            device,
+            global_bind_group_layout,
+            mesh_bind_group_layout,
+            part_bind_group_layout,
+            extra_bind_group_layout,
+            vs_module,
+            Some(skinned_vs_module),
+            Some(instanced_vs_module),
+            fs_module,
+            Some((unlit_vs_module, unlit_instanced_vs_module, unlit_fs_module)),
+            &MaterialRenderState::default(),
+        )
+    }
+
+    fn build(
+        label: Option<&str>,
+        swapchain_format: wgpu::TextureFormat,
+        device: &mut wgpu::Device,
+        global_bind_group_layout: &wgpu::BindGroupLayout,
+        mesh_bind_group_layout: &wgpu::BindGroupLayout,
+        part_bind_group_layout: wgpu::BindGroupLayout,
+        custom_bind_group_layout: Option<wgpu::BindGroupLayout>,
         vs_module: wgpu::ShaderModule,
+        skinned_vs_module: Option<wgpu::ShaderModule>,
+        instanced_vs_module: Option<wgpu::ShaderModule>,
         fs_module: wgpu::ShaderModule,
+        // The vertex, instanced vertex and fragment shaders of line and point pipelines, if not
+        // `vs_module`, `instanced_vs_module` and `fs_module`
+        non_triangle_modules: Option<(wgpu::ShaderModule, wgpu::ShaderModule, wgpu::ShaderModule)>,
+        render_state: &MaterialRenderState,
     ) -> Self {
+        let mut bind_group_layouts = vec![
+            global_bind_group_layout,
+            mesh_bind_group_layout,
+            &part_bind_group_layout,
+        ];
+        bind_group_layouts.extend(custom_bind_group_layout.as_ref());
+
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: None,
+            label,
             push_constant_ranges: &[],
-            bind_group_layouts: &[
-                global_bind_group_layout,
-                mesh_bind_group_layout,
-                &part_bind_group_layout,
-            ],
+            bind_group_layouts: &bind_group_layouts,
         });
 
-        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: None,
-            layout: Some(&pipeline_layout),
+        MeshPipeline {
+            part_bind_group_layout,
+            custom_bind_group_layout,
+            pipelines: HashMap::new(),
+            label: label.map(str::to_owned),
+            swapchain_format,
+            pipeline_layout,
+            vs_module,
+            skinned_vs_module,
+            instanced_vs_module,
+            fs_module,
+            non_triangle_modules,
+            render_state: *render_state,
+        }
+    }
+
+    /// Build the pipeline variant for a part layout if it hasn't been yet, warning if the material
+    /// can't draw it.
+    pub(crate) fn prepare(&mut self, device: &wgpu::Device, layout: PartLayout) {
+        if self.pipelines.contains_key(&layout) {
+            return;
+        }
+        let pipeline = self.create_pipeline(device, layout);
+        if pipeline.is_none() {
+            log::warn!("skipping mesh parts with layout {:?}, their material can't draw them", layout);
+        }
+        self.pipelines.insert(layout, pipeline);
+    }
+
+    /// The pipeline variant for a part layout, if it's been prepared and the material supports it.
+    /// Materials without a skinning or instancing vertex shader can't draw skinned or instanced
+    /// parts.
+    pub fn pipeline(&self, layout: PartLayout) -> Option<&wgpu::RenderPipeline> {
+        self.pipelines.get(&layout).and_then(Option::as_ref)
+    }
+
+    fn create_pipeline(
+        &self,
+        device: &wgpu::Device,
+        layout: PartLayout,
+    ) -> Option<wgpu::RenderPipeline> {
+        let (non_triangle_vs, non_triangle_instanced_vs, non_triangle_fs) =
+            match self.non_triangle_modules {
+                Some((ref vs, ref instanced_vs, ref fs)) => (vs, Some(instanced_vs), fs),
+                None => (&self.vs_module, self.instanced_vs_module.as_ref(), &self.fs_module),
+            };
+        let (vs_module, fs_module) = match (layout.topology, layout.skinned, layout.instanced) {
+            (Topology::Triangles, false, false) => (&self.vs_module, &self.fs_module),
+            (Topology::Triangles, true, false) => (self.skinned_vs_module.as_ref()?, &self.fs_module),
+            (Topology::Triangles, false, true) => {
+                (self.instanced_vs_module.as_ref()?, &self.fs_module)
+            }
+            (_, false, false) => (non_triangle_vs, non_triangle_fs),
+            (_, false, true) => (non_triangle_instanced_vs?, non_triangle_fs),
+            _ => return None,
+        };
+
+        let vertex_layout = wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<Vertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![
+                0 => Float32x3, 1 => Float32x3, 2 => Float32x4, 3 => Float32x2,
+            ],
+        };
+        // Positions, normals, tangents and UVs are normalized integers, read as floats
+        let quantized_layout = wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<QuantizedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![
+                0 => Snorm16x4, 1 => Snorm8x4, 2 => Snorm8x4, 3 => Unorm16x2,
+            ],
+        };
+        let extra_layout = wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<ExtraVertex>() as wgpu::BufferAddress,
+            step_mode: match layout.extra_attributes {
+                true => wgpu::VertexStepMode::Vertex,
+                false => wgpu::VertexStepMode::Instance,
+            },
+            attributes: &wgpu::vertex_attr_array![6 => Float32x4, 7 => Float32x2],
+        };
+        let skin_layout = wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<SkinVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![4 => Uint16x4, 5 => Float32x4],
+        };
+        // The instance transform is read a column at a time
+        let instance_layout = wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &wgpu::vertex_attr_array![
+                8 => Float32x4, 9 => Float32x4, 10 => Float32x4, 11 => Float32x4, 12 => Float32x4,
+            ],
+        };
+
+        let mut buffers = vec![
+            match layout.vertex_format {
+                VertexFormat::Full => vertex_layout,
+                VertexFormat::Compact => quantized_layout,
+            },
+            extra_layout,
+        ];
+        if layout.skinned {
+            buffers.push(skin_layout);
+        }
+        if layout.instanced {
+            buffers.push(instance_layout);
+        }
+        let topology = match layout.topology {
+            Topology::Triangles => wgpu::PrimitiveTopology::TriangleList,
+            Topology::Lines => wgpu::PrimitiveTopology::LineList,
+            Topology::Points => wgpu::PrimitiveTopology::PointList,
+        };
+
+        let render_state = &self.render_state;
+        Some(device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: self.label.as_deref(),
+            layout: Some(&self.pipeline_layout),
             vertex: wgpu::VertexState {
-                module: &vs_module,
+                module: vs_module,
                 entry_point: "main",
-                buffers: &[wgpu::VertexBufferLayout {
-                    array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-                    step_mode: wgpu::VertexStepMode::Vertex,
-                    attributes: &wgpu::vertex_attr_array![
-                        0 => Float32x3, 1 => Float32x3, 2 => Float32x4, 3 => Float32x2,
-                    ],
-                }],
+                buffers: &buffers,
             },
             fragment: Some(wgpu::FragmentState {
-                module: &fs_module,
+                module: fs_module,
                 entry_point: "main",
                 targets: &[
                     wgpu::ColorTargetState {
-                        format: swapchain_format,
-                        blend: Some(wgpu::BlendState {
-                            color: wgpu::BlendComponent::REPLACE,
-                            alpha: wgpu::BlendComponent::REPLACE,
-                        }),
+                        format: self.swapchain_format,
+                        blend: Some(render_state.blend),
                         write_mask: wgpu::ColorWrites::ALL,
                     },
                     wgpu::ColorTargetState {
-                        format: swapchain_format,
+                        format: self.swapchain_format,
                         blend: Some(wgpu::BlendState {
                             color: wgpu::BlendComponent::REPLACE,
                             alpha: wgpu::BlendComponent::REPLACE,
@@ -66,18 +258,18 @@ impl MeshPipeline {
                 ],
             }),
             primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
+                topology,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
-                cull_mode: None,
+                cull_mode: render_state.cull_mode,
                 clamp_depth: false,
                 polygon_mode: wgpu::PolygonMode::Fill,
                 conservative: false,
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: DEPTH_FORMAT,
-                depth_write_enabled: true,
-                depth_compare: wgpu::CompareFunction::Less,
+                depth_write_enabled: render_state.depth_write_enabled,
+                depth_compare: render_state.depth_compare,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
@@ -86,12 +278,81 @@ impl MeshPipeline {
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
-        });
+        }))
+    }
 
-        MeshPipeline {
+    pub fn custom(
+        swapchain_format: wgpu::TextureFormat,
+        device: &mut wgpu::Device,
+        global_bind_group_layout: &wgpu::BindGroupLayout,
+        mesh_bind_group_layout: &wgpu::BindGroupLayout,
+        desc: &CustomMaterialDescriptor,
+    ) -> Self {
+        let part_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: None,
+                entries: &[
+                    // Material factors
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: wgpu::BufferSize::new(
+                                mem::size_of::<MaterialFactorsUpload>() as wgpu::BufferAddress,
+                            ),
+                        },
+                        count: None,
+                    },
+                ],
+            });
+        let custom_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: desc.label,
+                entries: desc.bind_group_layout_entries,
+            });
+
+        // Get shaders
+        let vs_module = match desc.vertex_shader {
+            Some(ref vertex_shader) => device.create_shader_module(vertex_shader),
+            None => device.create_shader_module(&wgpu::include_spirv!("shaders/pbr_vert.spv")),
+        };
+        let skinned_vs_module = match (&desc.vertex_shader, &desc.skinned_vertex_shader) {
+            (_, Some(skinned_vertex_shader)) => {
+                Some(device.create_shader_module(skinned_vertex_shader))
+            }
+            (None, None) => Some(device.create_shader_module(
+                &wgpu::include_spirv!("shaders/pbr_skinned_vert.spv"),
+            )),
+            (Some(_), None) => None,
+        };
+        let instanced_vs_module = match (&desc.vertex_shader, &desc.instanced_vertex_shader) {
+            (_, Some(instanced_vertex_shader)) => {
+                Some(device.create_shader_module(instanced_vertex_shader))
+            }
+            (None, None) => Some(device.create_shader_module(
+                &wgpu::include_spirv!("shaders/pbr_instanced_vert.spv"),
+            )),
+            (Some(_), None) => None,
+        };
+        let fs_module = device.create_shader_module(&desc.fragment_shader);
+
+        MeshPipeline::build(
+            desc.label,
+            swapchain_format,
+            device,
+            global_bind_group_layout,
+            mesh_bind_group_layout,
             part_bind_group_layout,
-            pipeline,
-        }
+            Some(custom_bind_group_layout),
+            vs_module,
+            skinned_vs_module,
+            instanced_vs_module,
+            fs_module,
+            None,
+            &desc.render_state,
+        )
     }
 
     pub fn textured_unlit(
@@ -141,6 +402,12 @@ impl MeshPipeline {
         let vs_module = device.create_shader_module(
             &wgpu::include_spirv!("shaders/tex_unlit_vert.spv")
         );
+        let skinned_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/tex_unlit_skinned_vert.spv")
+        );
+        let instanced_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/tex_unlit_instanced_vert.spv")
+        );
         let fs_module = device.create_shader_module(
             &wgpu::include_spirv!("shaders/tex_unlit_frag.spv")
         );
@@ -151,7 +418,10 @@ impl MeshPipeline {
             global_bind_group_layout,
             mesh_bind_group_layout,
             part_bind_group_layout,
+            None,
             vs_module,
+            skinned_vs_module,
+            instanced_vs_module,
             fs_module,
         )
     }
@@ -203,6 +473,12 @@ impl MeshPipeline {
         let vs_module = device.create_shader_module(
             &wgpu::include_spirv!("shaders/pbr_vert.spv")
         );
+        let skinned_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_skinned_vert.spv")
+        );
+        let instanced_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_instanced_vert.spv")
+        );
         let fs_module = device.create_shader_module(
             &wgpu::include_spirv!("shaders/tex_pbr_frag.spv")
         );
@@ -213,7 +489,10 @@ impl MeshPipeline {
             global_bind_group_layout,
             mesh_bind_group_layout,
             part_bind_group_layout,
+            None,
             vs_module,
+            skinned_vs_module,
+            instanced_vs_module,
             fs_module,
         )
     }
@@ -269,6 +548,13 @@ impl MeshPipeline {
                         },
                         count: None,
                     },
+                    // Normal map sampler
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler { comparison: false, filtering: true },
+                        count: None,
+                    },
                 ],
             });
 
@@ -276,6 +562,12 @@ impl MeshPipeline {
         let vs_module = device.create_shader_module(
             &wgpu::include_spirv!("shaders/pbr_vert.spv")
         );
+        let skinned_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_skinned_vert.spv")
+        );
+        let instanced_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_instanced_vert.spv")
+        );
         let fs_module = device.create_shader_module(
             &wgpu::include_spirv!("shaders/tex_norm_frag.spv")
         );
@@ -286,7 +578,10 @@ impl MeshPipeline {
             global_bind_group_layout,
             mesh_bind_group_layout,
             part_bind_group_layout,
+            None,
             vs_module,
+            skinned_vs_module,
+            instanced_vs_module,
             fs_module,
         )
     }
@@ -362,6 +657,27 @@ impl MeshPipeline {
                         },
                         count: None,
                     },
+                    // Normal map sampler
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler { comparison: false, filtering: true },
+                        count: None,
+                    },
+                    // Metallic roughness map sampler
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler { comparison: false, filtering: true },
+                        count: None,
+                    },
+                    // AO map sampler
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler { comparison: false, filtering: true },
+                        count: None,
+                    },
                 ],
             });
 
@@ -369,6 +685,12 @@ impl MeshPipeline {
         let vs_module = device.create_shader_module(
             &wgpu::include_spirv!("shaders/pbr_vert.spv")
         );
+        let skinned_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_skinned_vert.spv")
+        );
+        let instanced_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_instanced_vert.spv")
+        );
         let fs_module = device.create_shader_module(
             &wgpu::include_spirv!("shaders/tex_norm_pbr_frag.spv")
         );
@@ -379,7 +701,10 @@ impl MeshPipeline {
             global_bind_group_layout,
             mesh_bind_group_layout,
             part_bind_group_layout,
+            None,
             vs_module,
+            skinned_vs_module,
+            instanced_vs_module,
             fs_module,
         )
     }
@@ -465,6 +790,34 @@ impl MeshPipeline {
                         },
                         count: None,
                     },
+                    // Normal map sampler
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 7,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler { comparison: false, filtering: true },
+                        count: None,
+                    },
+                    // Metallic roughness map sampler
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 8,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler { comparison: false, filtering: true },
+                        count: None,
+                    },
+                    // AO map sampler
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 9,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler { comparison: false, filtering: true },
+                        count: None,
+                    },
+                    // Emissive map sampler
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 10,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler { comparison: false, filtering: true },
+                        count: None,
+                    },
                 ],
             });
 
@@ -472,6 +825,12 @@ impl MeshPipeline {
         let vs_module = device.create_shader_module(
             &wgpu::include_spirv!("shaders/pbr_vert.spv")
         );
+        let skinned_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_skinned_vert.spv")
+        );
+        let instanced_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_instanced_vert.spv")
+        );
         let fs_module = device.create_shader_module(
             &wgpu::include_spirv!("shaders/tex_emiss_pbr_frag.spv")
         );
@@ -482,43 +841,76 @@ impl MeshPipeline {
             global_bind_group_layout,
             mesh_bind_group_layout,
             part_bind_group_layout,
+            None,
+            vs_module,
+            skinned_vs_module,
+            instanced_vs_module,
+            fs_module,
+        )
+    }
+
+    pub fn textured_layered(
+        swapchain_format: wgpu::TextureFormat,
+        device: &mut wgpu::Device,
+        global_bind_group_layout: &wgpu::BindGroupLayout,
+        mesh_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        // Every slot but transmission and thickness
+        let part_bind_group_layout = MeshPipeline::layered_bind_group_layout(
+            device, TextureSlot::ALL.len() - 2,
+        );
+
+        // Get shaders
+        let vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_vert.spv")
+        );
+        let skinned_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_skinned_vert.spv")
+        );
+        let instanced_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_instanced_vert.spv")
+        );
+        let fs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/tex_layered_pbr_frag.spv")
+        );
+
+        MeshPipeline::new(
+            swapchain_format,
+            device,
+            global_bind_group_layout,
+            mesh_bind_group_layout,
+            part_bind_group_layout,
+            None,
             vs_module,
+            skinned_vs_module,
+            instanced_vs_module,
             fs_module,
         )
     }
 
-    pub fn untextured(
+    /// The scene color copy is bound at set 3.
+    pub fn textured_transmissive(
         swapchain_format: wgpu::TextureFormat,
         device: &mut wgpu::Device,
         global_bind_group_layout: &wgpu::BindGroupLayout,
         mesh_bind_group_layout: &wgpu::BindGroupLayout,
     ) -> Self {
         let part_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: None,
-                entries: &[
-                    // Material factors
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: wgpu::BufferSize::new(
-                                mem::size_of::<MaterialFactorsUpload>() as wgpu::BufferAddress,
-                            ),
-                        },
-                        count: None,
-                    },
-                ],
-            });
+            MeshPipeline::layered_bind_group_layout(device, TextureSlot::ALL.len());
+        let scene_color_bind_group_layout = scene_color_bind_group_layout(device);
 
         // Get shaders
         let vs_module = device.create_shader_module(
             &wgpu::include_spirv!("shaders/pbr_vert.spv")
         );
+        let skinned_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_skinned_vert.spv")
+        );
+        let instanced_vs_module = device.create_shader_module(
+            &wgpu::include_spirv!("shaders/pbr_instanced_vert.spv")
+        );
         let fs_module = device.create_shader_module(
-            &wgpu::include_spirv!("shaders/untex_pbr_frag.spv")
+            &wgpu::include_spirv!("shaders/tex_transmissive_pbr_frag.spv")
         );
 
         MeshPipeline::new(
@@ -527,9 +919,68 @@ impl MeshPipeline {
             global_bind_group_layout,
             mesh_bind_group_layout,
             part_bind_group_layout,
+            Some(scene_color_bind_group_layout),
             vs_module,
+            skinned_vs_module,
+            instanced_vs_module,
             fs_module,
         )
     }
-}
 
+    /// The material bind group layout of the first `slot_count` slots in `TextureSlot::ALL`
+    /// order. The base slots are bound like the emissive material, each layer slot gets a texture
+    /// and sampler binding pair after them.
+    fn layered_bind_group_layout(
+        device: &mut wgpu::Device,
+        slot_count: usize,
+    ) -> wgpu::BindGroupLayout {
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let sampler = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler { comparison: false, filtering: true },
+            count: None,
+        };
+
+        let mut entries = vec![
+            // Material factors
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(
+                        mem::size_of::<MaterialFactorsUpload>() as wgpu::BufferAddress,
+                    ),
+                },
+                count: None,
+            },
+            sampler(1),
+        ];
+        // Base, normal, metallic roughness, AO and emissive maps, then their samplers
+        entries.extend((2..=6).map(texture));
+        entries.extend((7..=10).map(sampler));
+        // Clearcoat, clearcoat roughness, clearcoat normal, sheen color, sheen roughness, specular,
+        // specular color, transmission and thickness maps, each followed by its sampler
+        let layer_count = slot_count - (TextureSlot::ALL.len() - TextureSlot::LAYERS.len());
+        for binding in (11..).step_by(2).take(layer_count) {
+            entries.push(texture(binding));
+            entries.push(sampler(binding + 1));
+        }
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: None,
+            entries: &entries,
+        })
+    }
+}
@@ -1,13 +1,27 @@
 mod consts;
+mod custom_material;
 mod geometry;
+mod instanced_mesh;
 mod material;
 mod mesh;
+mod mesh_asset;
 mod mesh_part;
 mod mesh_pass;
 mod mesh_pipeline;
+mod scene_color;
 
-pub use geometry::{MeshPartGeometry, Vertex};
-pub use material::{Material, MaterialData, MaterialFactors, MaterialKind};
+pub(crate) use consts::MAX_JOINTS;
+pub use custom_material::{CustomMaterialDescriptor, CustomMaterialId, MaterialRenderState};
+pub use geometry::{
+    ExtraVertex, MeshPartGeometry, MorphVertex, SkinVertex, Topology, Vertex, VertexFormat,
+};
+pub use material::{
+    Material, MaterialData, MaterialFactors, MaterialKind, TextureSamplers, TextureSlot,
+    TextureSlots, TextureTransform, TextureTransforms,
+};
+pub use instanced_mesh::{InstancedMesh, MeshInstance};
 pub use mesh::Mesh;
+pub use mesh_asset::MeshAsset;
 pub use mesh_part::{MeshPart, MeshPartData, mesh_parts_bbox};
 pub use mesh_pass::MeshPass;
+pub use mesh_pipeline::PartLayout;
@@ -0,0 +1,171 @@
+use crate::mipmaps::mip_level_count;
+
+/// A mipped copy of the opaque scene, which transmissive materials see through. The opaque parts
+/// are drawn into it instead of the render target when any transmissive part is visible. Its mips
+/// are then generated, it's copied to the render target, and the transmissive parts are drawn on
+/// top, sampling blurrier levels the rougher they are.
+pub(crate) struct SceneColor {
+    // One view per mip level, each rendered from the level above
+    mip_views: Vec<wgpu::TextureView>,
+    // Sample a single mip level each, in the same order
+    mip_bind_groups: Vec<wgpu::BindGroup>,
+    // Samples every mip level, bound at set 3 of the transmissive pipeline
+    bind_group: wgpu::BindGroup,
+    blit_pipeline: wgpu::RenderPipeline,
+}
+
+impl SceneColor {
+    pub fn new(
+        device: &mut wgpu::Device,
+        surface_config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let bind_group_layout = scene_color_bind_group_layout(device);
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("scene-color-blit-layout"),
+            push_constant_ranges: &[],
+            bind_group_layouts: &[&bind_group_layout],
+        });
+        let vs_module = device.create_shader_module(&wgpu::include_spirv!("shaders/blit_vert.spv"));
+        let fs_module = device.create_shader_module(&wgpu::include_spirv!("shaders/blit_frag.spv"));
+        let blit_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("scene-color-blit"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &vs_module,
+                entry_point: "main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &fs_module,
+                entry_point: "main",
+                targets: &[surface_config.format.into()],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+        });
+
+        let mip_level_count = mip_level_count(surface_config.width, surface_config.height);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("scene-color"),
+            size: wgpu::Extent3d {
+                width: surface_config.width,
+                height: surface_config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: surface_config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("scene-color-sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let create_bind_group = |view: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                ],
+            })
+        };
+
+        let mip_views = (0..mip_level_count)
+            .map(|level| texture.create_view(&wgpu::TextureViewDescriptor {
+                base_mip_level: level,
+                mip_level_count: std::num::NonZeroU32::new(1),
+                ..Default::default()
+            }))
+            .collect::<Vec<_>>();
+        let mip_bind_groups = mip_views.iter().map(create_bind_group).collect();
+        let bind_group = create_bind_group(&texture.create_view(&Default::default()));
+
+        SceneColor {
+            mip_views,
+            mip_bind_groups,
+            bind_group,
+            blit_pipeline,
+        }
+    }
+
+    /// The full resolution level the opaque parts are drawn to.
+    pub fn view(&self) -> &wgpu::TextureView { &self.mip_views[0] }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup { &self.bind_group }
+
+    /// Downsample each mip level from the one above it.
+    pub fn generate_mipmaps(&self, encoder: &mut wgpu::CommandEncoder) {
+        for level in 1..self.mip_views.len() {
+            self.blit(encoder, level - 1, &self.mip_views[level]);
+        }
+    }
+
+    /// Copy the full resolution level to `target`, which must have the surface format.
+    pub fn copy_to(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        self.blit(encoder, 0, target);
+    }
+
+    fn blit(&self, encoder: &mut wgpu::CommandEncoder, level: usize, target: &wgpu::TextureView) {
+        let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: None,
+            color_attachments: &[
+                wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: true,
+                    },
+                },
+            ],
+            depth_stencil_attachment: None,
+        });
+        rpass.set_pipeline(&self.blit_pipeline);
+        rpass.set_bind_group(0, &self.mip_bind_groups[level], &[]);
+        rpass.draw(0 .. 3, 0 .. 1);
+    }
+}
+
+/// The layout of the scene color bind group: the texture at binding 0 and its sampler at
+/// binding 1. Layouts created from the same description are interchangeable, so the
+/// transmissive pipeline creates its own.
+pub(crate) fn scene_color_bind_group_layout(device: &mut wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("scene-color-bind-group-layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler { comparison: false, filtering: true },
+                count: None,
+            },
+        ],
+    })
+}
@@ -0,0 +1,583 @@
+//! Decoders for the vertex and index codecs and filters of meshoptimizer, as used by the glTF
+//! `EXT_meshopt_compression` extension.
+
+const VERTEX_HEADER: u8 = 0xa0;
+const INDEX_HEADER: u8 = 0xe0;
+const SEQUENCE_HEADER: u8 = 0xd0;
+
+const VERTEX_BLOCK_SIZE_BYTES: usize = 8192;
+const VERTEX_BLOCK_MAX_SIZE: usize = 256;
+const BYTE_GROUP_SIZE: usize = 16;
+const TAIL_MAX_SIZE: usize = 32;
+
+/// Vertex attribute data, `count` elements of `stride` bytes.
+pub(crate) fn decode_vertex_buffer(
+    count: usize,
+    stride: usize,
+    data: &[u8],
+) -> Result<Vec<u8>, &'static str> {
+    if stride == 0 || stride > 256 || stride % 4 != 0 {
+        return Err("vertex stride must be a multiple of 4 up to 256");
+    }
+    if data.len() < 1 + stride {
+        return Err("truncated vertex data");
+    }
+    if data[0] != VERTEX_HEADER {
+        return Err("unsupported vertex codec version");
+    }
+
+    // The first vertex is stored in the tail, as the base of the first deltas
+    let mut last_vertex = data[data.len() - stride..].to_vec();
+    let block_size = (VERTEX_BLOCK_SIZE_BYTES / stride & !(BYTE_GROUP_SIZE - 1))
+        .min(VERTEX_BLOCK_MAX_SIZE);
+
+    let mut vertices = vec![0; count * stride];
+    let mut pos = 1;
+    let mut buffer = [0; VERTEX_BLOCK_MAX_SIZE];
+    let mut vertex_offset = 0;
+    while vertex_offset < count {
+        let block_count = block_size.min(count - vertex_offset);
+        let aligned_count = (block_count + BYTE_GROUP_SIZE - 1) & !(BYTE_GROUP_SIZE - 1);
+
+        // Each byte of the vertex is stored separately, as zigzagged deltas
+        for k in 0..stride {
+            pos = decode_bytes(data, pos, &mut buffer[..aligned_count])?;
+
+            let mut p = last_vertex[k];
+            for i in 0..block_count {
+                let delta = buffer[i];
+                let v = ((delta >> 1) ^ (delta & 1).wrapping_neg()).wrapping_add(p);
+                vertices[(vertex_offset + i) * stride + k] = v;
+                p = v;
+            }
+        }
+
+        let last = vertex_offset + block_count - 1;
+        last_vertex.copy_from_slice(&vertices[last * stride..][..stride]);
+        vertex_offset += block_count;
+    }
+
+    if data.len() - pos != stride.max(TAIL_MAX_SIZE) {
+        return Err("malformed vertex data");
+    }
+
+    Ok(vertices)
+}
+
+/// Decode the byte groups of one byte of a vertex block. Returns the position after them.
+fn decode_bytes(data: &[u8], mut pos: usize, buffer: &mut [u8]) -> Result<usize, &'static str> {
+    // Each group's 2 bit encoding is packed in a header, 4 groups to a byte
+    let header_size = (buffer.len() / BYTE_GROUP_SIZE + 3) / 4;
+    if data.len() - pos < header_size {
+        return Err("truncated vertex data");
+    }
+    let header = pos;
+    pos += header_size;
+
+    for (group, out) in buffer.chunks_mut(BYTE_GROUP_SIZE).enumerate() {
+        if data.len() - pos < TAIL_MAX_SIZE {
+            return Err("truncated vertex data");
+        }
+        let bits_log2 = (data[header + group / 4] >> ((group % 4) * 2)) & 3;
+        pos = match bits_log2 {
+            0 => {
+                out.iter_mut().for_each(|byte| *byte = 0);
+                pos
+            }
+            1 => decode_bytes_group(data, pos, out, 2),
+            2 => decode_bytes_group(data, pos, out, 4),
+            _ => {
+                out.copy_from_slice(&data[pos..pos + BYTE_GROUP_SIZE]);
+                pos + BYTE_GROUP_SIZE
+            }
+        };
+    }
+
+    Ok(pos)
+}
+
+/// Unpack 16 values of `bits` bits each. Values with all bits set are followed by a full byte.
+fn decode_bytes_group(data: &[u8], pos: usize, out: &mut [u8], bits: u32) -> usize {
+    let sentinel = (1u8 << bits) - 1;
+    let per_byte = 8 / bits as usize;
+    let mut extra = pos + BYTE_GROUP_SIZE / per_byte;
+    for (i, value) in out.iter_mut().enumerate() {
+        let byte = data[pos + i / per_byte];
+        let shift = 8 - bits * (i % per_byte + 1) as u32;
+        let encoded = (byte >> shift) & sentinel;
+        *value = if encoded == sentinel {
+            extra += 1;
+            data[extra - 1]
+        } else {
+            encoded
+        };
+    }
+    extra
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// Triangle list indices.
+pub(crate) fn decode_index_buffer(count: usize, data: &[u8]) -> Result<Vec<u32>, &'static str> {
+    const CODE_AUX_TABLE_SIZE: usize = 16;
+
+    if count % 3 != 0 {
+        return Err("triangle index count must be a multiple of 3");
+    }
+    // A header, a code byte per triangle and the code auxiliary table at the end
+    if data.len() < 1 + count / 3 + CODE_AUX_TABLE_SIZE {
+        return Err("truncated index data");
+    }
+    if data[0] & 0xf0 != INDEX_HEADER {
+        return Err("malformed index data");
+    }
+    let version = data[0] & 0x0f;
+    if version > 1 {
+        return Err("unsupported index codec version");
+    }
+
+    let mut edge_fifo = [[u32::MAX; 2]; 16];
+    let mut vertex_fifo = [u32::MAX; 16];
+    let mut edge_fifo_offset = 0usize;
+    let mut vertex_fifo_offset = 0usize;
+
+    let mut next = 0u32;
+    let mut last = 0u32;
+    // Version 1 codes the free indices one before and after the last directly
+    let fec_max = if version >= 1 { 13 } else { 15 };
+
+    let mut code = 1;
+    let mut pos = 1 + count / 3;
+    let safe_end = data.len() - CODE_AUX_TABLE_SIZE;
+    let code_aux_table = &data[safe_end..];
+
+    let mut indices = Vec::with_capacity(count);
+    for _ in 0..count / 3 {
+        // A triangle reads at most 16 bytes: a code auxiliary byte and 3 varints
+        if pos > safe_end {
+            return Err("truncated index data");
+        }
+
+        let code_tri = data[code];
+        code += 1;
+
+        if code_tri < 0xf0 {
+            // An edge from the FIFO, and a vertex from the FIFO, the next new index or free
+            let fe = (code_tri >> 4) as usize;
+            let [a, b] = edge_fifo[(edge_fifo_offset.wrapping_sub(1 + fe)) & 15];
+            let fec = (code_tri & 15) as usize;
+
+            let c = if fec < fec_max {
+                let c = if fec == 0 {
+                    next += 1;
+                    next - 1
+                } else {
+                    vertex_fifo[(vertex_fifo_offset.wrapping_sub(1 + fec)) & 15]
+                };
+                push_vertex_fifo(&mut vertex_fifo, &mut vertex_fifo_offset, c, fec == 0);
+                c
+            } else {
+                // 13 and 14 decode to -1 and 1
+                last = if fec != 15 {
+                    last.wrapping_add((fec as i32 - (fec as i32 ^ 3)) as u32)
+                } else {
+                    decode_index(data, &mut pos, last)
+                };
+                push_vertex_fifo(&mut vertex_fifo, &mut vertex_fifo_offset, last, true);
+                last
+            };
+
+            indices.extend_from_slice(&[a, b, c]);
+            push_edge_fifo(&mut edge_fifo, &mut edge_fifo_offset, c, b);
+            push_edge_fifo(&mut edge_fifo, &mut edge_fifo_offset, a, c);
+        } else {
+            // Three vertices from the FIFO or new indices, with a code auxiliary byte
+            let (fea, code_aux) = if code_tri < 0xfe {
+                (0, code_aux_table[(code_tri & 15) as usize])
+            } else {
+                // A zero code auxiliary byte outside the table restarts the new indices
+                if data[pos] == 0 {
+                    next = 0;
+                }
+                pos += 1;
+                (if code_tri == 0xfe { 0 } else { 15 }, data[pos - 1])
+            };
+            let feb = (code_aux >> 4) as usize;
+            let fec = (code_aux & 15) as usize;
+
+            let vertex = |fe: usize, next: &mut u32| match fe {
+                0 => {
+                    *next += 1;
+                    *next - 1
+                }
+                _ => vertex_fifo[(vertex_fifo_offset.wrapping_sub(fe)) & 15],
+            };
+            let mut a = if fea == 0 { vertex(0, &mut next) } else { 0 };
+            let mut b = if feb != 15 { vertex(feb, &mut next) } else { 0 };
+            let mut c = if fec != 15 { vertex(fec, &mut next) } else { 0 };
+
+            // Free indices are delta coded from the last one
+            if fea == 15 {
+                last = decode_index(data, &mut pos, last);
+                a = last;
+            }
+            if feb == 15 {
+                last = decode_index(data, &mut pos, last);
+                b = last;
+            }
+            if fec == 15 {
+                last = decode_index(data, &mut pos, last);
+                c = last;
+            }
+
+            indices.extend_from_slice(&[a, b, c]);
+            push_vertex_fifo(&mut vertex_fifo, &mut vertex_fifo_offset, a, true);
+            push_vertex_fifo(&mut vertex_fifo, &mut vertex_fifo_offset, b, feb == 0 || feb == 15);
+            push_vertex_fifo(&mut vertex_fifo, &mut vertex_fifo_offset, c, fec == 0 || fec == 15);
+            push_edge_fifo(&mut edge_fifo, &mut edge_fifo_offset, b, a);
+            push_edge_fifo(&mut edge_fifo, &mut edge_fifo_offset, c, b);
+            push_edge_fifo(&mut edge_fifo, &mut edge_fifo_offset, a, c);
+        }
+    }
+
+    if pos != safe_end {
+        return Err("malformed index data");
+    }
+
+    Ok(indices)
+}
+
+/// Indices of any topology, each delta coded from one of two previous indices.
+pub(crate) fn decode_index_sequence(count: usize, data: &[u8]) -> Result<Vec<u32>, &'static str> {
+    const TAIL_SIZE: usize = 4;
+
+    // A header, at least a byte per index and a tail
+    if data.len() < 1 + count + TAIL_SIZE {
+        return Err("truncated index data");
+    }
+    if data[0] & 0xf0 != SEQUENCE_HEADER {
+        return Err("malformed index data");
+    }
+    if data[0] & 0x0f > 1 {
+        return Err("unsupported index sequence codec version");
+    }
+
+    let safe_end = data.len() - TAIL_SIZE;
+    let mut pos = 1;
+    let mut last = [0u32; 2];
+    let mut indices = Vec::with_capacity(count);
+    for _ in 0..count {
+        if pos >= safe_end {
+            return Err("truncated index data");
+        }
+        let v = decode_vbyte(data, &mut pos);
+        let baseline = (v & 1) as usize;
+        let v = v >> 1;
+        let delta = (v >> 1) ^ (v & 1).wrapping_neg();
+        last[baseline] = last[baseline].wrapping_add(delta);
+        indices.push(last[baseline]);
+    }
+
+    if pos != safe_end {
+        return Err("malformed index data");
+    }
+
+    Ok(indices)
+}
+
+fn push_edge_fifo(fifo: &mut [[u32; 2]; 16], offset: &mut usize, a: u32, b: u32) {
+    fifo[*offset] = [a, b];
+    *offset = (*offset + 1) & 15;
+}
+
+fn push_vertex_fifo(fifo: &mut [u32; 16], offset: &mut usize, v: u32, cond: bool) {
+    fifo[*offset] = v;
+    *offset = (*offset + cond as usize) & 15;
+}
+
+fn decode_index(data: &[u8], pos: &mut usize, last: u32) -> u32 {
+    let v = decode_vbyte(data, pos);
+    last.wrapping_add((v >> 1) ^ (v & 1).wrapping_neg())
+}
+
+/// A little endian base 128 varint of up to 5 bytes.
+fn decode_vbyte(data: &[u8], pos: &mut usize) -> u32 {
+    let lead = data[*pos];
+    *pos += 1;
+    if lead < 128 {
+        return lead as u32;
+    }
+
+    let mut result = (lead & 127) as u32;
+    let mut shift = 7;
+    for _ in 0..4 {
+        let group = data[*pos];
+        *pos += 1;
+        result |= ((group & 127) as u32) << shift;
+        shift += 7;
+        if group < 128 {
+            break;
+        }
+    }
+    result
+}
+
+////////////////////////////////////////////////////////////////////////////////
+
+/// A transform applied to attribute data after decoding, to make it compress better.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Filter {
+    None,
+    /// Unit vectors as octahedral coordinates, in 8 or 16 bit signed components
+    Octahedral,
+    /// Unit quaternions as 3 components, in 16 bit signed components
+    Quaternion,
+    /// Floats as a shared exponent and per-component mantissas
+    Exponential,
+}
+
+/// Undo a filter in place on `count` elements of `stride` bytes.
+pub(crate) fn decode_filter(
+    filter: Filter,
+    data: &mut [u8],
+    count: usize,
+    stride: usize,
+) -> Result<(), &'static str> {
+    match filter {
+        Filter::None => { }
+        Filter::Octahedral if stride == 4 => {
+            for element in data.chunks_exact_mut(4).take(count) {
+                let mut v = [0i32; 4];
+                for (v, &byte) in v.iter_mut().zip(element.iter()) {
+                    *v = byte as i8 as i32;
+                }
+                let [x, y, z] = decode_octahedral(v, 127.0);
+                element[0] = x as i8 as u8;
+                element[1] = y as i8 as u8;
+                element[2] = z as i8 as u8;
+            }
+        }
+        Filter::Octahedral if stride == 8 => {
+            for element in data.chunks_exact_mut(8).take(count) {
+                let mut v = [0i32; 4];
+                for (i, v) in v.iter_mut().enumerate() {
+                    *v = i16::from_le_bytes([element[i * 2], element[i * 2 + 1]]) as i32;
+                }
+                let xyz = decode_octahedral(v, 32767.0);
+                for (i, &c) in xyz.iter().enumerate() {
+                    element[i * 2..i * 2 + 2].copy_from_slice(&(c as i16).to_le_bytes());
+                }
+            }
+        }
+        Filter::Octahedral => return Err("octahedral filter stride must be 4 or 8"),
+        Filter::Quaternion if stride == 8 => {
+            let scale = 1.0 / f32::sqrt(2.0);
+            for element in data.chunks_exact_mut(8).take(count) {
+                let mut q = [0i16; 4];
+                for (i, q) in q.iter_mut().enumerate() {
+                    *q = i16::from_le_bytes([element[i * 2], element[i * 2 + 1]]);
+                }
+
+                // The 4th component holds the scale, and which component was dropped
+                let ss = scale / (q[3] | 3) as f32;
+                let x = q[0] as f32 * ss;
+                let y = q[1] as f32 * ss;
+                let z = q[2] as f32 * ss;
+                let w = (1.0 - x * x - y * y - z * z).max(0.0).sqrt();
+
+                let round = |v: f32| (v * 32767.0 + if v >= 0.0 { 0.5 } else { -0.5 }) as i16;
+                let dropped = (q[3] & 3) as usize;
+                let mut out = [0i16; 4];
+                out[(dropped + 1) & 3] = round(x);
+                out[(dropped + 2) & 3] = round(y);
+                out[(dropped + 3) & 3] = round(z);
+                out[dropped] = round(w);
+                for (i, &c) in out.iter().enumerate() {
+                    element[i * 2..i * 2 + 2].copy_from_slice(&c.to_le_bytes());
+                }
+            }
+        }
+        Filter::Quaternion => return Err("quaternion filter stride must be 8"),
+        Filter::Exponential if stride % 4 == 0 => {
+            for component in data.chunks_exact_mut(4).take(count * stride / 4) {
+                let v = u32::from_le_bytes([
+                    component[0], component[1], component[2], component[3],
+                ]);
+                let mantissa = ((v << 8) as i32) >> 8;
+                let exponent = (v as i32) >> 24;
+                let value = mantissa as f32 * f32::from_bits(((exponent + 127) as u32) << 23);
+                component.copy_from_slice(&value.to_le_bytes());
+            }
+        }
+        Filter::Exponential => return Err("exponential filter stride must be a multiple of 4"),
+    }
+    Ok(())
+}
+
+/// Reconstruct a unit vector from octahedral X and Y, with Z holding the encoding of 1.
+fn decode_octahedral(v: [i32; 4], max: f32) -> [i32; 3] {
+    let mut x = v[0] as f32;
+    let mut y = v[1] as f32;
+    let z = v[2] as f32 - x.abs() - y.abs();
+
+    // Fold the lower hemisphere
+    let t = z.min(0.0);
+    x += if x >= 0.0 { t } else { -t };
+    y += if y >= 0.0 { t } else { -t };
+
+    let s = max / (x * x + y * y + z * z).sqrt();
+    let round = |v: f32| (v * s + if v >= 0.0 { 0.5 } else { -0.5 }) as i32;
+    [round(x), round(y), round(z)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Encoded with meshoptimizer's `meshopt_encodeVertexBuffer` and `meshopt_encodeIndexBuffer`
+    const QUAD_VERTICES: [u8; 74] = [
+        0xa0, 0x00, 0x00, 0x01, 0x3f, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0x01, 0x3f, 0x00, 0x00,
+        0x00, 0x7e, 0x7d, 0x7e, 0x00, 0x00, 0x01, 0x0c, 0x00, 0x00, 0x00, 0xff, 0x01, 0x0c, 0x00,
+        0x00, 0x00, 0x7e, 0x00, 0x00, 0x00, 0x01, 0x03, 0x00, 0x00, 0x00, 0x7e, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    const QUAD_POSITIONS: [[f32; 3]; 4] =
+        [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 1.0, 0.5]];
+
+    const QUAD_INDICES: [u8; 19] = [
+        0xe0, 0xf0, 0x10, 0x00, 0x76, 0x87, 0x56, 0x67, 0x78, 0xa9, 0x86, 0x65, 0x89, 0x68, 0x98,
+        0x01, 0x69, 0x00, 0x00,
+    ];
+    const FREE_INDICES_V0: [u8; 30] = [
+        0xe0, 0xf0, 0xff, 0x1f, 0xff, 0xff, 0x14, 0x02, 0x02, 0x02, 0xff, 0x07, 0x16, 0x02, 0x00,
+        0x76, 0x87, 0x56, 0x67, 0x78, 0xa9, 0x86, 0x65, 0x89, 0x68, 0x98, 0x01, 0x69, 0x00, 0x00,
+    ];
+    // The same triangles in version 1, where 13 follows 12 as a free index one after the last
+    const FREE_INDICES_V1: [u8; 29] = [
+        0xe1, 0xf0, 0xff, 0x1e, 0xff, 0xff, 0x14, 0x02, 0x02, 0xff, 0x07, 0x16, 0x02, 0x00, 0x76,
+        0x87, 0x56, 0x67, 0x78, 0xa9, 0x86, 0x65, 0x89, 0x68, 0x98, 0x01, 0x69, 0x00, 0x00,
+    ];
+    const FREE_TRIANGLES: [u32; 12] = [0, 1, 2, 10, 11, 12, 13, 12, 11, 9, 20, 21];
+
+    /// The index codec keeps triangles, but may rotate their indices.
+    fn assert_same_triangles(decoded: &[u32], expected: &[u32]) {
+        assert_eq!(decoded.len(), expected.len());
+        for (d, e) in decoded.chunks(3).zip(expected.chunks(3)) {
+            let rotated = (0..3).any(|r| (0..3).all(|i| d[i] == e[(i + r) % 3]));
+            assert!(rotated, "triangle {:?} isn't a rotation of {:?}", d, e);
+        }
+    }
+
+    #[test]
+    fn vertex_buffer() {
+        let decoded = decode_vertex_buffer(4, 12, &QUAD_VERTICES).unwrap();
+        let positions: Vec<f32> = decoded.chunks(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        assert_eq!(positions, QUAD_POSITIONS.concat());
+    }
+
+    #[test]
+    fn vertex_buffer_malformed() {
+        // Truncated
+        assert!(decode_vertex_buffer(4, 12, &QUAD_VERTICES[..60]).is_err());
+        assert!(decode_vertex_buffer(4, 12, &QUAD_VERTICES[..8]).is_err());
+        // Trailing data
+        let mut padded = QUAD_VERTICES.to_vec();
+        padded.push(0);
+        assert!(decode_vertex_buffer(4, 12, &padded).is_err());
+        // Unknown version
+        let mut version = QUAD_VERTICES;
+        version[0] = 0xa1;
+        assert!(decode_vertex_buffer(4, 12, &version).is_err());
+        // Invalid stride
+        assert!(decode_vertex_buffer(4, 6, &QUAD_VERTICES).is_err());
+        // More vertices than were encoded
+        assert!(decode_vertex_buffer(400, 12, &QUAD_VERTICES).is_err());
+    }
+
+    #[test]
+    fn index_buffer() {
+        let decoded = decode_index_buffer(6, &QUAD_INDICES).unwrap();
+        assert_same_triangles(&decoded, &[0, 1, 2, 1, 3, 2]);
+        let decoded = decode_index_buffer(12, &FREE_INDICES_V0).unwrap();
+        assert_same_triangles(&decoded, &FREE_TRIANGLES);
+        let decoded = decode_index_buffer(12, &FREE_INDICES_V1).unwrap();
+        assert_same_triangles(&decoded, &FREE_TRIANGLES);
+    }
+
+    #[test]
+    fn index_buffer_malformed() {
+        assert!(decode_index_buffer(12, &FREE_INDICES_V0[..20]).is_err());
+        assert!(decode_index_buffer(5, &QUAD_INDICES).is_err());
+        let mut version = QUAD_INDICES;
+        version[0] = 0xe2;
+        assert!(decode_index_buffer(6, &version).is_err());
+        let mut header = QUAD_INDICES;
+        header[0] = 0xa0;
+        assert!(decode_index_buffer(6, &header).is_err());
+        // Decoding must end at the code auxiliary table
+        let mut padded = QUAD_INDICES.to_vec();
+        padded.insert(3, 0);
+        assert!(decode_index_buffer(6, &padded).is_err());
+    }
+
+    #[test]
+    fn index_sequence() {
+        // Index 100 switches to the second baseline, and 4 switches back to the first
+        let data = [0xd1, 0, 4, 4, 0, 2, 8, 145, 3, 4, 0, 0, 0, 0];
+        let decoded = decode_index_sequence(8, &data).unwrap();
+        assert_eq!(decoded, [0, 1, 2, 2, 1, 3, 100, 4]);
+
+        assert!(decode_index_sequence(9, &data).is_err());
+        assert!(decode_index_sequence(8, &data[..12]).is_err());
+        let mut version = data;
+        version[0] = 0xd2;
+        assert!(decode_index_sequence(8, &version).is_err());
+    }
+
+    #[test]
+    fn octahedral_filter() {
+        // +X, and -Z from the folded lower hemisphere
+        let mut data = [127, 0, 127, 9, 127, 127, 127, 9];
+        decode_filter(Filter::Octahedral, &mut data, 2, 4).unwrap();
+        assert_eq!(data, [127, 0, 0, 9, 0, 0, (-127i8) as u8, 9]);
+
+        let mut data: Vec<u8> = [32767i16, 0, 32767, 1].iter()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        decode_filter(Filter::Octahedral, &mut data, 1, 8).unwrap();
+        let decoded: Vec<i16> = data.chunks(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect();
+        assert_eq!(decoded, [32767, 0, 0, 1]);
+
+        assert!(decode_filter(Filter::Octahedral, &mut data, 1, 12).is_err());
+    }
+
+    #[test]
+    fn quaternion_filter() {
+        // The dropped component is stored in the low bits of the 4th, and is reconstructed
+        let mut data: Vec<u8> = [10000i16, 0, 0, 32767, 10000, 0, 0, 32764].iter()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        decode_filter(Filter::Quaternion, &mut data, 2, 8).unwrap();
+        let decoded: Vec<i16> = data.chunks(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect();
+        assert_eq!(decoded, [7071, 0, 0, 31995, 31995, 7071, 0, 0]);
+
+        assert!(decode_filter(Filter::Quaternion, &mut data, 2, 4).is_err());
+    }
+
+    #[test]
+    fn exponential_filter() {
+        let mut data: Vec<u8> = [0xfe00_0006u32, 0x01ff_fffd].iter()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        decode_filter(Filter::Exponential, &mut data, 1, 8).unwrap();
+        let decoded: Vec<f32> = data.chunks(4)
+            .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        assert_eq!(decoded, [1.5, -6.0]);
+
+        assert!(decode_filter(Filter::Exponential, &mut data, 1, 6).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+use half::f16;
+
+/// How texels are averaged when downsampling.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MipmapFilter {
+    /// Linear data, e.g. metallic roughness or occlusion maps
+    Linear,
+    /// sRGB encoded color; the RGB channels are averaged in linear space
+    Srgb,
+    /// Tangent space normals; averaged normals are renormalized
+    Normal,
+}
+
+/// A channel type that can be averaged.
+pub trait Texel: Copy {
+    fn to_f32(self) -> f32;
+    fn from_f32(v: f32) -> Self;
+}
+
+impl Texel for u8 {
+    fn to_f32(self) -> f32 { self as f32 / 255.0 }
+    fn from_f32(v: f32) -> Self { (v.max(0.0).min(1.0) * 255.0 + 0.5) as u8 }
+}
+
+impl Texel for f16 {
+    fn to_f32(self) -> f32 { f16::to_f32(self) }
+    fn from_f32(v: f32) -> Self { f16::from_f32(v) }
+}
+
+/// A single mip level of `channels` interleaved channels per texel.
+pub struct MipLevel<T> {
+    pub width: u32,
+    pub height: u32,
+    pub texels: Vec<T>,
+}
+
+/// The number of mip levels in a full chain down to 1x1.
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+/// Generate every mip level below `base` with a 2x2 box filter. Level 0 isn't included.
+pub fn generate_mipmaps<T: Texel>(
+    base: &MipLevel<T>,
+    channels: usize,
+    filter: MipmapFilter,
+) -> Vec<MipLevel<T>> {
+    let mut levels: Vec<MipLevel<T>> = Vec::new();
+    for _ in 1..mip_level_count(base.width, base.height) {
+        let next = downsample(levels.last().unwrap_or(base), channels, filter);
+        levels.push(next);
+    }
+    levels
+}
+
+fn downsample<T: Texel>(src: &MipLevel<T>, channels: usize, filter: MipmapFilter) -> MipLevel<T> {
+    let width = (src.width / 2).max(1);
+    let height = (src.height / 2).max(1);
+    // Only the RGB channels of 3+ channel textures are color or direction data
+    let filter = if channels < 3 { MipmapFilter::Linear } else { filter };
+
+    let mut texels = Vec::with_capacity((width * height) as usize * channels);
+    for y in 0..height {
+        for x in 0..width {
+            // Clamp so odd sized and 1 texel wide levels reuse their last row/column
+            let x0 = (2 * x).min(src.width - 1);
+            let x1 = (2 * x + 1).min(src.width - 1);
+            let y0 = (2 * y).min(src.height - 1);
+            let y1 = (2 * y + 1).min(src.height - 1);
+
+            let mut avg = [0.0f32; 4];
+            for &(sx, sy) in &[(x0, y0), (x1, y0), (x0, y1), (x1, y1)] {
+                let i = (sy * src.width + sx) as usize * channels;
+                for c in 0..channels {
+                    let v = src.texels[i + c].to_f32();
+                    avg[c] += 0.25 * match filter {
+                        MipmapFilter::Srgb if c < 3 => srgb_to_linear(v),
+                        MipmapFilter::Normal if c < 3 => v * 2.0 - 1.0,
+                        _ => v,
+                    };
+                }
+            }
+
+            match filter {
+                MipmapFilter::Linear => { }
+                MipmapFilter::Srgb => {
+                    for c in 0..3 {
+                        avg[c] = linear_to_srgb(avg[c]);
+                    }
+                }
+                MipmapFilter::Normal => {
+                    let len = (avg[0] * avg[0] + avg[1] * avg[1] + avg[2] * avg[2]).sqrt();
+                    let n = if len > 1e-6 {
+                        [avg[0] / len, avg[1] / len, avg[2] / len]
+                    } else {
+                        [0.0, 0.0, 1.0]
+                    };
+                    for c in 0..3 {
+                        avg[c] = n[c] * 0.5 + 0.5;
+                    }
+                }
+            }
+
+            texels.extend(avg[..channels].iter().map(|&v| T::from_f32(v)));
+        }
+    }
+
+    MipLevel { width, height, texels }
+}
+
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(width: u32, height: u32, texels: &[u8]) -> MipLevel<u8> {
+        MipLevel { width, height, texels: texels.to_vec() }
+    }
+
+    #[test]
+    fn srgb_averages_in_linear_space() {
+        // Black and white RGBA texels, with alpha going from transparent to opaque
+        let black = [0, 0, 0, 0];
+        let white = [255, 255, 255, 255];
+        let base = level(2, 2, &[black, white, black, white].concat());
+
+        let srgb = generate_mipmaps(&base, 4, MipmapFilter::Srgb);
+        assert_eq!(srgb.len(), 1);
+        // Half intensity in linear space is ~0.735 in sRGB, while alpha stays linear
+        assert_eq!(srgb[0].texels, vec![188, 188, 188, 128]);
+
+        let linear = generate_mipmaps(&base, 4, MipmapFilter::Linear);
+        assert_eq!(linear[0].texels, vec![128, 128, 128, 128]);
+
+        // Single and dual channel textures are never color
+        let luma = level(2, 2, &[0, 255, 0, 255]);
+        assert_eq!(generate_mipmaps(&luma, 1, MipmapFilter::Srgb)[0].texels, vec![128]);
+    }
+
+    #[test]
+    fn normals_are_renormalized() {
+        // (0.6, 0, 0.8) and (-0.6, 0, 0.8) average to (0, 0, 0.8), which points straight up
+        let left = [204, 128, 230, 255];
+        let right = [51, 128, 230, 255];
+        let base = level(2, 2, &[left, right, left, right].concat());
+        let mips = generate_mipmaps(&base, 4, MipmapFilter::Normal);
+        assert_eq!(mips[0].texels, vec![128, 128, 255, 255]);
+
+        // Opposite normals cancel out, which falls back to the flat normal. 0 and 255 are the
+        // only values that cancel exactly, as 128 is slightly above 0.
+        let a = [0, 0, 255, 255];
+        let b = [255, 255, 0, 255];
+        let base = level(2, 2, &[a, b, b, a].concat());
+        let mips = generate_mipmaps(&base, 4, MipmapFilter::Normal);
+        assert_eq!(mips[0].texels, vec![128, 128, 255, 255]);
+    }
+
+    #[test]
+    fn odd_and_one_texel_dimensions() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(5, 1), 3);
+        assert_eq!(mip_level_count(4, 3), 3);
+        assert!(generate_mipmaps(&level(1, 1, &[7]), 1, MipmapFilter::Linear).is_empty());
+
+        // A 5x1 row halves to 2x1 then 1x1, dropping the odd last texel
+        let row = level(5, 1, &[0, 10, 20, 30, 40]);
+        let mips = generate_mipmaps(&row, 1, MipmapFilter::Linear);
+        let sizes = mips.iter().map(|mip| (mip.width, mip.height)).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![(2, 1), (1, 1)]);
+        assert_eq!(mips[0].texels, vec![5, 25]);
+        assert_eq!(mips[1].texels, vec![15]);
+
+        // A 1x3 column keeps its width
+        let column = level(1, 3, &[0, 100, 200]);
+        let mips = generate_mipmaps(&column, 1, MipmapFilter::Linear);
+        assert_eq!((mips[0].width, mips[0].height), (1, 1));
+        assert_eq!(mips[0].texels, vec![50]);
+    }
+}
@@ -0,0 +1,122 @@
+use std::cell::Cell;
+
+use ultraviolet::{Mat3, Mat4, Rotor3, Vec3};
+
+use super::scene::{InstancedMeshId, MeshId, NodeId, PointLightId, SpotLightId};
+
+/// What a node positions. Meshes and lights keep their own transform, which is applied in the
+/// node's space. The camera looks down the node's -Z axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NodeContent {
+    Empty,
+    Mesh(MeshId),
+    InstancedMesh(InstancedMeshId),
+    PointLight(PointLightId),
+    SpotLight(SpotLightId),
+    Camera,
+}
+
+/// Binds a skinned mesh's joints to nodes, so the mesh follows them. See `Scene::set_skin`.
+#[derive(Debug, Clone)]
+pub struct Skin {
+    /// The node of each joint index used by the mesh's skin vertices
+    pub joints: Vec<NodeId>,
+    /// For each joint, the transform from mesh space to the joint's space in the bind pose
+    pub inverse_bind_matrices: Vec<Mat4>,
+}
+
+/// A transform in the scene graph. Nodes are created and linked through `Scene`.
+pub struct Node {
+    pub name: Option<String>,
+
+    position: Vec3,
+    rotation: Rotor3,
+    scale: Vec3,
+    pub(crate) content: NodeContent,
+    pub(crate) parent: Option<NodeId>,
+    pub(crate) children: Vec<NodeId>,
+
+    world: Cell<Mat4>,
+    // The local transform changed since the world transform was computed
+    pub(crate) dirty: Cell<bool>,
+}
+
+impl Node {
+    pub(crate) fn new(content: NodeContent, parent: Option<NodeId>) -> Self {
+        Node {
+            name: None,
+            position: Vec3::zero(),
+            rotation: Rotor3::identity(),
+            scale: Vec3::broadcast(1.0),
+            content,
+            parent,
+            children: Vec::new(),
+            world: Cell::new(Mat4::identity()),
+            dirty: Cell::new(true),
+        }
+    }
+
+    pub fn position(&self) -> Vec3 { self.position }
+    pub fn rotation(&self) -> Rotor3 { self.rotation }
+    pub fn scale(&self) -> Vec3 { self.scale }
+    pub fn content(&self) -> NodeContent { self.content }
+    pub fn parent(&self) -> Option<NodeId> { self.parent }
+    pub fn children(&self) -> &[NodeId] { &self.children }
+
+    pub fn set_position(&mut self, position: Vec3) {
+        self.position = position;
+        self.dirty.set(true);
+    }
+
+    pub fn set_rotation(&mut self, rotation: Rotor3) {
+        self.rotation = rotation;
+        self.dirty.set(true);
+    }
+
+    pub fn set_scale(&mut self, scale: Vec3) {
+        self.scale = scale;
+        self.dirty.set(true);
+    }
+
+    /// Set the local transform from a matrix, which is decomposed into translation, rotation
+    /// and scale. Shear can't be represented and is lost.
+    pub fn set_local_transform(&mut self, transform: Mat4) {
+        let (position, rotation, scale) = decompose(transform);
+        self.position = position;
+        self.rotation = rotation;
+        self.scale = scale;
+        self.dirty.set(true);
+    }
+
+    pub fn local_transform(&self) -> Mat4 {
+        Mat4::from_translation(self.position) *
+            self.rotation.into_matrix().into_homogeneous() *
+            Mat4::from_nonuniform_scale(self.scale)
+    }
+
+    pub(crate) fn cached_world_transform(&self) -> Mat4 { self.world.get() }
+
+    pub(crate) fn update_world_transform(&self, parent_world: Mat4) {
+        self.world.set(parent_world * self.local_transform());
+        self.dirty.set(false);
+    }
+}
+
+fn decompose(transform: Mat4) -> (Vec3, Rotor3, Vec3) {
+    let translation = transform.cols[3].xyz();
+    let x = transform.cols[0].xyz();
+    let y = transform.cols[1].xyz();
+    let z = transform.cols[2].xyz();
+
+    let mut scale = Vec3::new(x.mag(), y.mag(), z.mag());
+    // A mirrored transform is represented by a negative X scale
+    if x.cross(y).dot(z) < 0.0 {
+        scale.x = -scale.x;
+    }
+    if scale.x == 0.0 || scale.y == 0.0 || scale.z == 0.0 {
+        return (translation, Rotor3::identity(), scale);
+    }
+
+    let rotation = Mat3::new(x / scale.x, y / scale.y, z / scale.z).into_rotor3();
+    (translation, rotation.normalized(), scale)
+}
@@ -1,8 +1,11 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use super::resources::ResourceLoader;
+use super::resources::{ResourceLoader, TextureOptions};
 use super::compute_tangents::compute_tangents;
-use super::mesh::{Vertex, MeshPartData, MeshPartGeometry, MaterialData, MaterialFactors};
+use super::mesh::{
+    Vertex, MeshPartData, MeshPartGeometry, MaterialData, MaterialFactors, TextureSlots, Topology,
+    VertexFormat,
+};
 
 pub fn load_obj(
     resources: &mut ResourceLoader,
@@ -38,53 +41,36 @@ pub fn load_obj(
             });
         }
 
-        let mut geometry = MeshPartGeometry { vertices, indices: mesh.indices.clone() };
+        let mut geometry = MeshPartGeometry {
+            vertices,
+            indices: mesh.indices.clone(),
+            topology: Topology::Triangles,
+            vertex_format: VertexFormat::Full,
+            skin: None,
+            extra_attributes: None,
+            morph_targets: Vec::new(),
+        };
         compute_tangents(&mut geometry);
 
         let path_prefix = path.as_ref().parent()
             .map(|p| p.to_owned())
             .unwrap_or("".into());
 
-        let (
-            diffuse, texture_path, normal_path,
-            metallic_roughness_path, ao_path, emissive_path,
-        ) = if let Some(material_id) = mesh.material_id {
-            let diffuse_texture = materials[material_id].diffuse_texture.clone();
-            let texture_path = if diffuse_texture.len() > 0 {
-                Some(path_prefix.join(diffuse_texture))
-            } else {
-                None
-            };
-
-            let normal_path = materials[material_id].unknown_param.get("norm")
-                .or(materials[material_id].unknown_param.get("map_Bump"))
-                .map(|p| path_prefix.join(p));
-
-            let metallic_roughness_path =
-                materials[material_id].unknown_param.get("metallic_roughness").or(
-                    materials[material_id].unknown_param.get("metal_rough")
-                ).map(|p| path_prefix.join(p));
-
-            let ao_path =
-                materials[material_id].unknown_param.get("ao").map(|p| path_prefix.join(p));
-
-            let emissive_path =
-                materials[material_id].unknown_param.get("emissive").map(|p| path_prefix.join(p));
+        let (diffuse, paths) = if let Some(material_id) = mesh.material_id {
+            let material = &materials[material_id];
             (
-                [
-                    materials[material_id].diffuse[0],
-                    materials[material_id].diffuse[1],
-                    materials[material_id].diffuse[2],
-                    1.0,
-                ],
-                texture_path,
-                normal_path,
-                metallic_roughness_path,
-                ao_path,
-                emissive_path,
+                [material.diffuse[0], material.diffuse[1], material.diffuse[2], 1.0],
+                material_texture_paths(material, &path_prefix),
             )
         } else {
-            ([1.0, 1.0, 1.0, 1.0], None, None, None, None, None)
+            ([1.0, 1.0, 1.0, 1.0], TextureSlots::default())
+        };
+
+        // Maps that fail to load fall back to the material's neutral textures
+        let mut load_texture = |path: PathBuf, options| {
+            resources.load_texture(&path, options)
+                .map_err(|err| log::warn!("skipping texture {}: {}", path.display(), err))
+                .ok()
         };
 
         parts.push(MeshPartData {
@@ -96,13 +82,27 @@ pub fn load_obj(
                     rough: 1.0,
                     emissive: [1.0, 1.0, 1.0],
                     extra_emissive: [0.0, 0.0, 0.0],
+                    ..Default::default()
                 },
                 lighting: true,
-                texture: texture_path.map(|p| resources.load_texture(p, true)),
-                normal: normal_path.map(|p| resources.load_texture(p, false)),
-                metallic_roughness: metallic_roughness_path.map(|p| resources.load_texture(p, false)),
-                ao: ao_path.map(|p| resources.load_texture(p, false)),
-                emissive: emissive_path.map(|p| resources.load_texture(p, true)),
+                texture: paths.texture.and_then(|p| load_texture(p, TextureOptions::srgb())),
+                normal: paths.normal.and_then(|p| load_texture(p, TextureOptions::normal_map())),
+                metallic_roughness: paths.metallic_roughness
+                    .and_then(|p| load_texture(p, TextureOptions::metallic_roughness())),
+                ao: paths.ao.and_then(|p| load_texture(p, TextureOptions::linear())),
+                emissive: paths.emissive.and_then(|p| load_texture(p, TextureOptions::srgb())),
+                clearcoat: None,
+                clearcoat_roughness: None,
+                clearcoat_normal: None,
+                sheen_color: None,
+                sheen_roughness: None,
+                specular: None,
+                specular_color: None,
+                transmission: None,
+                thickness: None,
+                texture_transforms: Default::default(),
+                samplers: Default::default(),
+                texture_uv_sets: Default::default(),
             },
         });
     }
@@ -110,3 +110,58 @@ pub fn load_obj(
     parts
 }
 
+
+/// List the textures referenced by an OBJ file's materials, along with how each is used.
+pub fn obj_texture_paths(
+    path: impl AsRef<Path>,
+) -> Result<Vec<(PathBuf, TextureOptions)>, tobj::LoadError> {
+    let (_, materials) = tobj::load_obj(path.as_ref(), false)?;
+    let path_prefix = path.as_ref().parent()
+        .map(|p| p.to_owned())
+        .unwrap_or("".into());
+
+    let mut textures = Vec::new();
+    for material in &materials {
+        let paths = material_texture_paths(material, &path_prefix);
+        let slots = [
+            (paths.texture, TextureOptions::srgb()),
+            (paths.normal, TextureOptions::normal_map()),
+            (paths.metallic_roughness, TextureOptions::metallic_roughness()),
+            (paths.ao, TextureOptions::linear()),
+            (paths.emissive, TextureOptions::srgb()),
+        ];
+        for (path, options) in slots.iter().cloned() {
+            if let Some(path) = path {
+                textures.push((path, options));
+            }
+        }
+    }
+
+    Ok(textures)
+}
+
+/// The texture paths of a material, including the non-standard `norm`/`map_Bump`,
+/// `metallic_roughness`/`metal_rough`, `ao` and `emissive` parameters.
+fn material_texture_paths(
+    material: &tobj::Material,
+    path_prefix: &Path,
+) -> TextureSlots<Option<PathBuf>> {
+    let param = |names: &[&str]| {
+        names.iter()
+            .find_map(|name| material.unknown_param.get(*name))
+            .map(|p| path_prefix.join(p))
+    };
+
+    TextureSlots {
+        texture: if material.diffuse_texture.len() > 0 {
+            Some(path_prefix.join(&material.diffuse_texture))
+        } else {
+            None
+        },
+        normal: param(&["norm", "map_Bump"]),
+        metallic_roughness: param(&["metallic_roughness", "metal_rough"]),
+        ao: param(&["ao"]),
+        emissive: param(&["emissive"]),
+        ..Default::default()
+    }
+}
@@ -1,10 +1,19 @@
 use std::path::Path;
+use std::rc::Rc;
 
-use crate::resources::{Resources, ResourceLoader};
-use super::mesh::{Mesh, MeshPass, MeshPartData};
+use crate::resources::{Resources, ResourceLoader, SamplerData};
+use crate::texture::Texture;
+use super::mesh::{
+    CustomMaterialDescriptor, CustomMaterialId, InstancedMesh, Material, MaterialFactors, Mesh,
+    MeshAsset, MeshInstance, MeshPass, MeshPartData, TextureSlot,
+};
 use super::obj::load_obj;
-use super::gltf::{load_gltf, load_gltf_from_reader, load_gltf_single_mesh, GltfLoadError};
-use super::scene::Scene;
+use super::gltf::{
+    load_gltf, load_gltf_from_reader, load_gltf_scene, load_gltf_single_mesh, GltfLight,
+    GltfLoadError, GltfScene,
+};
+use super::node::{NodeContent, Skin};
+use super::scene::{NodeId, Scene};
 
 pub struct Renderer {
     pub device: wgpu::Device,
@@ -20,11 +29,12 @@ impl Renderer {
         mut queue: wgpu::Queue,
     ) -> Renderer {
         let mesh_pass = MeshPass::init(surface_config, &mut device, &mut queue);
+        let resources = Resources::new(&mut device, &mut queue);
 
         Renderer {
             device,
             queue,
-            resources: Resources::new(),
+            resources,
             mesh_pass,
         }
     }
@@ -35,7 +45,7 @@ impl Renderer {
         encoder: &mut wgpu::CommandEncoder,
         scene: &Scene,
     ) {
-        self.mesh_pass.render(&self.device, render_target, encoder, scene);
+        self.mesh_pass.render(&self.device, &self.queue, render_target, encoder, scene);
     }
 
     pub fn mesh_from_file(&mut self, path: impl AsRef<std::path::Path>, lighting: bool) -> Mesh {
@@ -49,11 +59,92 @@ impl Renderer {
     pub fn mesh_from_parts(&mut self, parts: &[MeshPartData]) -> Mesh {
         Mesh::from_parts(
             &mut self.device,
+            &mut self.resources,
             &self.mesh_pass,
             parts,
         )
     }
 
+    /// Upload a model once, to be placed by any number of meshes with `mesh_from_asset`.
+    pub fn mesh_asset_from_parts(&mut self, parts: &[MeshPartData]) -> Rc<MeshAsset> {
+        Rc::new(MeshAsset::from_parts(
+            &mut self.device,
+            &mut self.resources,
+            &self.mesh_pass,
+            parts,
+        ))
+    }
+
+    /// Create a mesh sharing the geometry and materials of `asset`.
+    pub fn mesh_from_asset(&mut self, asset: &Rc<MeshAsset>) -> Mesh {
+        Mesh::from_asset(&mut self.device, &self.mesh_pass, asset.clone())
+    }
+
+    /// Create a mesh drawn once for each of `instances`, with a single draw call per part.
+    pub fn instanced_mesh_from_parts(
+        &mut self,
+        parts: &[MeshPartData],
+        instances: &[MeshInstance],
+    ) -> InstancedMesh {
+        InstancedMesh::from_parts(
+            &mut self.device,
+            &mut self.resources,
+            &self.mesh_pass,
+            parts,
+            instances,
+        )
+    }
+
+    /// Create an instanced mesh sharing the geometry and materials of `asset`.
+    pub fn instanced_mesh_from_asset(
+        &mut self,
+        asset: &Rc<MeshAsset>,
+        instances: &[MeshInstance],
+    ) -> InstancedMesh {
+        InstancedMesh::from_asset(&mut self.device, &self.mesh_pass, asset.clone(), instances)
+    }
+
+    /// Replace the instances of an instanced mesh.
+    pub fn set_mesh_instances(&mut self, mesh: &mut InstancedMesh, instances: &[MeshInstance]) {
+        mesh.set_instances(&mut self.device, &self.queue, instances);
+    }
+
+    /// Register a user-defined material pipeline. See `CustomMaterialDescriptor`.
+    pub fn register_material(&mut self, desc: &CustomMaterialDescriptor) -> CustomMaterialId {
+        self.mesh_pass.register_material(&mut self.device, desc)
+    }
+
+    /// Create a material for a registered custom pipeline. It can be assigned to the `material`
+    /// of any `MeshPart`.
+    pub fn custom_material(
+        &mut self,
+        id: CustomMaterialId,
+        factors: MaterialFactors,
+        entries: &[wgpu::BindGroupEntry],
+    ) -> Material {
+        Material::custom(&mut self.device, &self.mesh_pass, id, factors, entries)
+    }
+
+    /// Replace a single texture of a built-in material, rebuilding only its bind group.
+    pub fn set_material_texture(
+        &mut self,
+        material: &mut Material,
+        slot: TextureSlot,
+        texture: Option<Rc<Texture>>,
+    ) {
+        material.set_texture(&mut self.device, &mut self.resources, &self.mesh_pass, slot, texture);
+    }
+
+    /// Change how a single texture slot of a built-in material is sampled.
+    pub fn set_material_sampler(
+        &mut self,
+        material: &mut Material,
+        slot: TextureSlot,
+        sampler: SamplerData,
+    ) {
+        material.set_sampler(&mut self.device, &mut self.resources, &self.mesh_pass, slot, sampler);
+    }
+
     pub fn mesh_parts_from_file(&mut self, path: impl AsRef<Path>) -> Vec<MeshPartData> {
         let mut resource_loader = ResourceLoader::new(
             &mut self.device, &mut self.queue, &mut self.resources,
@@ -93,6 +184,127 @@ impl Renderer {
         mesh_parts
     }
 
+    /// Load the node hierarchy of a glTF file. See `instantiate_gltf_scene`.
+    pub fn gltf_scene_from_file(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<GltfScene, GltfLoadError> {
+        let mut resource_loader = ResourceLoader::new(
+            &mut self.device, &mut self.queue, &mut self.resources,
+        );
+
+        let gltf_scene = load_gltf_scene(&mut resource_loader, path)?;
+
+        self.queue.submit(None);
+
+        Ok(gltf_scene)
+    }
+
+    /// Add the node hierarchy of `gltf_scene` to `scene` under `parent`, with the mesh and light
+    /// of every node that has them. The first node with a camera holds the scene's camera,
+    /// which takes that camera's projection. Returns the scene node created for each glTF node,
+    /// indexed like `gltf_scene.nodes`; nodes outside the glTF scene are left as `None`.
+    ///
+    /// Each glTF mesh is uploaded once, and shared by every node using it.
+    pub fn instantiate_gltf_scene(
+        &mut self,
+        scene: &mut Scene,
+        gltf_scene: &GltfScene,
+        parent: Option<NodeId>,
+    ) -> Vec<Option<NodeId>> {
+        let mut node_ids = vec![None; gltf_scene.nodes.len()];
+        let mut assets: Vec<Option<Rc<MeshAsset>>> = vec![None; gltf_scene.meshes.len()];
+        let mut camera_attached = false;
+        let mut stack = gltf_scene.roots.iter().map(|&root| (root, parent)).collect::<Vec<_>>();
+        while let Some((index, parent)) = stack.pop() {
+            let gltf_node = &gltf_scene.nodes[index];
+            let mut contents = Vec::new();
+            let asset = gltf_node.mesh.map(|mesh| match assets[mesh] {
+                Some(ref asset) => asset.clone(),
+                None => {
+                    let asset = self.mesh_asset_from_parts(&gltf_scene.meshes[mesh]);
+                    assets[mesh] = Some(asset.clone());
+                    asset
+                }
+            });
+            if let (Some(asset), Some(instances)) = (&asset, &gltf_node.instances) {
+                let mesh = self.instanced_mesh_from_asset(asset, instances);
+                contents.push(NodeContent::InstancedMesh(scene.add_instanced_mesh(mesh)));
+            } else if let Some(asset) = asset {
+                let mut mesh = self.mesh_from_asset(&asset);
+                let weights = mesh.morph_weights.iter_mut().zip(&gltf_node.morph_weights);
+                for (weight, &value) in weights {
+                    *weight = value;
+                }
+                contents.push(NodeContent::Mesh(scene.add_mesh(mesh)));
+            }
+            match gltf_node.light.map(|light| &gltf_scene.lights[light]) {
+                Some(GltfLight::Point(light)) => {
+                    contents.push(NodeContent::PointLight(scene.add_point_light(*light)));
+                }
+                Some(GltfLight::Spot(light)) => {
+                    contents.push(NodeContent::SpotLight(scene.add_spot_light(*light)));
+                }
+                Some(GltfLight::Directional { .. }) => {
+                    log::warn!(
+                        "skipping directional light of node {}, directional lights aren't supported yet",
+                        gltf_node.name.as_deref().unwrap_or("<unnamed>"),
+                    );
+                }
+                None => { }
+            }
+            if let (Some(camera), false) = (gltf_node.camera, camera_attached) {
+                scene.camera.set_projection(gltf_scene.cameras[camera].projection);
+                contents.push(NodeContent::Camera);
+                camera_attached = true;
+            }
+
+            let content = contents.first().copied().unwrap_or(NodeContent::Empty);
+            let id = scene.add_node(parent, content);
+            // A node holds one thing, so the rest go in child nodes
+            for &content in contents.iter().skip(1) {
+                scene.add_node(Some(id), content);
+            }
+
+            let node = scene.node_mut(id);
+            node.name = gltf_node.name.clone();
+            node.set_position(gltf_node.translation);
+            node.set_rotation(gltf_node.rotation);
+            node.set_scale(gltf_node.scale);
+
+            node_ids[index] = Some(id);
+            stack.extend(gltf_node.children.iter().map(|&child| (child, Some(id))));
+        }
+
+        // Joints can be anywhere in the hierarchy, so skins are bound once every node exists
+        for (gltf_node, &id) in gltf_scene.nodes.iter().zip(node_ids.iter()) {
+            if let (Some(skin), Some(id)) = (gltf_node.skin, id) {
+                let skin = &gltf_scene.skins[skin];
+                let joints = skin.joints.iter()
+                    .map(|&joint| node_ids[joint])
+                    .collect::<Option<Vec<_>>>();
+                let joints = match joints {
+                    Some(joints) => joints,
+                    None => {
+                        log::warn!(
+                            "skipping skin of node {}, some of its joints are outside of the scene",
+                            gltf_node.name.as_deref().unwrap_or("<unnamed>"),
+                        );
+                        continue;
+                    }
+                };
+                if let NodeContent::Mesh(mesh) = scene.node(id).content() {
+                    scene.set_skin(mesh, Some(Skin {
+                        joints,
+                        inverse_bind_matrices: skin.inverse_bind_matrices.clone(),
+                    }));
+                }
+            }
+        }
+
+        node_ids
+    }
+
     pub fn gltf_single_mesh_parts(
         &mut self, path: impl AsRef<Path>, mesh_name: &str,
     ) -> Result<Option<(Vec<MeshPartData>, gltf::scene::Transform)>, GltfLoadError> {
@@ -1,7 +1,16 @@
 use std::collections::HashMap;
+use std::mem;
+use std::num::NonZeroU8;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
+use bytemuck::Pod;
+use half::f16;
+
+use crate::compressed_texture::{is_ktx2, load_baked_texture, load_ktx2, CompressedTextureError};
+use crate::mipmaps::{generate_mipmaps, srgb_to_linear, MipLevel, MipmapFilter, Texel};
+use crate::texture::Texture;
+
 pub struct ResourceLoader<'d, 'q, 'r> {
     device: &'d mut wgpu::Device,
     queue: &'q mut wgpu::Queue,
@@ -19,127 +28,496 @@ impl<'d, 'q, 'r> ResourceLoader<'d, 'q, 'r> {
         }
     }
 
+    /// Load a texture, or get it from the cache. Images are decoded with `image`, except `.ktx2`
+    /// files which are uploaded in a GPU compressed format when the device has one of the
+    /// `TEXTURE_COMPRESSION_*` features enabled. An up to date sidecar baked by `wgpu-pbr-bake`
+    /// is used instead of the image when the device supports its format. KTX2 files the device
+    /// can't sample, e.g. BC7 without `TEXTURE_COMPRESSION_BC`, are an error, as are missing
+    /// files and images that can't be decoded.
     pub fn load_texture(
         &mut self,
         path: impl AsRef<Path>,
-        srgb: bool,
-    ) -> Rc<wgpu::Texture> {
-        self.resources.load_texture(self.device, self.queue, path, srgb)
+        options: TextureOptions,
+    ) -> Result<Rc<Texture>, TextureLoadError> {
+        self.resources.load_texture(self.device, self.queue, path, options)
     }
 
     pub fn texture_from_bytes(
         &mut self,
         name: impl AsRef<Path>,
         texture_bytes: &[u8],
-        srgb: bool,
-    ) -> Rc<wgpu::Texture> {
+        options: TextureOptions,
+    ) -> Result<Rc<Texture>, TextureLoadError> {
         let ResourceLoader { device, queue, resources } = self;
-        resources.textures.entry(name.as_ref().into())
-            .or_insert_with(|| {
-                Rc::new(Resources::texture_from_bytes(
-                    device, queue, srgb, texture_bytes,
-                ))
-            })
-            .clone()
+        let key = (name.as_ref().to_owned(), options);
+        if let Some(texture) = resources.textures.get(&key) {
+            return Ok(texture.clone());
+        }
+
+        let texture = Rc::new(Resources::texture_from_bytes(device, queue, options, texture_bytes)?);
+        resources.textures.insert(key, texture.clone());
+        Ok(texture)
+    }
+}
+
+/// How a texture is interpreted when it's uploaded to the GPU. The same image loaded with
+/// different options is cached as separate textures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TextureOptions {
+    /// The texture holds sRGB encoded color, e.g. base color and emissive maps
+    pub srgb: bool,
+    /// The texture is a tangent space normal map, so its mip levels are renormalized
+    pub normal_map: bool,
+    /// The texture is a glTF metallic roughness map. Only its green (roughness) and blue
+    /// (metallic) channels are kept, stored as two channel (roughness, metallic). Two channel
+    /// KTX2 files are sampled the same way.
+    pub metallic_roughness: bool,
+    /// Generate a full mip chain on upload
+    pub mipmaps: bool,
+}
+
+impl TextureOptions {
+    pub fn srgb() -> Self {
+        TextureOptions { srgb: true, normal_map: false, metallic_roughness: false, mipmaps: true }
+    }
+
+    pub fn linear() -> Self {
+        TextureOptions { srgb: false, normal_map: false, metallic_roughness: false, mipmaps: true }
+    }
+
+    pub fn normal_map() -> Self {
+        TextureOptions { srgb: false, normal_map: true, metallic_roughness: false, mipmaps: true }
+    }
+
+    pub fn metallic_roughness() -> Self {
+        TextureOptions { srgb: false, normal_map: false, metallic_roughness: true, mipmaps: true }
+    }
+
+    /// Only upload the base level, e.g. for pixel art or UI textures.
+    pub fn without_mipmaps(self) -> Self {
+        TextureOptions { mipmaps: false, ..self }
+    }
+
+    pub(crate) fn mipmap_filter(&self) -> MipmapFilter {
+        if self.normal_map {
+            MipmapFilter::Normal
+        } else if self.srgb {
+            MipmapFilter::Srgb
+        } else {
+            MipmapFilter::Linear
+        }
+    }
+}
+
+/// How a texture is sampled: wrap modes and filters, as described by a glTF sampler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerData {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    /// When false, only the base mip level is sampled
+    pub mipmaps: bool,
+}
+
+impl Default for SamplerData {
+    fn default() -> Self {
+        SamplerData {
+            address_mode_u: wgpu::AddressMode::Repeat,
+            address_mode_v: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            mipmaps: true,
+        }
     }
 }
 
 pub struct Resources {
-    textures: HashMap<PathBuf, Rc<wgpu::Texture>>,
+    textures: HashMap<(PathBuf, TextureOptions), Rc<Texture>>,
+    samplers: HashMap<(SamplerData, Option<NonZeroU8>), Rc<wgpu::Sampler>>,
+    anisotropy_clamp: Option<NonZeroU8>,
+
+    // 1x1 textures bound in place of any material map that wasn't provided
+    white_texture: Rc<Texture>,
+    black_texture: Rc<Texture>,
+    flat_normal_texture: Rc<Texture>,
 }
 
 impl Resources {
-    pub fn new() -> Self {
+    pub fn new(device: &mut wgpu::Device, queue: &mut wgpu::Queue) -> Self {
         Resources {
             textures: HashMap::new(),
+            samplers: HashMap::new(),
+            anisotropy_clamp: None,
+
+            white_texture: Rc::new(Self::solid_color_texture(device, queue, [255, 255, 255, 255])),
+            black_texture: Rc::new(Self::solid_color_texture(device, queue, [0, 0, 0, 255])),
+            flat_normal_texture: Rc::new(Self::solid_color_texture(device, queue, [128, 128, 255, 255])),
         }
     }
 
+    /// Neutral fallback for base color, metallic roughness, ambient occlusion and layer maps.
+    pub fn white_texture(&self) -> &Rc<Texture> { &self.white_texture }
+
+    /// Neutral fallback for emissive maps.
+    pub fn black_texture(&self) -> &Rc<Texture> { &self.black_texture }
+
+    /// Neutral fallback for tangent space normal and clearcoat normal maps, i.e. `(0.5, 0.5, 1.0)`.
+    pub fn flat_normal_texture(&self) -> &Rc<Texture> { &self.flat_normal_texture }
+
+    /// Enable anisotropic filtering for samplers that filter linearly. `clamp` must be 1, 2, 4, 8
+    /// or 16. Only materials created afterwards are affected, and it's silently ignored by devices
+    /// without `DownlevelFlags::ANISOTROPIC_FILTERING`.
+    pub fn set_anisotropy_clamp(&mut self, clamp: Option<NonZeroU8>) {
+        if let Some(clamp) = clamp {
+            assert!(
+                clamp.get().is_power_of_two() && clamp.get() <= 16,
+                "invalid anisotropy clamp {}", clamp,
+            );
+        }
+        self.anisotropy_clamp = clamp;
+    }
+
+    pub fn anisotropy_clamp(&self) -> Option<NonZeroU8> { self.anisotropy_clamp }
+
+    /// Get a sampler from the cache, creating it on first use.
+    pub fn sampler(&mut self, device: &mut wgpu::Device, data: &SamplerData) -> Rc<wgpu::Sampler> {
+        // Anisotropy only makes sense, and is only valid, with linear filtering
+        let anisotropy_clamp = self.anisotropy_clamp.filter(|_| {
+            data.mag_filter == wgpu::FilterMode::Linear
+                && data.min_filter == wgpu::FilterMode::Linear
+                && data.mipmap_filter == wgpu::FilterMode::Linear
+        });
+
+        self.samplers.entry((*data, anisotropy_clamp))
+            .or_insert_with(|| {
+                Rc::new(device.create_sampler(&wgpu::SamplerDescriptor {
+                    label: None,
+                    address_mode_u: data.address_mode_u,
+                    address_mode_v: data.address_mode_v,
+                    address_mode_w: wgpu::AddressMode::Repeat,
+                    mag_filter: data.mag_filter,
+                    min_filter: data.min_filter,
+                    mipmap_filter: data.mipmap_filter,
+                    lod_min_clamp: -100.0,
+                    lod_max_clamp: if data.mipmaps { 100.0 } else { 0.0 },
+                    compare: None,
+                    anisotropy_clamp,
+                    ..Default::default()
+                }))
+            })
+            .clone()
+    }
+
     pub fn load_texture(
         &mut self,
         device: &mut wgpu::Device,
         queue: &mut wgpu::Queue,
         path: impl AsRef<Path>,
-        srgb: bool,
-    ) -> Rc<wgpu::Texture> {
-        self.textures.entry(path.as_ref().into())
-            .or_insert_with(|| Rc::new(Self::texture_from_path(device, queue, path, srgb)))
-            .clone()
+        options: TextureOptions,
+    ) -> Result<Rc<Texture>, TextureLoadError> {
+        let key = (path.as_ref().to_owned(), options);
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(texture.clone());
+        }
+
+        let texture = Rc::new(Self::texture_from_path(device, queue, &path, options)?);
+        self.textures.insert(key, texture.clone());
+        Ok(texture)
     }
 
     fn texture_from_path(
         device: &mut wgpu::Device,
         queue: &mut wgpu::Queue,
         texture_path: impl AsRef<Path>,
-        srgb: bool,
-    ) -> wgpu::Texture {
-        let img = match image::open(texture_path).unwrap() {
-            image::DynamicImage::ImageRgba8(img) => img,
-            img @ _ => img.to_rgba(),
-        };
+        options: TextureOptions,
+    ) -> Result<Texture, TextureLoadError> {
+        let texture_path = texture_path.as_ref();
+        if texture_path.extension().map_or(false, |ext| ext == "ktx2") {
+            let bytes = std::fs::read(texture_path)?;
+            return Ok(load_ktx2(device, queue, options, &bytes)?);
+        }
+
+        if let Some(texture) = load_baked_texture(device, queue, options, texture_path) {
+            return Ok(texture);
+        }
 
-        Self::texture_to_gpu(device, queue, srgb, img)
+        let img = image::open(texture_path)?;
+
+        Ok(Self::texture_to_gpu(device, queue, options, img))
     }
 
     fn texture_from_bytes(
         device: &mut wgpu::Device,
         queue: &mut wgpu::Queue,
-        srgb: bool,
+        options: TextureOptions,
         texture_bytes: &[u8],
-    ) -> wgpu::Texture {
+    ) -> Result<Texture, TextureLoadError> {
+        if is_ktx2(texture_bytes) {
+            return Ok(load_ktx2(device, queue, options, texture_bytes)?);
+        }
+
         // Create the texture
-        let img = match image::load_from_memory(texture_bytes).unwrap() {
-            image::DynamicImage::ImageRgba8(img) => img,
-            img @ _ => img.to_rgba(),
-        };
+        let img = image::load_from_memory(texture_bytes)?;
 
-        Self::texture_to_gpu(device, queue, srgb, img)
+        Ok(Self::texture_to_gpu(device, queue, options, img))
     }
 
+    fn solid_color_texture(
+        device: &mut wgpu::Device,
+        queue: &mut wgpu::Queue,
+        color: [u8; 4],
+    ) -> Texture {
+        let img = image::RgbaImage::from_pixel(1, 1, image::Rgba(color));
+
+        Self::texture_to_gpu(
+            device, queue,
+            TextureOptions::linear().without_mipmaps(),
+            image::DynamicImage::ImageRgba8(img),
+        )
+    }
+
+    /// Upload an image in the most compact format that preserves it. Linear single and dual
+    /// channel images stay R8/RG8, metallic roughness maps are packed to RG8, and 16 bit images
+    /// are stored as half floats since wgpu has no 16 bit normalized formats. Everything else is
+    /// expanded to RGBA8.
     fn texture_to_gpu(
         device: &mut wgpu::Device,
         queue: &mut wgpu::Queue,
-        srgb: bool,
-        img: image::RgbaImage,
-    ) -> wgpu::Texture {
-        let size = img.width();
+        options: TextureOptions,
+        img: image::DynamicImage,
+    ) -> Texture {
+        use image::DynamicImage::*;
+
+        // sRGB and normal map data always needs all three color channels
+        let linear = !options.srgb && !options.normal_map;
+        let mipmap_filter = options.mipmap_filter();
+
+        match img {
+            ImageLuma8(img) if linear => {
+                let base = MipLevel {
+                    width: img.width(),
+                    height: img.height(),
+                    texels: img.into_raw(),
+                };
+                Self::mip_chain_to_gpu(
+                    device, queue, options, wgpu::TextureFormat::R8Unorm, 1, mipmap_filter, base,
+                )
+            }
+            ImageLuma16(img) if linear => {
+                let base = MipLevel {
+                    width: img.width(),
+                    height: img.height(),
+                    texels: img.into_raw().into_iter().map(unorm16_to_f16).collect(),
+                };
+                Self::mip_chain_to_gpu(
+                    device, queue, options, wgpu::TextureFormat::R16Float, 1, mipmap_filter, base,
+                )
+            }
+            // Metallic roughness maps keep their green (roughness) and blue (metallic) channels.
+            // Both are the luminance of grayscale images with alpha.
+            ImageLumaA16(_) | ImageRgb16(_) | ImageRgba16(_) if options.metallic_roughness => {
+                let img = img.to_rgba16();
+                let base = MipLevel {
+                    width: img.width(),
+                    height: img.height(),
+                    texels: img.into_raw().chunks(4)
+                        .flat_map(|texel| [unorm16_to_f16(texel[1]), unorm16_to_f16(texel[2])])
+                        .collect(),
+                };
+                Self::mip_chain_to_gpu(
+                    device, queue, options, wgpu::TextureFormat::Rg16Float, 2, mipmap_filter, base,
+                )
+            }
+            _ if options.metallic_roughness => {
+                let img = img.to_rgba8();
+                let base = MipLevel {
+                    width: img.width(),
+                    height: img.height(),
+                    texels: img.into_raw().chunks(4).flat_map(|texel| [texel[1], texel[2]]).collect(),
+                };
+                Self::mip_chain_to_gpu(
+                    device, queue, options, wgpu::TextureFormat::Rg8Unorm, 2, mipmap_filter, base,
+                )
+            }
+            ImageLumaA8(img) if linear => {
+                let base = MipLevel {
+                    width: img.width(),
+                    height: img.height(),
+                    texels: img.into_raw(),
+                };
+                Self::mip_chain_to_gpu(
+                    device, queue, options, wgpu::TextureFormat::Rg8Unorm, 2, mipmap_filter, base,
+                )
+            }
+            ImageLumaA16(img) if linear => {
+                let base = MipLevel {
+                    width: img.width(),
+                    height: img.height(),
+                    texels: img.into_raw().into_iter().map(unorm16_to_f16).collect(),
+                };
+                Self::mip_chain_to_gpu(
+                    device, queue, options, wgpu::TextureFormat::Rg16Float, 2, mipmap_filter, base,
+                )
+            }
+            img @ ImageLuma16(_) | img @ ImageLumaA16(_) | img @ ImageRgb16(_) | img @ ImageRgba16(_) => {
+                // There's no sRGB float format, so color is converted to linear before upload
+                let img = img.to_rgba16();
+                let base = MipLevel {
+                    width: img.width(),
+                    height: img.height(),
+                    texels: img.into_raw().chunks(4)
+                        .flat_map(|texel| {
+                            let mut texel = [
+                                texel[0] as f32 / 65535.0,
+                                texel[1] as f32 / 65535.0,
+                                texel[2] as f32 / 65535.0,
+                                texel[3] as f32 / 65535.0,
+                            ];
+                            if options.srgb {
+                                for c in 0..3 {
+                                    texel[c] = srgb_to_linear(texel[c]);
+                                }
+                            }
+                            texel.iter().map(|&v| f16::from_f32(v)).collect::<Vec<_>>()
+                        })
+                        .collect(),
+                };
+                let mipmap_filter = match mipmap_filter {
+                    MipmapFilter::Srgb => MipmapFilter::Linear,
+                    filter => filter,
+                };
+                Self::mip_chain_to_gpu(
+                    device, queue, options, wgpu::TextureFormat::Rgba16Float, 4, mipmap_filter, base,
+                )
+            }
+            img => {
+                let img = match img {
+                    ImageRgba8(img) => img,
+                    img => img.to_rgba8(),
+                };
+                let format = if options.srgb {
+                    wgpu::TextureFormat::Rgba8UnormSrgb
+                } else {
+                    wgpu::TextureFormat::Rgba8Unorm
+                };
+                let base = MipLevel {
+                    width: img.width(),
+                    height: img.height(),
+                    texels: img.into_raw(),
+                };
+                Self::mip_chain_to_gpu(
+                    device, queue, options, format, 4, mipmap_filter, base,
+                )
+            }
+        }
+    }
+
+    fn mip_chain_to_gpu<T: Texel + Pod>(
+        device: &mut wgpu::Device,
+        queue: &mut wgpu::Queue,
+        options: TextureOptions,
+        format: wgpu::TextureFormat,
+        channels: usize,
+        mipmap_filter: MipmapFilter,
+        base: MipLevel<T>,
+    ) -> Texture {
         let texture_extent = wgpu::Extent3d {
-            width: size,
-            height: size,
+            width: base.width,
+            height: base.height,
             depth_or_array_layers: 1,
         };
-        let texels = img.into_raw();
+        let mips = if options.mipmaps {
+            generate_mipmaps(&base, channels, mipmap_filter)
+        } else {
+            Vec::new()
+        };
+        let mip_level_count = 1 + mips.len() as u32;
+
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: None,
             size: texture_extent,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: if srgb {
-                wgpu::TextureFormat::Rgba8UnormSrgb
-            } else {
-                wgpu::TextureFormat::Rgba8Unorm
-            },
+            format,
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
         });
 
-        queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            bytemuck::cast_slice(&texels),
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: std::num::NonZeroU32::new(4 * size),
-                rows_per_image: std::num::NonZeroU32::new(size),
-            },
-            texture_extent,
-        );
-
-        texture
+        let texel_size = (channels * mem::size_of::<T>()) as u32;
+        for (mip_level, level) in std::iter::once(&base).chain(mips.iter()).enumerate() {
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: mip_level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                bytemuck::cast_slice(&level.texels),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: std::num::NonZeroU32::new(texel_size * level.width),
+                    rows_per_image: std::num::NonZeroU32::new(level.height),
+                },
+                wgpu::Extent3d {
+                    width: level.width,
+                    height: level.height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        Texture::new(texture, format, texture_extent, mip_level_count)
+    }
+}
+
+fn unorm16_to_f16(v: u16) -> f16 {
+    f16::from_f32(v as f32 / 65535.0)
+}
+
+#[derive(Debug)]
+pub enum TextureLoadError {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Compressed(CompressedTextureError),
+}
+
+impl std::error::Error for TextureLoadError {
+    fn cause(&self) -> Option<&dyn std::error::Error> {
+        match *self {
+            TextureLoadError::Io(ref err) => Some(err),
+            TextureLoadError::Image(ref err) => Some(err),
+            TextureLoadError::Compressed(ref err) => Some(err),
+        }
     }
 }
 
+impl std::fmt::Display for TextureLoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            TextureLoadError::Io(ref err) => write!(f, "Texture IO error: {}", err),
+            TextureLoadError::Image(ref err) => write!(f, "Texture decode error: {}", err),
+            TextureLoadError::Compressed(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for TextureLoadError {
+    fn from(err: std::io::Error) -> TextureLoadError {
+        TextureLoadError::Io(err)
+    }
+}
+
+impl From<image::ImageError> for TextureLoadError {
+    fn from(err: image::ImageError) -> TextureLoadError {
+        TextureLoadError::Image(err)
+    }
+}
+
+impl From<CompressedTextureError> for TextureLoadError {
+    fn from(err: CompressedTextureError) -> TextureLoadError {
+        TextureLoadError::Compressed(err)
+    }
+}
@@ -1,18 +1,32 @@
-use slotmap::DenseSlotMap;
+use slotmap::{DenseSlotMap, SecondaryMap};
+use ultraviolet::{Mat4, Vec3};
 
 use super::{Camera, PointLight, SpotLight};
-use super::mesh::Mesh;
+use super::mesh::{InstancedMesh, Mesh};
+use super::node::{Node, NodeContent, Skin};
 
 pub type MeshId = slotmap::DefaultKey;
+pub type InstancedMeshId = slotmap::DefaultKey;
 pub type PointLightId = slotmap::DefaultKey;
 pub type SpotLightId = slotmap::DefaultKey;
+pub type NodeId = slotmap::DefaultKey;
 
 pub struct Scene {
     pub camera: Camera,
 
     pub(crate) meshes: DenseSlotMap<MeshId, Mesh>,
+    pub(crate) instanced_meshes: DenseSlotMap<InstancedMeshId, InstancedMesh>,
     pub(crate) point_lights: DenseSlotMap<PointLightId, PointLight>,
     pub(crate) spot_lights: DenseSlotMap<SpotLightId, SpotLight>,
+
+    nodes: DenseSlotMap<NodeId, Node>,
+    // The node each attached mesh or light belongs to
+    mesh_nodes: SecondaryMap<MeshId, NodeId>,
+    instanced_mesh_nodes: SecondaryMap<InstancedMeshId, NodeId>,
+    point_light_nodes: SecondaryMap<PointLightId, NodeId>,
+    spot_light_nodes: SecondaryMap<SpotLightId, NodeId>,
+    camera_node: Option<NodeId>,
+    skins: SecondaryMap<MeshId, Skin>,
 }
 
 impl Scene {
@@ -20,8 +34,17 @@ impl Scene {
         Scene {
             camera,
             meshes: DenseSlotMap::new(),
+            instanced_meshes: DenseSlotMap::new(),
             point_lights: DenseSlotMap::new(),
             spot_lights: DenseSlotMap::new(),
+
+            nodes: DenseSlotMap::new(),
+            mesh_nodes: SecondaryMap::new(),
+            instanced_mesh_nodes: SecondaryMap::new(),
+            point_light_nodes: SecondaryMap::new(),
+            spot_light_nodes: SecondaryMap::new(),
+            camera_node: None,
+            skins: SecondaryMap::new(),
         }
     }
 
@@ -29,8 +52,13 @@ impl Scene {
         self.meshes.insert(mesh)
     }
 
+    /// Remove a mesh. A node holding it is left empty.
     pub fn remove_mesh(&mut self, id: MeshId) {
         self.meshes.remove(id);
+        self.skins.remove(id);
+        if let Some(node) = self.mesh_nodes.remove(id) {
+            self.nodes[node].content = NodeContent::Empty;
+        }
     }
 
     pub fn mesh(&mut self, id: MeshId) -> &mut Mesh {
@@ -41,14 +69,56 @@ impl Scene {
         &mut self.meshes[id]
     }
 
+    /// Pose a skinned mesh from the world transforms of its joint nodes every frame, instead of
+    /// its `joint_matrices`. `None` removes the skin.
+    pub fn set_skin(&mut self, id: MeshId, skin: Option<Skin>) {
+        match skin {
+            Some(skin) => {
+                assert_eq!(skin.joints.len(), skin.inverse_bind_matrices.len());
+                self.skins.insert(id, skin);
+            }
+            None => { self.skins.remove(id); }
+        }
+    }
+
+    pub fn skin(&self, id: MeshId) -> Option<&Skin> {
+        self.skins.get(id)
+    }
+
+    ////////////////////////////////////
+
+    pub fn add_instanced_mesh(&mut self, mesh: InstancedMesh) -> InstancedMeshId {
+        self.instanced_meshes.insert(mesh)
+    }
+
+    /// Remove an instanced mesh. A node holding it is left empty.
+    pub fn remove_instanced_mesh(&mut self, id: InstancedMeshId) {
+        self.instanced_meshes.remove(id);
+        if let Some(node) = self.instanced_mesh_nodes.remove(id) {
+            self.nodes[node].content = NodeContent::Empty;
+        }
+    }
+
+    pub fn instanced_mesh(&self, id: InstancedMeshId) -> &InstancedMesh {
+        &self.instanced_meshes[id]
+    }
+
+    pub fn instanced_mesh_mut(&mut self, id: InstancedMeshId) -> &mut InstancedMesh {
+        &mut self.instanced_meshes[id]
+    }
+
     ////////////////////////////////////
 
     pub fn add_point_light(&mut self, point_light: PointLight) -> PointLightId {
         self.point_lights.insert(point_light)
     }
 
+    /// Remove a point light. A node holding it is left empty.
     pub fn remove_point_light(&mut self, id: PointLightId) {
         self.point_lights.remove(id);
+        if let Some(node) = self.point_light_nodes.remove(id) {
+            self.nodes[node].content = NodeContent::Empty;
+        }
     }
 
     pub fn point_light(&mut self, id: PointLightId) -> &mut PointLight {
@@ -61,11 +131,251 @@ impl Scene {
         self.spot_lights.insert(spot_light)
     }
 
+    /// Remove a spot light. A node holding it is left empty.
     pub fn remove_spot_light(&mut self, id: SpotLightId) {
         self.spot_lights.remove(id);
+        if let Some(node) = self.spot_light_nodes.remove(id) {
+            self.nodes[node].content = NodeContent::Empty;
+        }
     }
 
     pub fn spot_light(&mut self, id: SpotLightId) -> &mut SpotLight {
         &mut self.spot_lights[id]
     }
+
+    ////////////////////////////////////
+
+    /// Add a node under `parent`, or at the root. `content` must already be in the scene and
+    /// not held by another node.
+    pub fn add_node(&mut self, parent: Option<NodeId>, content: NodeContent) -> NodeId {
+        let id = self.nodes.insert(Node::new(NodeContent::Empty, parent));
+        if let Some(parent) = parent {
+            self.nodes[parent].children.push(id);
+        }
+        self.set_node_content(id, content);
+        id
+    }
+
+    /// Remove a node and its descendants, along with the meshes and lights they hold. Skins using
+    /// any of the removed nodes as joints leave those joints in their bind pose.
+    pub fn remove_node(&mut self, id: NodeId) {
+        if let Some(parent) = self.nodes[id].parent {
+            self.nodes[parent].children.retain(|&child| child != id);
+        }
+
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            let node = self.nodes.remove(id).unwrap();
+            match node.content {
+                NodeContent::Empty => { }
+                NodeContent::Mesh(mesh) => {
+                    self.meshes.remove(mesh);
+                    self.mesh_nodes.remove(mesh);
+                    self.skins.remove(mesh);
+                }
+                NodeContent::InstancedMesh(mesh) => {
+                    self.instanced_meshes.remove(mesh);
+                    self.instanced_mesh_nodes.remove(mesh);
+                }
+                NodeContent::PointLight(light) => {
+                    self.point_lights.remove(light);
+                    self.point_light_nodes.remove(light);
+                }
+                NodeContent::SpotLight(light) => {
+                    self.spot_lights.remove(light);
+                    self.spot_light_nodes.remove(light);
+                }
+                NodeContent::Camera => self.camera_node = None,
+            }
+            stack.extend(node.children);
+        }
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id]
+    }
+
+    pub fn node_by_name(&self, name: &str) -> Option<NodeId> {
+        self.nodes.iter()
+            .find(|(_, node)| node.name.as_deref() == Some(name))
+            .map(|(id, _)| id)
+    }
+
+    /// Replace what a node holds. The previous content stays in the scene, unattached.
+    pub fn set_node_content(&mut self, id: NodeId, content: NodeContent) {
+        match self.nodes[id].content {
+            NodeContent::Empty => { }
+            NodeContent::Mesh(mesh) => { self.mesh_nodes.remove(mesh); }
+            NodeContent::InstancedMesh(mesh) => { self.instanced_mesh_nodes.remove(mesh); }
+            NodeContent::PointLight(light) => { self.point_light_nodes.remove(light); }
+            NodeContent::SpotLight(light) => { self.spot_light_nodes.remove(light); }
+            NodeContent::Camera => self.camera_node = None,
+        }
+
+        match content {
+            NodeContent::Empty => { }
+            NodeContent::Mesh(mesh) => { self.mesh_nodes.insert(mesh, id); }
+            NodeContent::InstancedMesh(mesh) => { self.instanced_mesh_nodes.insert(mesh, id); }
+            NodeContent::PointLight(light) => { self.point_light_nodes.insert(light, id); }
+            NodeContent::SpotLight(light) => { self.spot_light_nodes.insert(light, id); }
+            NodeContent::Camera => {
+                if let Some(previous) = self.camera_node {
+                    self.nodes[previous].content = NodeContent::Empty;
+                }
+                self.camera_node = Some(id);
+            }
+        }
+        self.nodes[id].content = content;
+    }
+
+    /// Move a node under another parent, or to the root, keeping its local transform.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) {
+        if let Some(parent) = parent {
+            assert!(
+                !self.is_ancestor_or_self(id, parent),
+                "can't parent a node to itself or one of its descendants",
+            );
+        }
+
+        if let Some(old_parent) = self.nodes[id].parent {
+            self.nodes[old_parent].children.retain(|&child| child != id);
+        }
+        if let Some(parent) = parent {
+            self.nodes[parent].children.push(id);
+        }
+        self.nodes[id].parent = parent;
+        self.nodes[id].dirty.set(true);
+    }
+
+    /// Move a node under another parent, or to the root, adjusting its local transform so it
+    /// stays where it is in the world.
+    pub fn set_parent_keep_world(&mut self, id: NodeId, parent: Option<NodeId>) {
+        let world = self.node_world_transform(id);
+        let parent_world = parent.map_or(Mat4::identity(), |p| self.node_world_transform(p));
+        self.set_parent(id, parent);
+        self.nodes[id].set_local_transform(parent_world.inversed() * world);
+    }
+
+    /// The transform of a node relative to the world.
+    pub fn node_world_transform(&self, id: NodeId) -> Mat4 {
+        let mut ancestor = Some(id);
+        while let Some(node) = ancestor {
+            if self.nodes[node].dirty.get() {
+                self.update_transforms();
+                break;
+            }
+            ancestor = self.nodes[node].parent;
+        }
+        self.nodes[id].cached_world_transform()
+    }
+
+    fn is_ancestor_or_self(&self, ancestor: NodeId, mut node: NodeId) -> bool {
+        loop {
+            if node == ancestor {
+                return true;
+            }
+            match self.nodes[node].parent {
+                Some(parent) => node = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Recompute the world transforms of nodes whose local transform, or an ancestor's, changed.
+    pub(crate) fn update_transforms(&self) {
+        let mut stack = self.nodes.iter()
+            .filter(|(_, node)| node.parent.is_none())
+            .map(|(id, _)| (id, Mat4::identity(), false))
+            .collect::<Vec<_>>();
+
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = &self.nodes[id];
+            let changed = parent_changed || node.dirty.get();
+            if changed {
+                node.update_world_transform(parent_world);
+            }
+            let world = node.cached_world_transform();
+            stack.extend(node.children.iter().map(|&child| (child, world, changed)));
+        }
+    }
+
+    /// The world transform of a mesh, including its node's. Transforms must be up to date.
+    pub(crate) fn mesh_world_transform(&self, id: MeshId) -> Mat4 {
+        let local = self.meshes[id].transform();
+        match self.mesh_nodes.get(id) {
+            Some(&node) => self.nodes[node].cached_world_transform() * local,
+            None => local,
+        }
+    }
+
+    /// The world transform of an instanced mesh, including its node's. Transforms must be up to
+    /// date.
+    pub(crate) fn instanced_mesh_world_transform(&self, id: InstancedMeshId) -> Mat4 {
+        let local = self.instanced_meshes[id].transform();
+        match self.instanced_mesh_nodes.get(id) {
+            Some(&node) => self.nodes[node].cached_world_transform() * local,
+            None => local,
+        }
+    }
+
+    /// The joint matrices of a skinned mesh, from its skin's joint nodes if it has one.
+    /// Transforms must be up to date.
+    pub(crate) fn mesh_joint_matrices(&self, id: MeshId) -> Vec<Mat4> {
+        match self.skins.get(id) {
+            Some(skin) => {
+                // Joints are posed in the world, so undo the mesh's own transform. Joints whose
+                // node was removed stay in the bind pose.
+                let world_to_mesh = self.mesh_world_transform(id).inversed();
+                skin.joints.iter()
+                    .zip(skin.inverse_bind_matrices.iter())
+                    .map(|(&joint, inverse_bind)| match self.nodes.get(joint) {
+                        Some(node) => world_to_mesh * node.cached_world_transform() * *inverse_bind,
+                        None => Mat4::identity(),
+                    })
+                    .collect()
+            }
+            None => self.meshes[id].joint_matrices.clone(),
+        }
+    }
+
+    /// Point lights with their node's transform applied. Transforms must be up to date.
+    pub(crate) fn world_point_lights(&self) -> impl Iterator<Item = PointLight> + '_ {
+        self.point_lights.iter().map(move |(id, light)| match self.point_light_nodes.get(id) {
+            Some(&node) => {
+                let world = self.nodes[node].cached_world_transform();
+                let pos = world.transform_point3(light.pos.into());
+                PointLight { pos: pos.into(), ..*light }
+            }
+            None => *light,
+        })
+    }
+
+    /// Spot lights with their node's transform applied. Transforms must be up to date.
+    pub(crate) fn world_spot_lights(&self) -> impl Iterator<Item = SpotLight> + '_ {
+        self.spot_lights.iter().map(move |(id, light)| match self.spot_light_nodes.get(id) {
+            Some(&node) => {
+                let world = self.nodes[node].cached_world_transform();
+                let pos = world.transform_point3(light.pos.into());
+                let dir = world.transform_vec3(light.dir.into()).normalized();
+                SpotLight { pos: pos.into(), dir: dir.into(), ..*light }
+            }
+            None => *light,
+        })
+    }
+
+    /// The camera's view projection matrix and position. A camera held by a node looks down the
+    /// node's -Z axis. Transforms must be up to date.
+    pub(crate) fn camera_view(&self) -> (Mat4, Vec3) {
+        match self.camera_node {
+            Some(node) => {
+                let world = self.nodes[node].cached_world_transform();
+                (self.camera.proj * world.inversed(), world.cols[3].xyz())
+            }
+            None => (self.camera.total_matrix(), *self.camera.position()),
+        }
+    }
 }
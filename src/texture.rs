@@ -0,0 +1,47 @@
+use std::ops::Deref;
+
+/// A GPU texture along with the properties needed to bind it to a material. Derefs to the
+/// underlying `wgpu::Texture`.
+pub struct Texture {
+    texture: wgpu::Texture,
+    format: wgpu::TextureFormat,
+    size: wgpu::Extent3d,
+    mip_level_count: u32,
+}
+
+impl Texture {
+    /// Wrap a texture created by the caller. `format`, `size` and `mip_level_count` must match
+    /// the descriptor it was created with.
+    pub fn new(
+        texture: wgpu::Texture,
+        format: wgpu::TextureFormat,
+        size: wgpu::Extent3d,
+        mip_level_count: u32,
+    ) -> Self {
+        Texture { texture, format, size, mip_level_count }
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat { self.format }
+    pub fn size(&self) -> wgpu::Extent3d { self.size }
+    pub fn width(&self) -> u32 { self.size.width }
+    pub fn height(&self) -> u32 { self.size.height }
+    pub fn mip_level_count(&self) -> u32 { self.mip_level_count }
+
+    /// The number of color channels stored. Materials sample single channel textures as
+    /// luminance and dual channel textures as luminance-alpha.
+    pub fn channel_count(&self) -> u32 {
+        use wgpu::TextureFormat::*;
+
+        match self.format {
+            R8Unorm | R8Snorm | R16Float | R32Float | Bc4RUnorm | EacRUnorm => 1,
+            Rg8Unorm | Rg8Snorm | Rg16Float | Rg32Float | Bc5RgUnorm | EacRgUnorm => 2,
+            _ => 4,
+        }
+    }
+}
+
+impl Deref for Texture {
+    type Target = wgpu::Texture;
+
+    fn deref(&self) -> &wgpu::Texture { &self.texture }
+}